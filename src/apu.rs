@@ -0,0 +1,891 @@
+use super::region::Region;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// Noise periods in CPU cycles.
+const NOISE_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const NOISE_TABLE_PAL: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// DMC periods in CPU cycles.
+const DMC_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_TABLE_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// CPU cycles at which the frame counter clocks the envelopes and length
+/// counters, for the four and five step sequences.
+const FOUR_STEP: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const FOUR_STEP_PAL: [u32; 4] = [8313, 16627, 24939, 33253];
+const FIVE_STEP_PAL: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay: u8,
+    /// Doubles as the length counter halt flag.
+    looping: bool,
+    constant: bool,
+    volume: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.volume = data & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Savestate for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.start);
+        state.u8(self.divider);
+        state.u8(self.decay);
+        state.bool(self.looping);
+        state.bool(self.constant);
+        state.u8(self.volume);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.start = state.bool()?;
+        self.divider = state.u8()?;
+        self.decay = state.u8()?;
+        self.looping = state.bool()?;
+        self.constant = state.bool()?;
+        self.volume = state.u8()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    /// The first pulse channel negates its sweep in ones' complement.
+    first: bool,
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.duty = data >> 6;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0x07;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[data as usize >> 3];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.first {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_half_frame(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Savestate for Pulse {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.u8(self.duty);
+        state.u8(self.step);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u8(self.length);
+        self.envelope.save_state(state);
+
+        state.bool(self.sweep_enabled);
+        state.u8(self.sweep_period);
+        state.bool(self.sweep_negate);
+        state.u8(self.sweep_shift);
+        state.u8(self.sweep_divider);
+        state.bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.duty = state.u8()?;
+        self.step = state.u8()?;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.length = state.u8()?;
+        self.envelope.load_state(state)?;
+
+        self.sweep_enabled = state.bool()?;
+        self.sweep_period = state.u8()?;
+        self.sweep_negate = state.bool()?;
+        self.sweep_shift = state.u8()?;
+        self.sweep_divider = state.u8()?;
+        self.sweep_reload = state.bool()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    /// Doubles as the length counter halt flag.
+    control: bool,
+    linear_period: u8,
+    linear: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.linear_period = data & 0x7F;
+            }
+            2 => self.period = (self.period & 0x0700) | data as u16,
+            3 => {
+                self.period = (self.period & 0x00FF) | ((data as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[data as usize >> 3];
+                }
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length > 0 && self.linear > 0 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear = self.linear_period;
+        } else if self.linear > 0 {
+            self.linear -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+}
+
+impl Savestate for Triangle {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.u8(self.step);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u8(self.length);
+        state.bool(self.control);
+        state.u8(self.linear_period);
+        state.u8(self.linear);
+        state.bool(self.linear_reload);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.step = state.u8()?;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.length = state.u8()?;
+        self.control = state.bool()?;
+        self.linear_period = state.u8()?;
+        self.linear = state.u8()?;
+        self.linear_reload = state.bool()?;
+
+        Ok(())
+    }
+}
+
+struct Noise {
+    table: &'static [u16; 16],
+    enabled: bool,
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            table: &NOISE_TABLE,
+            enabled: false,
+            short_mode: false,
+            period: NOISE_TABLE[0],
+            timer: 0,
+            shift: 1,
+            length: 0,
+            envelope: Envelope::default(),
+        }
+    }
+}
+
+impl Noise {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => self.envelope.write(data),
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = self.table[data as usize & 0x0F];
+            }
+            3 => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[data as usize >> 3];
+                }
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_half_frame(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Savestate for Noise {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.enabled);
+        state.bool(self.short_mode);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u16(self.shift);
+        state.u8(self.length);
+        self.envelope.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = state.bool()?;
+        self.short_mode = state.bool()?;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.shift = state.u16()?;
+        self.length = state.u8()?;
+        self.envelope.load_state(state)
+    }
+}
+
+/// The delta modulation channel plays 1 bit delta encoded samples, which it
+/// fetches from CPU memory one byte at a time.
+struct Dmc {
+    table: &'static [u16; 16],
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    remaining: u16,
+    buffer: Option<u8>,
+
+    shift: u8,
+    bits: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self {
+            table: &DMC_TABLE,
+            irq_enabled: false,
+            looping: false,
+            period: DMC_TABLE[0],
+            timer: 0,
+            level: 0,
+
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            remaining: 0,
+            buffer: None,
+
+            shift: 0,
+            bits: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+}
+
+impl Dmc {
+    fn write(&mut self, register: u16, data: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.period = self.table[data as usize & 0x0F];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_address = 0xC000 | (data as u16) << 6,
+            _ => self.sample_length = (data as u16) << 4 | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.remaining = self.sample_length;
+    }
+
+    fn fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        self.address = self.address.checked_add(1).unwrap_or(0x8000);
+        self.remaining -= 1;
+
+        if self.remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.bits = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.shift = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+impl Savestate for Dmc {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.irq_enabled);
+        state.bool(self.looping);
+        state.u16(self.period);
+        state.u16(self.timer);
+        state.u8(self.level);
+
+        state.u16(self.sample_address);
+        state.u16(self.sample_length);
+        state.u16(self.address);
+        state.u16(self.remaining);
+        state.bool(self.buffer.is_some());
+        state.u8(self.buffer.unwrap_or(0));
+
+        state.u8(self.shift);
+        state.u8(self.bits);
+        state.bool(self.silence);
+        state.bool(self.irq);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = state.bool()?;
+        self.looping = state.bool()?;
+        self.period = state.u16()?;
+        self.timer = state.u16()?;
+        self.level = state.u8()?;
+
+        self.sample_address = state.u16()?;
+        self.sample_length = state.u16()?;
+        self.address = state.u16()?;
+        self.remaining = state.u16()?;
+        let full = state.bool()?;
+        let buffer = state.u8()?;
+        self.buffer = full.then_some(buffer);
+
+        self.shift = state.u8()?;
+        self.bits = state.u8()?;
+        self.silence = state.bool()?;
+        self.irq = state.bool()?;
+
+        Ok(())
+    }
+}
+
+/// The 2A03's audio processing unit: two pulse channels, a triangle, noise,
+/// the DMC and the frame counter that sequences their envelopes, sweeps and
+/// length counters.
+pub struct Apu {
+    region: Region,
+
+    pulse: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles into the current frame counter sequence.
+    frame_cycle: u32,
+    /// Pulse timers only count on every other CPU cycle.
+    even_cycle: bool,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self {
+            region: Region::Ntsc,
+
+            pulse: [
+                Pulse {
+                    first: true,
+                    ..Pulse::default()
+                },
+                Pulse::default(),
+            ],
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            even_cycle: false,
+        }
+    }
+}
+
+impl Apu {
+    /// PAL consoles use their own period tables and frame counter timing.
+    /// The Dendy keeps the NTSC ones.
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+
+        let pal = region == Region::Pal;
+        self.noise.table = if pal { &NOISE_TABLE_PAL } else { &NOISE_TABLE };
+        self.dmc.table = if pal { &DMC_TABLE_PAL } else { &DMC_TABLE };
+    }
+
+    /// A reset silences every channel, as if $4015 was cleared.
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0x00);
+        self.frame_irq = false;
+        self.dmc.irq = false;
+        self.frame_cycle = 0;
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse[0].write(addr & 0x03, data),
+            0x4004..=0x4007 => self.pulse[1].write(addr & 0x03, data),
+            0x4008..=0x400B => self.triangle.write(addr & 0x03, data),
+            0x400C..=0x400F => self.noise.write(addr & 0x03, data),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, data),
+            0x4015 => {
+                for (bit, pulse) in self.pulse.iter_mut().enumerate() {
+                    pulse.enabled = data & (1 << bit) != 0;
+                    if !pulse.enabled {
+                        pulse.length = 0;
+                    }
+                }
+
+                self.triangle.enabled = data & 0x04 != 0;
+                if !self.triangle.enabled {
+                    self.triangle.length = 0;
+                }
+
+                self.noise.enabled = data & 0x08 != 0;
+                if !self.noise.enabled {
+                    self.noise.length = 0;
+                }
+
+                if data & 0x10 == 0 {
+                    self.dmc.remaining = 0;
+                } else if self.dmc.remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq = false;
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Reads $4015. Reading acknowledges the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let status = (self.pulse[0].length > 0) as u8
+            | ((self.pulse[1].length > 0) as u8) << 1
+            | ((self.triangle.length > 0) as u8) << 2
+            | ((self.noise.length > 0) as u8) << 3
+            | ((self.dmc.remaining > 0) as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7;
+
+        self.frame_irq = false;
+
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// The address the DMC wants to fetch its next sample byte from, which
+    /// the bus answers with [`Apu::dmc_fill`].
+    pub fn dmc_request(&self) -> Option<u16> {
+        (self.dmc.buffer.is_none() && self.dmc.remaining > 0).then_some(self.dmc.address)
+    }
+
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    /// Advances the APU by one CPU cycle.
+    pub fn clock(&mut self) {
+        self.clock_frame_counter();
+
+        self.even_cycle = !self.even_cycle;
+        if self.even_cycle {
+            self.pulse[0].clock();
+            self.pulse[1].clock();
+        }
+
+        self.triangle.clock();
+        self.noise.clock();
+        self.dmc.clock();
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        let steps: &[u32] = match (self.five_step, self.region) {
+            (false, Region::Pal) => &FOUR_STEP_PAL,
+            (true, Region::Pal) => &FIVE_STEP_PAL,
+            (false, _) => &FOUR_STEP,
+            (true, _) => &FIVE_STEP,
+        };
+
+        if let Some(step) = steps.iter().position(|&cycle| cycle == self.frame_cycle) {
+            self.clock_quarter_frame();
+
+            // The fourth step of the five step sequence does nothing.
+            match (step, self.five_step) {
+                (1, _) | (3, false) | (4, true) => self.clock_half_frame(),
+                _ => {}
+            }
+
+            if step == 3 && !self.five_step && !self.irq_inhibit {
+                self.frame_irq = true;
+            }
+        }
+
+        if self.frame_cycle > *steps.last().unwrap() {
+            self.frame_cycle = 0;
+        }
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse[0].envelope.clock();
+        self.pulse[1].envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse[0].clock_half_frame();
+        self.pulse[1].clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    /// The mixed output of all channels, using the nonlinear DAC formulas.
+    /// The range is 0.0 to about 1.0.
+    pub fn output(&self) -> f32 {
+        let pulse = (self.pulse[0].output() + self.pulse[1].output()) as f32;
+        let triangle = self.triangle.output() as f32;
+        let noise = self.noise.output() as f32;
+        let dmc = self.dmc.level as f32;
+
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+}
+
+impl Savestate for Apu {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.pulse[0].save_state(state);
+        self.pulse[1].save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+
+        state.bool(self.five_step);
+        state.bool(self.irq_inhibit);
+        state.bool(self.frame_irq);
+        state.u32(self.frame_cycle);
+        state.bool(self.even_cycle);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.pulse[0].load_state(state)?;
+        self.pulse[1].load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+
+        self.five_step = state.bool()?;
+        self.irq_inhibit = state.bool()?;
+        self.frame_irq = state.bool()?;
+        self.frame_cycle = state.u32()?;
+        self.even_cycle = state.bool()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_counter_status() {
+        let mut apu = Apu::default();
+
+        // Lengths are only loaded while the channel is enabled.
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_status() & 0x01, 0);
+
+        apu.write_register(0x4015, 0x01);
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+
+        apu.write_register(0x4015, 0x00);
+        assert_eq!(apu.read_status() & 0x01, 0);
+    }
+
+    #[test]
+    fn frame_irq() {
+        let mut apu = Apu::default();
+
+        for _ in 0..FOUR_STEP[3] {
+            apu.clock();
+        }
+        assert!(apu.irq());
+
+        // Reading the status acknowledges the interrupt.
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq());
+
+        apu.write_register(0x4017, 0x40);
+        for _ in 0..FIVE_STEP[4] * 2 {
+            apu.clock();
+        }
+        assert!(!apu.irq());
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::cartridge::Cartridge;
+
+/// Keeps a cartridge's battery-backed memory in a `.sav` file.
+///
+/// The file holds the memory as is, the way other emulators save it. It is
+/// only written when the memory changed since it was last loaded or saved,
+/// either on [`SaveFile::flush`] or every so many frames with autosave on.
+pub struct SaveFile {
+    path: PathBuf,
+    /// The memory as the file has it.
+    saved: Vec<u8>,
+    autosave: Option<u32>,
+    frames: u32,
+}
+
+impl SaveFile {
+    /// The save file for a ROM: beside it, with the `.sav` extension.
+    pub fn path_for(rom: impl AsRef<Path>) -> PathBuf {
+        rom.as_ref().with_extension("sav")
+    }
+
+    /// Loads the file at `path` into the cartridge if it exists. Returns
+    /// `None` for cartridges without a battery, which have nothing to save.
+    pub fn open(path: impl Into<PathBuf>, cartridge: &mut Cartridge) -> io::Result<Option<Self>> {
+        let path = path.into();
+        if cartridge.save_ram().is_none() {
+            return Ok(None);
+        }
+
+        match fs::read(&path) {
+            Ok(data) => cartridge.load_save_ram(&data),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        Ok(Some(Self {
+            path,
+            saved: cartridge.save_ram().unwrap_or_default(),
+            autosave: None,
+            frames: 0,
+        }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Saves every `frames` frames counted by [`SaveFile::frame`], or only
+    /// when flushed with `None`.
+    pub fn set_autosave(&mut self, frames: Option<u32>) {
+        self.autosave = frames.filter(|&frames| frames > 0);
+        self.frames = 0;
+    }
+
+    /// Writes the memory if it changed. Returns whether it did.
+    pub fn flush(&mut self, cartridge: &Cartridge) -> io::Result<bool> {
+        let Some(ram) = cartridge.save_ram() else {
+            return Ok(false);
+        };
+        if ram == self.saved {
+            return Ok(false);
+        }
+
+        fs::write(&self.path, &ram)?;
+        self.saved = ram;
+        Ok(true)
+    }
+
+    /// Counts a frame, flushing when autosave is due.
+    pub fn frame(&mut self, cartridge: &Cartridge) -> io::Result<()> {
+        let Some(interval) = self.autosave else {
+            return Ok(());
+        };
+
+        self.frames += 1;
+        if self.frames >= interval {
+            self.frames = 0;
+            self.flush(cartridge)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An NROM image, with a battery when `battery` is set.
+    fn cartridge(battery: bool) -> Cartridge {
+        let mut data = b"NES\x1A\x01\x01".to_vec();
+        data.push(if battery { 0x02 } else { 0x00 });
+        data.resize(16 + 24 * 1024, 0);
+        Cartridge::from_bytes(&data).unwrap()
+    }
+
+    #[test]
+    fn saves_battery_ram() {
+        let path = std::env::temp_dir().join(format!("nes-battery-{}.sav", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut first = cartridge(true);
+        let mut save_file = SaveFile::open(&path, &mut first).unwrap().unwrap();
+        assert!(!save_file.flush(&first).unwrap());
+        assert!(!path.exists());
+
+        first.cpu_write(0x6000, 0x12);
+        first.cpu_write(0x7FFF, 0x34);
+        save_file.set_autosave(Some(2));
+        save_file.frame(&first).unwrap();
+        assert!(!path.exists());
+        save_file.frame(&first).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 8 * 1024);
+        assert!(!save_file.flush(&first).unwrap());
+
+        let mut second = cartridge(true);
+        SaveFile::open(&path, &mut second).unwrap().unwrap();
+        assert_eq!(second.cpu_read(0x6000), Some(0x12));
+        assert_eq!(second.cpu_read(0x7FFF), Some(0x34));
+
+        // RAM without a battery is lost at power off, and never saved.
+        let mut third = cartridge(false);
+        assert!(SaveFile::open(&path, &mut third).unwrap().is_none());
+        assert_eq!(third.cpu_read(0x6000), Some(0x00));
+        assert!(third.save_ram().is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+use super::controller;
+use super::movie::{self, Frame, Movie, MovieError, POWER_CYCLE, SOFT_RESET};
+use super::region::Region;
+use super::zip::{Archive, ZipError};
+
+/// The file in a BizHawk movie that holds its input.
+pub const INPUT_LOG: &str = "Input Log.txt";
+
+/// What a column of the input log stands for.
+#[derive(Debug, Clone, Copy)]
+enum Column {
+    Command(u8),
+    Button { player: usize, button: u8 },
+}
+
+/// Reads a BizHawk movie, a zip archive with the header and input log as
+/// text files.
+///
+/// Movies that start from a savestate or SaveRAM, or that need anything but
+/// gamepads, are refused.
+pub fn parse(data: &[u8]) -> Result<Movie, MovieError> {
+    let archive = Archive::new(data)?;
+    let mut movie = Movie::default();
+
+    for (number, line) in text(&archive, "Header.txt")?.lines().enumerate() {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+        let enabled = value == "1" || value.eq_ignore_ascii_case("true");
+
+        match key {
+            "Platform" if value != "NES" => {
+                return Err(MovieError::Unsupported(format!("the {} platform", value)));
+            }
+            "GameName" => movie.rom_name = value.to_string(),
+            "SHA1" => {
+                let sha1 = value.strip_prefix("SHA1:").unwrap_or(value);
+                movie.rom_sha1 =
+                    Some(movie::parse_digest(sha1).ok_or(MovieError::Syntax(number + 1))?);
+            }
+            "rerecordCount" => {
+                movie.rerecords = value.parse().map_err(|_| MovieError::Syntax(number + 1))?;
+            }
+            "PAL" if enabled => movie.region = Region::Pal,
+            "StartsFromSavestate" | "StartsFromSaveRam" if enabled => {
+                return Err(MovieError::Unsupported("a savestate to start".to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    match text(&archive, "Comments.txt") {
+        Ok(comments) => movie.comments = comments.lines().map(str::to_string).collect(),
+        Err(MovieError::Archive(ZipError::NotFound(_))) => {}
+        Err(error) => return Err(error),
+    }
+
+    let mut columns = None;
+    for (number, line) in text(&archive, INPUT_LOG)?.lines().enumerate() {
+        let number = number + 1;
+
+        if let Some(key) = line.strip_prefix("LogKey:") {
+            columns = Some(log_key(key)?);
+        } else if line.starts_with('|') {
+            let columns = columns.as_ref().ok_or(MovieError::Syntax(number))?;
+            movie
+                .frames
+                .push(parse_frame(line, columns).ok_or(MovieError::Syntax(number))?);
+        }
+    }
+
+    Ok(movie)
+}
+
+fn text(archive: &Archive, name: &str) -> Result<String, MovieError> {
+    Ok(String::from_utf8_lossy(&archive.read(name)?).into_owned())
+}
+
+/// Reads the column names, like `#Reset|Power|#P1 Up|P1 Down|...`, where
+/// each `#` starts the group of columns in one field of an input line.
+fn log_key(key: &str) -> Result<Vec<Vec<Column>>, MovieError> {
+    key.split('#')
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            group
+                .split('|')
+                .filter(|name| !name.is_empty())
+                .map(column)
+                .collect()
+        })
+        .collect()
+}
+
+fn column(name: &str) -> Result<Column, MovieError> {
+    let unsupported = || MovieError::Unsupported(format!("the {} input", name));
+
+    match name {
+        "Reset" => return Ok(Column::Command(SOFT_RESET)),
+        "Power" => return Ok(Column::Command(POWER_CYCLE)),
+        _ => {}
+    }
+
+    let (player, button) = name.split_once(' ').ok_or_else(unsupported)?;
+    let player = match player {
+        "P1" => 0,
+        "P2" => 1,
+        _ => return Err(unsupported()),
+    };
+    let button = match button {
+        "Up" => controller::UP,
+        "Down" => controller::DOWN,
+        "Left" => controller::LEFT,
+        "Right" => controller::RIGHT,
+        "Start" => controller::START,
+        "Select" => controller::SELECT,
+        "B" => controller::B,
+        "A" => controller::A,
+        _ => return Err(unsupported()),
+    };
+
+    Ok(Column::Button { player, button })
+}
+
+/// Reads an input line like `|..|...U...A|........|`, where every column
+/// that isn't `.` is held.
+fn parse_frame(line: &str, columns: &[Vec<Column>]) -> Option<Frame> {
+    let mut fields = line.strip_prefix('|')?.split('|');
+    let mut frame = Frame::default();
+
+    for group in columns {
+        let field = fields.next()?;
+        if field.len() != group.len() {
+            return None;
+        }
+
+        for (flag, column) in field.bytes().zip(group) {
+            if flag == b'.' || flag == b' ' {
+                continue;
+            }
+
+            match *column {
+                Column::Command(command) => frame.commands |= command,
+                Column::Button { player, button } => frame.buttons[player] |= button,
+            }
+        }
+    }
+
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip;
+
+    const LOG: &str = "[Input]
+LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Start|P1 Select|P1 B|P1 A|#P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|
+|.P|........|........|
+|..|....S...|........|
+|..|U......A|.D....B.|
+|r.|........|........|
+[/Input]
+";
+
+    fn header(extra: &str) -> String {
+        format!(
+            "MovieVersion BizHawk v2.0\nPlatform NES\nGameName Test\n\
+             SHA1 DA39A3EE5E6B4B0D3255BFEF95601890AFD80709\nrerecordCount 7\n{}",
+            extra
+        )
+    }
+
+    #[test]
+    fn parse_movie() {
+        let header = header("PAL 1\n");
+        let data = zip::store(&[
+            ("Header.txt", header.as_bytes()),
+            ("Comments.txt", b"first try"),
+            (INPUT_LOG, LOG.as_bytes()),
+        ]);
+        let movie = parse(&data).unwrap();
+
+        assert_eq!(movie.rom_name, "Test");
+        assert_eq!(movie.rerecords, 7);
+        assert_eq!(movie.region, Region::Pal);
+        assert_eq!(movie.comments, ["first try"]);
+        // The SHA-1 of nothing.
+        assert_eq!(movie.rom_sha1.unwrap()[..2], [0xDA, 0x39]);
+
+        assert_eq!(movie.frames.len(), 4);
+        assert_eq!(movie.frames[0].commands, POWER_CYCLE);
+        assert_eq!(movie.frames[1].buttons, [controller::START, 0]);
+        assert_eq!(
+            movie.frames[2].buttons,
+            [
+                controller::UP | controller::A,
+                controller::DOWN | controller::B
+            ]
+        );
+        assert_eq!(movie.frames[3].commands, SOFT_RESET);
+        assert_eq!(movie::import(&data).unwrap(), movie);
+    }
+
+    #[test]
+    fn refused() {
+        let savestate = header("StartsFromSavestate True\n");
+        let data = zip::store(&[
+            ("Header.txt", savestate.as_bytes()),
+            (INPUT_LOG, LOG.as_bytes()),
+        ]);
+        assert!(matches!(parse(&data), Err(MovieError::Unsupported(_))));
+
+        let header = header("");
+        let log = "LogKey:#P1 Up|P1 Fire|\n|..|\n";
+        let data = zip::store(&[
+            ("Header.txt", header.as_bytes()),
+            (INPUT_LOG, log.as_bytes()),
+        ]);
+        assert!(matches!(parse(&data), Err(MovieError::Unsupported(_))));
+
+        let log = "LogKey:#P1 Up|P1 Down|\n|...|\n";
+        let data = zip::store(&[
+            ("Header.txt", header.as_bytes()),
+            (INPUT_LOG, log.as_bytes()),
+        ]);
+        assert_eq!(parse(&data), Err(MovieError::Syntax(2)));
+
+        let data = zip::store(&[(INPUT_LOG, log.as_bytes())]);
+        assert_eq!(
+            parse(&data),
+            Err(MovieError::Archive(ZipError::NotFound(
+                "Header.txt".to_string()
+            )))
+        );
+    }
+}
@@ -1,27 +1,342 @@
+use super::apu::Apu;
+use super::cartridge::Cartridge;
+use super::cheats::Cheats;
+use super::controller::{Controller, Multitap};
+use super::palette::Palette;
+use super::ppu::Ppu;
+use super::region::Region;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// The CPU address space.
+///
+/// Without a cartridge the bus is a flat 64 KiB of RAM, which is what the
+/// processor tests expect. Once a cartridge is inserted the console's memory
+/// map applies: mirrored work RAM, the PPU and APU registers, the controller
+/// ports and the cartridge from $4020 up.
 pub struct Bus {
     ram: [u8; 64 * 1024],
+    pub cartridge: Option<Cartridge>,
+    pub ppu: Ppu,
+    pub apu: Apu,
+    /// The controllers in the two ports, and the third and fourth joypads
+    /// when a four player adapter is connected.
+    pub controllers: [Controller; 4],
+    pub multitap: Multitap,
+    /// Not part of save states, so they stay as set when one is loaded.
+    pub cheats: Cheats,
+    /// Colors for [`Nes::frame_rgb`] and for the Zapper to see by. Not part
+    /// of save states either.
+    ///
+    /// [`Nes::frame_rgb`]: super::nes::Nes::frame_rgb
+    pub palette: Palette,
+
+    region: Region,
+    /// Master clock cycles owed to the PPU.
+    ppu_clock: u32,
+    /// CPU cycles the processor is halted for, e.g. by OAM DMA.
+    pub stall: usize,
+    /// CPU cycles since power on.
+    pub cycles: u64,
+    /// The last value on the data bus, which reads of unmapped addresses
+    /// and undriven bits return.
+    open_bus: u8,
+    /// The address of the CPU's last access, and whether it wrote, for DMA
+    /// halting it.
+    cpu_address: u16,
+    cpu_writing: bool,
+    /// The cycle the running OAM DMA ends on.
+    oam_dma_end: u64,
 }
 
 impl Default for Bus {
     fn default() -> Self {
         Self {
             ram: [0; 64 * 1024],
+            cartridge: None,
+            ppu: Ppu::default(),
+            apu: Apu::default(),
+            controllers: Default::default(),
+            multitap: Multitap::default(),
+            cheats: Cheats::default(),
+            palette: Palette::default(),
+
+            region: Region::Ntsc,
+            ppu_clock: 0,
+            stall: 0,
+            cycles: 0,
+            open_bus: 0,
+            cpu_address: 0,
+            cpu_writing: false,
+            oam_dma_end: 0,
         }
     }
 }
 
 impl Bus {
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.ppu_clock = 0;
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+    }
+
+    pub fn insert(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
     pub fn write<T: Into<u16>>(&mut self, addr: T, data: u8) {
         let address = addr.into();
+        self.open_bus = data;
+        self.cpu_address = address;
+        self.cpu_writing = true;
+        let Some(cartridge) = &mut self.cartridge else {
+            self.ram[address as usize] = data;
+            return;
+        };
+
         match address {
-            0x0000..=0xFFFF => self.ram[address as usize] = data,
+            0x0000..=0x1FFF => self.ram[address as usize & 0x07FF] = data,
+            0x2000..=0x3FFF => self.ppu.write_register(cartridge, address, data),
+            0x4014 => self.oam_dma(data),
+            0x4016 => {
+                for controller in &mut self.controllers {
+                    controller.write(data);
+                }
+                self.multitap.write(data);
+            }
+            0x4000..=0x4017 => self.apu.write_register(address, data),
+            0x4018..=0x401F => {}
+            _ => cartridge.cpu_write(address, data),
         }
     }
 
-    pub fn read<T: Into<u16>>(&self, addr: T) -> u8 {
+    /// Reads need mutable access as cartridge hardware may react to them, e.g.
+    /// by acknowledging an interrupt or advancing an address port.
+    pub fn read<T: Into<u16>>(&mut self, addr: T) -> u8 {
         let address = addr.into();
+        self.cpu_address = address;
+        self.cpu_writing = false;
+        self.fetch(address)
+    }
+
+    /// Reads for the CPU or DMA.
+    fn fetch(&mut self, address: u16) -> u8 {
+        let data = self.read_hardware(address);
+        let data = self.cheats.read(address, data);
+
+        // The APU status is read inside the CPU, without reaching the bus.
+        if address != 0x4015 || self.cartridge.is_none() {
+            self.open_bus = data;
+        }
+
+        data
+    }
+
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    fn read_hardware(&mut self, address: u16) -> u8 {
+        let Some(cartridge) = &mut self.cartridge else {
+            return self.ram[address as usize];
+        };
+
         match address {
-            0x0000..=0xFFFF => self.ram[address as usize],
+            0x0000..=0x1FFF => self.ram[address as usize & 0x07FF],
+            0x2000..=0x3FFF => self.ppu.read_register(cartridge, address),
+            // Bit 5 is not driven.
+            0x4015 => self.apu.read_status() | (self.open_bus & 0x20),
+            0x4016 | 0x4017 => {
+                let port = (address - 0x4016) as usize;
+                let lines = self.region.controller_lines(port);
+                let data =
+                    match self
+                        .multitap
+                        .read(port, &mut self.controllers, &self.ppu, &self.palette)
+                    {
+                        Some(data) => data,
+                        None => self.controllers[port].read(&self.ppu, &self.palette),
+                    };
+                (data & lines) | (self.open_bus & !lines)
+            }
+            0x4000..=0x401F => self.open_bus,
+            _ => cartridge.cpu_read(address).unwrap_or(self.open_bus),
+        }
+    }
+
+    /// Writes the values of the enabled freeze cheats, once a frame. They go
+    /// straight into RAM or the cartridge's RAM, leaving the bus as the CPU
+    /// last drove it.
+    pub fn freeze_cheats(&mut self) {
+        for index in 0..self.cheats.freezes().len() {
+            let (address, value, compare) = self.cheats.freezes()[index];
+            if compare.is_some_and(|compare| compare != self.read_hardware(address)) {
+                continue;
+            }
+
+            let Some(cartridge) = &mut self.cartridge else {
+                self.ram[address as usize] = value;
+                continue;
+            };
+            match address {
+                0x0000..=0x1FFF => self.ram[address as usize & 0x07FF] = value,
+                0x6000..=0x7FFF => cartridge.cpu_write(address, value),
+                _ => {}
+            }
+        }
+    }
+
+    /// Copies a page of memory into OAM, halting the CPU for 513 cycles, or
+    /// 514 when the transfer starts on an odd cycle.
+    fn oam_dma(&mut self, page: u8) {
+        for offset in 0..=0xFF {
+            let data = self.fetch((page as u16) << 8 | offset);
+            self.ppu.write_oam(data);
+        }
+
+        let cycles = 513 + (self.cycles & 0x01) as usize;
+        self.stall += cycles;
+        self.oam_dma_end = self.cycles + cycles as u64;
+    }
+
+    /// Fetches a sample byte for the DMC, halting the CPU for four cycles:
+    /// the halt, a dummy cycle, one to line up with the APU and the fetch.
+    ///
+    /// The CPU only halts on a read, so when it is writing the DMA waits a
+    /// cycle, taking three. During OAM DMA the CPU is halted already and it
+    /// takes two, except near the end of OAM DMA. On the NTSC CPU the halted
+    /// CPU keeps reading its address, which clocks $2007 again for every
+    /// cycle and the controllers once more, dropping a bit of their report.
+    ///
+    /// This is an approximation: the CPU runs a whole instruction at once, so
+    /// the address repeated, and whether the CPU was writing, are those of the
+    /// instruction's last access rather than of the cycle the DMA halted. That
+    /// only matches the hardware when the DMA lands on the instruction's last
+    /// read, as in the `LDA $2007` and `LDA $4016` loops it is known to upset.
+    fn dmc_dma(&mut self, address: u16) {
+        let oam_dma = self.oam_dma_end.saturating_sub(self.cycles);
+        let cycles = match oam_dma {
+            0 if self.cpu_writing => 3,
+            0 => 4,
+            1 => 3,
+            2 => 1,
+            _ => 2,
+        };
+
+        if oam_dma == 0 && !self.cpu_writing && self.region != Region::Pal {
+            // The controllers only see consecutive reads as one.
+            let repeats = match self.cpu_address {
+                0x4016 | 0x4017 => 1,
+                _ => cycles - 1,
+            };
+            for _ in 0..repeats {
+                self.fetch(self.cpu_address);
+            }
+        }
+
+        let data = self.fetch(address);
+        self.apu.dmc_fill(data);
+
+        self.stall += cycles;
+        if oam_dma > 0 {
+            self.oam_dma_end += cycles as u64;
+        }
+    }
+
+    /// Advances the hardware on the bus by one CPU cycle.
+    pub fn clock(&mut self) {
+        self.cycles += 1;
+
+        if let Some(cartridge) = &mut self.cartridge {
+            // Three dots per CPU cycle on NTSC and the Dendy, and 3.2 on PAL.
+            self.ppu_clock += self.region.cpu_divider();
+            while self.ppu_clock >= self.region.ppu_divider() {
+                self.ppu_clock -= self.region.ppu_divider();
+                self.ppu.clock(cartridge);
+            }
+            cartridge.clock();
+        }
+
+        self.apu.clock();
+        if let Some(addr) = self.apu.dmc_request() {
+            self.dmc_dma(addr);
+        }
+    }
+
+    pub fn irq(&self) -> bool {
+        self.apu.irq()
+            || self
+                .cartridge
+                .as_ref()
+                .is_some_and(|cartridge| cartridge.irq())
+    }
+
+    /// The APU mixed with the cartridge's expansion audio.
+    pub fn audio_output(&self) -> f32 {
+        let expansion = self
+            .cartridge
+            .as_ref()
+            .map_or(0.0, |cartridge| cartridge.mapper.audio_output());
+
+        self.apu.output() + expansion
+    }
+}
+
+/// The cartridge's board is included, but not the cartridge's ROM, which is
+/// expected to be the same when the state is loaded.
+impl Savestate for Bus {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.region.save_state(state);
+        state.bytes(&self.ram);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        for controller in &self.controllers {
+            controller.save_state(state);
+        }
+        self.multitap.save_state(state);
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.mapper.save_state(state);
         }
+
+        state.u32(self.ppu_clock);
+        state.u64(self.stall as u64);
+        state.u64(self.cycles);
+        state.u8(self.open_bus);
+        state.u16(self.cpu_address);
+        state.bool(self.cpu_writing);
+        state.u64(self.oam_dma_end);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        let mut region = self.region;
+        region.load_state(state)?;
+        if region != self.region {
+            self.set_region(region);
+        }
+
+        state.bytes_into(&mut self.ram)?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        for controller in &mut self.controllers {
+            controller.load_state(state)?;
+        }
+        self.multitap.load_state(state)?;
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.mapper.load_state(state)?;
+        }
+
+        self.ppu_clock = state.u32()?;
+        self.stall = state.u64()? as usize;
+        self.cycles = state.u64()?;
+        self.open_bus = state.u8()?;
+        self.cpu_address = state.u16()?;
+        self.cpu_writing = state.bool()?;
+        self.oam_dma_end = state.u64()?;
+
+        Ok(())
     }
 }
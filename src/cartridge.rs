@@ -0,0 +1,421 @@
+use std::any::Any;
+use std::fmt;
+
+use super::database::Database;
+use super::fds::DiskImage;
+use super::mapper::{self, Fds, Mapper};
+use super::patch::{self, PatchError};
+use super::region::Region;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mirroring {
+    #[default]
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
+impl Savestate for Mirroring {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.u8()? {
+            0 => Mirroring::Horizontal,
+            1 => Mirroring::Vertical,
+            2 => Mirroring::SingleScreenLower,
+            3 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::FourScreen,
+        };
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The image does not start with the `NES<EOF>` magic.
+    InvalidHeader,
+    /// The image is shorter than the sizes announced by its header.
+    Truncated,
+    /// No implementation exists for the mapper number in the header.
+    UnsupportedMapper(u16),
+    /// The PRG ROM, of this many bytes, is smaller than the mapper's fixed
+    /// banks.
+    PrgTooSmall(usize),
+    /// The Famicom Disk System image is malformed.
+    InvalidDiskImage,
+    /// The Famicom Disk System BIOS is not an 8 KiB image.
+    InvalidBios,
+    /// The patch could not be applied to the image.
+    Patch(PatchError),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CartridgeError::InvalidHeader => write!(f, "not an iNES image"),
+            CartridgeError::Truncated => write!(f, "image is smaller than its header claims"),
+            CartridgeError::UnsupportedMapper(id) => write!(f, "mapper {} is not supported", id),
+            CartridgeError::PrgTooSmall(size) => {
+                write!(f, "PRG ROM of {} bytes is too small for the mapper", size)
+            }
+            CartridgeError::InvalidDiskImage => write!(f, "not a Famicom Disk System image"),
+            CartridgeError::InvalidBios => write!(f, "disk system BIOS must be 8 KiB"),
+            CartridgeError::Patch(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<PatchError> for CartridgeError {
+    fn from(error: PatchError) -> Self {
+        CartridgeError::Patch(error)
+    }
+}
+
+/// The 16 byte iNES / NES 2.0 header.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Header {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    /// The console the game was made for, from the NES 2.0 timing field.
+    pub region: Region,
+}
+
+impl Header {
+    pub fn parse(data: &[u8]) -> Result<Self, CartridgeError> {
+        if data.len() < 16 || data[0..4] != *b"NES\x1A" {
+            return Err(CartridgeError::InvalidHeader);
+        }
+
+        let nes2 = data[7] & 0x0C == 0x08;
+
+        let mirroring = if data[6] & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if data[6] & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+
+        let mut header = Self {
+            nes2,
+            mapper: ((data[7] & 0xF0) | (data[6] >> 4)) as u16,
+            submapper: 0,
+            mirroring,
+            battery: data[6] & 0x02 != 0,
+            trainer: data[6] & 0x04 != 0,
+            prg_rom_size: data[4] as usize * 16 * 1024,
+            chr_rom_size: data[5] as usize * 8 * 1024,
+            prg_ram_size: 8 * 1024,
+            prg_nvram_size: 0,
+            chr_ram_size: if data[5] == 0 { 8 * 1024 } else { 0 },
+            chr_nvram_size: 0,
+            region: Region::Ntsc,
+        };
+
+        if nes2 {
+            header.mapper |= ((data[8] & 0x0F) as u16) << 8;
+            header.submapper = data[8] >> 4;
+            // Sizes that do not even fit in memory cannot be in the image.
+            header.prg_rom_size =
+                rom_size(data[4], data[9] & 0x0F, 16 * 1024).ok_or(CartridgeError::Truncated)?;
+            header.chr_rom_size =
+                rom_size(data[5], data[9] >> 4, 8 * 1024).ok_or(CartridgeError::Truncated)?;
+            header.prg_ram_size = ram_size(data[10] & 0x0F);
+            header.prg_nvram_size = ram_size(data[10] >> 4);
+            header.chr_ram_size = ram_size(data[11] & 0x0F);
+            header.chr_nvram_size = ram_size(data[11] >> 4);
+            header.region = Region::from_timing(data[12]);
+        } else if header.battery {
+            header.prg_nvram_size = header.prg_ram_size;
+            header.prg_ram_size = 0;
+        }
+
+        Ok(header)
+    }
+}
+
+/// NES 2.0 ROM sizes are either a count of `unit` sized banks, or, when the
+/// most significant nibble is $F, an exponent-multiplier pair. Returns `None`
+/// when the size overflows a `usize`.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0F {
+        1usize
+            .checked_shl((lsb >> 2) as u32)?
+            .checked_mul((lsb & 0x03) as usize * 2 + 1)
+    } else {
+        ((msb as usize) << 8 | lsb as usize).checked_mul(unit)
+    }
+}
+
+/// NES 2.0 RAM sizes are stored as a shift count of 64 bytes.
+fn ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        n => 64 << n,
+    }
+}
+
+pub struct Cartridge {
+    pub header: Header,
+    pub mapper: Box<dyn Mapper>,
+    /// The header fields the game database corrected, e.g. `"mapper"`.
+    pub corrected: Vec<&'static str>,
+}
+
+impl Cartridge {
+    /// Loads an iNES or NES 2.0 image, with the header corrected by the
+    /// builtin game database.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, CartridgeError> {
+        Self::with_database(data, Database::builtin())
+    }
+
+    /// Loads an image, with the header corrected by `database` when it knows
+    /// the game.
+    pub fn with_database(data: &[u8], database: &Database) -> Result<Self, CartridgeError> {
+        let mut header = Header::parse(data)?;
+
+        let prg_start: usize = if header.trainer { 16 + 512 } else { 16 };
+        // The database has the checksums of the ROM alone, without anything
+        // a dumper may have left after it.
+        let rom_end = prg_start
+            .saturating_add(header.prg_rom_size)
+            .saturating_add(header.chr_rom_size)
+            .min(data.len());
+        let corrected = match database.find(data.get(prg_start..rom_end).unwrap_or_default()) {
+            Some(game) => game.correct(&mut header),
+            None => Vec::new(),
+        };
+
+        let chr_end = prg_start
+            .checked_add(header.prg_rom_size)
+            .and_then(|chr_start| chr_start.checked_add(header.chr_rom_size))
+            .filter(|&chr_end| chr_end <= data.len())
+            .ok_or(CartridgeError::Truncated)?;
+        let chr_start = chr_end - header.chr_rom_size;
+
+        let prg = data[prg_start..chr_start].to_vec();
+        let chr = data[chr_start..chr_end].to_vec();
+        let mapper = mapper::new(&header, prg, chr)?;
+
+        Ok(Self {
+            header,
+            mapper,
+            corrected,
+        })
+    }
+
+    /// Loads an image after applying an IPS, BPS or UPS patch to it, e.g. a
+    /// translation.
+    pub fn from_patched(data: &[u8], patch: &[u8]) -> Result<Self, CartridgeError> {
+        Self::from_bytes(&patch::apply(data, patch)?)
+    }
+
+    /// Loads a Famicom Disk System image, which also needs the BIOS from
+    /// the RAM adapter (`disksys.rom`) to boot.
+    pub fn from_fds(image: &[u8], bios: &[u8]) -> Result<Self, CartridgeError> {
+        if bios.len() != 8 * 1024 {
+            return Err(CartridgeError::InvalidBios);
+        }
+
+        let disk = DiskImage::parse(image)?;
+        let header = Header {
+            nes2: false,
+            mapper: 20,
+            submapper: 0,
+            mirroring: Mirroring::Horizontal,
+            battery: false,
+            trainer: false,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            prg_ram_size: 32 * 1024,
+            prg_nvram_size: 0,
+            chr_ram_size: 8 * 1024,
+            chr_nvram_size: 0,
+            region: Region::Ntsc,
+        };
+
+        Ok(Self {
+            header,
+            mapper: Box::new(Fds::new(bios.to_vec(), disk)),
+            corrected: Vec::new(),
+        })
+    }
+
+    /// The disk drive, when this is a Famicom Disk System image.
+    pub fn fds(&mut self) -> Option<&mut Fds> {
+        (self.mapper.as_mut() as &mut dyn Any).downcast_mut()
+    }
+
+    pub fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        self.mapper.cpu_read(addr)
+    }
+
+    pub fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.mapper.cpu_write(addr, data)
+    }
+
+    /// Advances the cartridge hardware by one CPU cycle.
+    pub fn clock(&mut self) {
+        self.mapper.clock()
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    /// The memory to keep between sessions, or `None` when the header has
+    /// no battery, so that RAM that doesn't survive power off is never
+    /// saved.
+    pub fn save_ram(&self) -> Option<Vec<u8>> {
+        if !self.header.battery {
+            return None;
+        }
+
+        Some(self.mapper.save_ram()).filter(|ram| !ram.is_empty())
+    }
+
+    /// Restores memory from [`Cartridge::save_ram`]. Ignored without a
+    /// battery.
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        if self.header.battery {
+            self.mapper.load_save_ram(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut data = b"NES\x1A".to_vec();
+        data.extend_from_slice(&bytes);
+        data
+    }
+
+    #[test]
+    fn ines() {
+        let data = header([2, 1, 0x13, 0x40, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let header = Header::parse(&data).unwrap();
+
+        assert!(!header.nes2);
+        assert_eq!(header.mapper, 0x41);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery);
+        assert_eq!(header.prg_rom_size, 32 * 1024);
+        assert_eq!(header.chr_rom_size, 8 * 1024);
+        assert_eq!(header.prg_nvram_size, 8 * 1024);
+        assert_eq!(header.region, Region::Ntsc);
+    }
+
+    #[test]
+    fn nes2() {
+        let data = header([0x10, 0, 0x98, 0x48, 0x21, 0x01, 0x70, 0x07, 0x01, 0, 0, 0]);
+        let header = Header::parse(&data).unwrap();
+
+        assert!(header.nes2);
+        assert_eq!(header.mapper, 0x149);
+        assert_eq!(header.submapper, 2);
+        assert_eq!(header.mirroring, Mirroring::FourScreen);
+        assert_eq!(header.prg_rom_size, 0x110 * 16 * 1024);
+        assert_eq!(header.prg_ram_size, 0);
+        assert_eq!(header.prg_nvram_size, 8 * 1024);
+        assert_eq!(header.chr_ram_size, 8 * 1024);
+        assert_eq!(header.region, Region::Pal);
+    }
+
+    #[test]
+    fn oversized() {
+        // PRG ROM of 2^63 * 7 bytes.
+        let data = header([0xFF, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(Header::parse(&data).unwrap_err(), CartridgeError::Truncated);
+        assert_eq!(
+            Cartridge::from_bytes(&data).err(),
+            Some(CartridgeError::Truncated)
+        );
+    }
+
+    #[test]
+    fn database() {
+        // NROM with vertical mirroring, its header saying UxROM.
+        let mut data = header([1, 1, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.resize(16 + 24 * 1024, 0);
+        let crc = crate::checksum::crc32(&data[16..]);
+
+        let xml = format!(
+            "<game><rom crc32=\"{:08X}\"/><pcb mapper=\"0\" mirroring=\"V\"/></game>",
+            crc
+        );
+        let database = Database::parse(&xml).unwrap();
+        let cartridge = Cartridge::with_database(&data, &database).unwrap();
+        assert_eq!(cartridge.header.mapper, 0);
+        assert_eq!(cartridge.header.mirroring, Mirroring::Vertical);
+        assert_eq!(cartridge.corrected, ["mapper", "mirroring"]);
+
+        // Bytes after the ROM are not part of the checksum.
+        let mut padded = data.clone();
+        padded.extend_from_slice(b"trailing junk");
+        let cartridge = Cartridge::with_database(&padded, &database).unwrap();
+        assert_eq!(cartridge.corrected, ["mapper", "mirroring"]);
+
+        assert_eq!(
+            Cartridge::with_database(&data, &Database::default()).err(),
+            Some(CartridgeError::UnsupportedMapper(2))
+        );
+    }
+
+    #[test]
+    fn builtin_database() {
+        // The entry src/test_database.xml adds to the builtin database.
+        let mut data = header([1, 1, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.resize(16 + 16 * 1024, 0xEA);
+        data.resize(16 + 24 * 1024, 0);
+
+        let cartridge = Cartridge::from_bytes(&data).unwrap();
+        assert_eq!(cartridge.header.mapper, 0);
+        assert_eq!(cartridge.corrected, ["mapper", "mirroring"]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            Header::parse(b"NES\x00").unwrap_err(),
+            CartridgeError::InvalidHeader
+        );
+    }
+
+    #[test]
+    fn prg_too_small() {
+        // MMC2 fixes the last three of four 8 KiB banks.
+        let mut data = header([1, 1, 0x90, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        data.resize(16 + 24 * 1024, 0);
+        assert_eq!(
+            Cartridge::from_bytes(&data).err(),
+            Some(CartridgeError::PrgTooSmall(16 * 1024))
+        );
+
+        let data = header([0; 12]);
+        assert_eq!(
+            Cartridge::from_bytes(&data).err(),
+            Some(CartridgeError::PrgTooSmall(0))
+        );
+    }
+}
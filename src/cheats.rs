@@ -0,0 +1,380 @@
+use std::fmt;
+use std::fmt::Write;
+
+/// The letters of Game Genie codes, standing for the values 0 to 15.
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheatError {
+    /// The code is neither a Game Genie code nor an `AAAA:VV` raw code.
+    InvalidCode(String),
+    /// The line of a cheat file with this number, counting from 1, could
+    /// not be read.
+    Syntax(usize),
+    /// A freeze targets this address, which is neither RAM at $0000-$1FFF
+    /// nor cartridge RAM at $6000-$7FFF.
+    FreezeAddress(u16),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheatError::InvalidCode(code) => write!(f, "invalid cheat code {}", code),
+            CheatError::Syntax(line) => write!(f, "cheat file line {} is malformed", line),
+            CheatError::FreezeAddress(address) => {
+                write!(f, "cannot freeze ${:04X}, which is not RAM", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// Reads of the address return the value instead, the way a Game Genie
+    /// patches the cartridge's data on its way to the CPU.
+    Substitute,
+    /// The value is written to the address once a frame, the way a Pro
+    /// Action Replay keeps RAM frozen.
+    Freeze,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub kind: CheatKind,
+    pub address: u16,
+    pub value: u8,
+    /// Only apply while the address holds this value, which keeps codes for
+    /// banked ROM to the bank they were made for.
+    pub compare: Option<u8>,
+    pub enabled: bool,
+    pub name: String,
+}
+
+impl Cheat {
+    /// Reads a 6 or 8 letter Game Genie code, or a raw code: `AAAA:VV`
+    /// freezes RAM at $AAAA to $VV, which must be in $0000-$1FFF or
+    /// $6000-$7FFF, and `AAAA?CC:VV` substitutes $VV for
+    /// reads of $AAAA that would return $CC.
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let code = code.trim();
+        let invalid = || CheatError::InvalidCode(code.to_string());
+
+        let Some((address, value)) = code.split_once(':') else {
+            return Self::game_genie(code);
+        };
+
+        let (address, compare) = match address.split_once('?') {
+            Some((address, compare)) => (address, Some(hex(compare).ok_or_else(invalid)?)),
+            None => (address, None),
+        };
+
+        let cheat = Self {
+            kind: if compare.is_some() {
+                CheatKind::Substitute
+            } else {
+                CheatKind::Freeze
+            },
+            address: u16::from_str_radix(address, 16).map_err(|_| invalid())?,
+            value: hex(value).ok_or_else(invalid)?,
+            compare,
+            enabled: true,
+            name: String::new(),
+        };
+        cheat.check_freeze()?;
+
+        Ok(cheat)
+    }
+
+    /// Freezes are written to memory directly, which only works for RAM.
+    fn check_freeze(&self) -> Result<(), CheatError> {
+        match (self.kind, self.address) {
+            (CheatKind::Freeze, 0x2000..=0x5FFF | 0x8000..=0xFFFF) => {
+                Err(CheatError::FreezeAddress(self.address))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes a Game Genie code. Six letters patch an address in
+    /// $8000-$FFFF with a value; eight add a value to compare with.
+    pub fn game_genie(code: &str) -> Result<Self, CheatError> {
+        let invalid = || CheatError::InvalidCode(code.to_string());
+        if code.len() != 6 && code.len() != 8 {
+            return Err(invalid());
+        }
+
+        let mut n = [0u16; 8];
+        for (value, letter) in n.iter_mut().zip(code.bytes()) {
+            *value = GAME_GENIE_LETTERS
+                .iter()
+                .position(|&candidate| candidate == letter.to_ascii_uppercase())
+                .ok_or_else(invalid)? as u16;
+        }
+
+        let address = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+        // The bit that makes up the rest of the value comes from the last
+        // letter.
+        let last = n[code.len() - 1];
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (last & 8);
+        let compare = (code.len() == 8)
+            .then(|| ((n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8)) as u8);
+
+        Ok(Self {
+            kind: CheatKind::Substitute,
+            address,
+            value: value as u8,
+            compare,
+            enabled: true,
+            name: code.to_ascii_uppercase(),
+        })
+    }
+}
+
+fn hex(text: &str) -> Option<u8> {
+    u8::from_str_radix(text.trim(), 16).ok()
+}
+
+/// The cheats in effect on a console's bus.
+///
+/// Substitutions are looked up on every CPU read, freezes are written once a
+/// frame, both only while enabled.
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    /// The enabled cheats of each kind, for quick lookups.
+    substitutes: Vec<(u16, u8, Option<u8>)>,
+    freezes: Vec<(u16, u8, Option<u8>)>,
+}
+
+impl Cheats {
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+        self.update();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Cheat {
+        let cheat = self.cheats.remove(index);
+        self.update();
+        cheat
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.cheats[index].enabled = enabled;
+        self.update();
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let enabled = |kind| {
+            self.cheats
+                .iter()
+                .filter(|cheat| cheat.enabled && cheat.kind == kind)
+                .map(|cheat| (cheat.address, cheat.value, cheat.compare))
+                .collect()
+        };
+
+        self.substitutes = enabled(CheatKind::Substitute);
+        self.freezes = enabled(CheatKind::Freeze);
+    }
+
+    /// Substitutes for `data` read from `address`.
+    pub(crate) fn read(&self, address: u16, data: u8) -> u8 {
+        for &(target, value, compare) in &self.substitutes {
+            if target == address && compare.is_none_or(|compare| compare == data) {
+                return value;
+            }
+        }
+
+        data
+    }
+
+    pub(crate) fn freezes(&self) -> &[(u16, u8, Option<u8>)] {
+        &self.freezes
+    }
+
+    /// Reads an FCEUX cheat file, which holds a cheat per line:
+    /// `[S][C][:]AAAA:VV[:CC]:NAME`. `S` marks substitutions, which are
+    /// freezes otherwise, `C` a value to compare with and `:` disabled
+    /// cheats.
+    pub fn from_cht(text: &str) -> Result<Self, CheatError> {
+        let mut cheats = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() {
+                continue;
+            }
+            let syntax = || CheatError::Syntax(number + 1);
+
+            let mut rest = line;
+            let substitute = rest.starts_with('S');
+            rest = rest.strip_prefix('S').unwrap_or(rest);
+            let compare = rest.starts_with('C');
+            rest = rest.strip_prefix('C').unwrap_or(rest);
+            let enabled = !rest.starts_with(':');
+            rest = rest.strip_prefix(':').unwrap_or(rest);
+
+            let count = if compare { 4 } else { 3 };
+            let fields: Vec<&str> = rest.splitn(count, ':').collect();
+            let [address, value, .., name] = fields[..] else {
+                return Err(syntax());
+            };
+            if fields.len() != count {
+                return Err(syntax());
+            }
+
+            let cheat = Cheat {
+                kind: if substitute {
+                    CheatKind::Substitute
+                } else {
+                    CheatKind::Freeze
+                },
+                address: u16::from_str_radix(address, 16).map_err(|_| syntax())?,
+                value: hex(value).ok_or_else(syntax)?,
+                compare: match compare {
+                    true => Some(hex(fields[2]).ok_or_else(syntax)?),
+                    false => None,
+                },
+                enabled,
+                name: name.to_string(),
+            };
+            cheat.check_freeze()?;
+            cheats.cheats.push(cheat);
+        }
+
+        cheats.update();
+        Ok(cheats)
+    }
+
+    /// Writes the cheats in the format [`Cheats::from_cht`] reads.
+    pub fn to_cht(&self) -> String {
+        let mut text = String::new();
+
+        for cheat in &self.cheats {
+            if cheat.kind == CheatKind::Substitute {
+                text.push('S');
+            }
+            if cheat.compare.is_some() {
+                text.push('C');
+            }
+            if !cheat.enabled {
+                text.push(':');
+            }
+
+            let _ = write!(text, "{:04X}:{:02X}:", cheat.address, cheat.value);
+            if let Some(compare) = cheat.compare {
+                let _ = write!(text, "{:02X}:", compare);
+            }
+            let _ = writeln!(text, "{}", cheat.name);
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_genie() {
+        // Infinite lives in Super Mario Bros.
+        let cheat = Cheat::parse("SXIOPO").unwrap();
+        assert_eq!(cheat.kind, CheatKind::Substitute);
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x91D9, 0xAD, None)
+        );
+
+        assert_eq!(Cheat::game_genie("gossip").unwrap().address, 0xD1DD);
+        assert_eq!(Cheat::game_genie("GOSSIP").unwrap().value, 0x14);
+
+        let cheat = Cheat::parse("AEKPEAZY").unwrap();
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x98C0, 0x00, Some(0x72))
+        );
+
+        assert!(Cheat::parse("SXIOPB").is_err());
+        assert!(Cheat::parse("SXIOP").is_err());
+    }
+
+    #[test]
+    fn raw_codes() {
+        let cheat = Cheat::parse("075A:09").unwrap();
+        assert_eq!(cheat.kind, CheatKind::Freeze);
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0x075A, 0x09, None)
+        );
+
+        let cheat = Cheat::parse("C000?A5:EA").unwrap();
+        assert_eq!(cheat.kind, CheatKind::Substitute);
+        assert_eq!(
+            (cheat.address, cheat.value, cheat.compare),
+            (0xC000, 0xEA, Some(0xA5))
+        );
+
+        assert!(Cheat::parse("075A:100").is_err());
+        assert_eq!(Cheat::parse("6001:09").unwrap().address, 0x6001);
+        assert_eq!(
+            Cheat::parse("2001:00").unwrap_err(),
+            CheatError::FreezeAddress(0x2001)
+        );
+    }
+
+    #[test]
+    fn substitution() {
+        let mut cheats = Cheats::default();
+        cheats.add(Cheat::parse("C000?A5:EA").unwrap());
+        cheats.add(Cheat::parse("0010:01").unwrap());
+
+        assert_eq!(cheats.read(0xC000, 0xA5), 0xEA);
+        assert_eq!(cheats.read(0xC000, 0xA6), 0xA6);
+        assert_eq!(cheats.read(0xC001, 0xA5), 0xA5);
+        assert_eq!(cheats.freezes(), [(0x0010, 0x01, None)]);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.read(0xC000, 0xA5), 0xA5);
+    }
+
+    #[test]
+    fn cht_files() {
+        let text = "SC:8056:A5:85:Infinite lives\nS91D9:AD:More lives\n0075:09:Max power: really\n";
+        let cheats = Cheats::from_cht(text).unwrap();
+
+        assert_eq!(cheats.cheats().len(), 3);
+        assert!(!cheats.cheats()[0].enabled);
+        assert_eq!(cheats.cheats()[0].compare, Some(0x85));
+        assert_eq!(cheats.cheats()[1].kind, CheatKind::Substitute);
+        assert_eq!(cheats.cheats()[2].kind, CheatKind::Freeze);
+        assert_eq!(cheats.cheats()[2].name, "Max power: really");
+        assert_eq!(cheats.to_cht(), text);
+
+        assert_eq!(
+            Cheats::from_cht("S91D9:AD:ok\nS91D9\n").err(),
+            Some(CheatError::Syntax(2))
+        );
+        assert_eq!(
+            Cheats::from_cht("8000:EA:ROM\n").err(),
+            Some(CheatError::FreezeAddress(0x8000))
+        );
+    }
+}
@@ -0,0 +1,538 @@
+use crate::palette::Palette;
+use crate::ppu::{Ppu, HEIGHT, WIDTH};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+// Button bits, in the order the controller shifts them out.
+pub const A: u8 = 1 << 0;
+pub const B: u8 = 1 << 1;
+pub const SELECT: u8 = 1 << 2;
+pub const START: u8 = 1 << 3;
+pub const UP: u8 = 1 << 4;
+pub const DOWN: u8 = 1 << 5;
+pub const LEFT: u8 = 1 << 6;
+pub const RIGHT: u8 = 1 << 7;
+
+/// Power Pad buttons, numbered 1 to 12 on side B, in the order its two data
+/// lines shift them out.
+const POWER_PAD_D3: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const POWER_PAD_D4: [u8; 4] = [4, 3, 12, 8];
+
+/// How far, in pixels, the Zapper sees around where it points.
+const ZAPPER_RADIUS: i32 = 2;
+/// Scanlines the Zapper's photodiode stays lit after the beam passes.
+const ZAPPER_PERSISTENCE: u16 = 20;
+/// The luminance, out of 255, the Zapper takes for light.
+const ZAPPER_THRESHOLD: u32 = 128;
+
+/// Signatures the four player adapters report after the controllers, in
+/// the order they are read, for $4016 and $4017.
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0x10, 0x20];
+const HORI_SIGNATURES: [u8; 2] = [0x20, 0x10];
+
+/// A four player adapter, which takes over both controller ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Adapter {
+    /// A device in each port.
+    #[default]
+    None,
+    /// The NES Four Score: $4016 reads the first and third joypads on bit 0,
+    /// then a signature, $4017 the second and fourth.
+    FourScore,
+    /// The Hori adapter on the Famicom's expansion port, which reads the same
+    /// way but on bit 1, with the signatures swapped.
+    Hori,
+}
+
+/// What is plugged into a controller port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Device {
+    None,
+    #[default]
+    Joypad,
+    /// The light gun: bit 3 reads 0 while it sees light, bit 4 is the
+    /// trigger.
+    Zapper,
+    /// Arkanoid's paddle: the knob's position shifted out on bit 4, most
+    /// significant bit first and inverted, and its button on bit 3.
+    Vaus,
+    /// The 12 button floor mat, shifted out on bits 3 and 4.
+    PowerPad,
+}
+
+/// What the frontend sets on a controller, taken as a whole to replay it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Input {
+    pub buttons: u8,
+    pub mat: u16,
+    pub aim: Option<(u16, u16)>,
+    pub trigger: bool,
+    pub paddle: u8,
+}
+
+/// A controller port and what is plugged into it: a parallel-in serial-out
+/// shift register of the device's state, read one bit at a time through
+/// $4016 or $4017.
+///
+/// The frontend sets the fields of the device in use.
+#[derive(Default)]
+pub struct Controller {
+    pub device: Device,
+    /// Joypad buttons held down.
+    pub buttons: u8,
+    /// Power Pad buttons held down, bit `n - 1` for button `n`.
+    pub mat: u16,
+    /// Where the Zapper points, in picture coordinates, or `None` when it
+    /// points away from the screen.
+    pub aim: Option<(u16, u16)>,
+    /// The Zapper's trigger or the Vaus' button.
+    pub trigger: bool,
+    /// The Vaus knob's position, from about $62 fully left to $F2 fully
+    /// right for Arkanoid.
+    pub paddle: u8,
+
+    strobe: bool,
+    shift: u8,
+    /// The Power Pad's second data line.
+    shift_high: u8,
+    /// Bits shifted out so far, after which the register reads back ones.
+    reads: u8,
+}
+
+impl Controller {
+    /// While the strobe is high the register keeps reloading the buttons.
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.reload();
+        }
+    }
+
+    /// Reads the port. The Zapper looks at the picture the PPU is drawing,
+    /// in the colors of `palette`.
+    pub fn read(&mut self, ppu: &Ppu, palette: &Palette) -> u8 {
+        if self.strobe {
+            self.reload();
+        }
+
+        match self.device {
+            Device::None => 0x00,
+            Device::Joypad => {
+                if self.reads >= 8 {
+                    return 0x01;
+                }
+
+                let bit = self.shift & 0x01;
+                self.shift >>= 1;
+                self.reads += 1;
+
+                bit
+            }
+            Device::Zapper => {
+                (!self.sees_light(ppu, palette) as u8) << 3 | (self.trigger as u8) << 4
+            }
+            Device::Vaus => {
+                let bit = self.shift >> 7;
+                self.shift <<= 1;
+
+                (self.trigger as u8) << 3 | bit << 4
+            }
+            Device::PowerPad => {
+                let bits = (self.shift & 0x01) << 3 | (self.shift_high & 0x01) << 4;
+                self.shift = self.shift >> 1 | 0x80;
+                self.shift_high = self.shift_high >> 1 | 0x80;
+
+                bits
+            }
+        }
+    }
+
+    pub fn input(&self) -> Input {
+        Input {
+            buttons: self.buttons,
+            mat: self.mat,
+            aim: self.aim,
+            trigger: self.trigger,
+            paddle: self.paddle,
+        }
+    }
+
+    pub fn set_input(&mut self, input: Input) {
+        self.buttons = input.buttons;
+        self.mat = input.mat;
+        self.aim = input.aim;
+        self.trigger = input.trigger;
+        self.paddle = input.paddle;
+    }
+
+    fn reload(&mut self) {
+        self.reads = 0;
+
+        match self.device {
+            Device::Vaus => self.shift = !self.paddle,
+            Device::PowerPad => {
+                let pressed = |button: u8| (self.mat >> (button - 1)) as u8 & 0x01;
+                let pack = |buttons: &[u8]| {
+                    buttons
+                        .iter()
+                        .enumerate()
+                        .fold(0, |bits, (index, &button)| bits | pressed(button) << index)
+                };
+
+                self.shift = pack(&POWER_PAD_D3);
+                self.shift_high = pack(&POWER_PAD_D4) | 0xF0;
+            }
+            _ => self.shift = self.buttons,
+        }
+    }
+
+    /// Whether the Zapper sees a bright pixel near where it points, drawn
+    /// recently enough that the photodiode still responds.
+    fn sees_light(&self, ppu: &Ppu, palette: &Palette) -> bool {
+        let Some((x, y)) = self.aim else {
+            return false;
+        };
+        if x as usize >= WIDTH || y as usize >= HEIGHT {
+            return false;
+        }
+
+        // Only pixels the beam has passed this frame give off light.
+        let drawn = |line: u16, column: u16| {
+            ppu.scanline > line || (ppu.scanline == line && ppu.dot > column)
+        };
+        if !drawn(y, x) || ppu.scanline - y > ZAPPER_PERSISTENCE {
+            return false;
+        }
+
+        (-ZAPPER_RADIUS..=ZAPPER_RADIUS).any(|dy| {
+            (-ZAPPER_RADIUS..=ZAPPER_RADIUS).any(|dx| {
+                let (column, line) = (x as i32 + dx, y as i32 + dy);
+                if column < 0 || line < 0 || column >= WIDTH as i32 || line >= HEIGHT as i32 {
+                    return false;
+                }
+                if !drawn(line as u16, column as u16) {
+                    return false;
+                }
+
+                let [r, g, b] = palette.rgb(ppu.frame[line as usize * WIDTH + column as usize]);
+                (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000 >= ZAPPER_THRESHOLD
+            })
+        })
+    }
+}
+
+/// Connects four joypads to the ports through an [`Adapter`].
+///
+/// Each port reads eight bits from one joypad, eight from another and then
+/// the adapter's signature, by which games detect it, and ones after that.
+#[derive(Default)]
+pub struct Multitap {
+    pub adapter: Adapter,
+
+    strobe: bool,
+    /// Bits read from each port so far.
+    reads: [u8; 2],
+}
+
+impl Multitap {
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 0x01 != 0;
+        if self.strobe {
+            self.reads = [0; 2];
+        }
+    }
+
+    /// Reads the port at `$4016 + port`, or `None` without an adapter, when
+    /// the controller in the port answers by itself.
+    pub fn read(
+        &mut self,
+        port: usize,
+        controllers: &mut [Controller; 4],
+        ppu: &Ppu,
+        palette: &Palette,
+    ) -> Option<u8> {
+        let (signatures, line) = match self.adapter {
+            Adapter::None => return None,
+            Adapter::FourScore => (FOUR_SCORE_SIGNATURES, 0),
+            Adapter::Hori => (HORI_SIGNATURES, 1),
+        };
+
+        let reads = self.reads[port];
+        let bit = match reads {
+            0..=7 => controllers[port].read(ppu, palette) & 0x01,
+            8..=15 => controllers[port + 2].read(ppu, palette) & 0x01,
+            16..=23 => signatures[port] >> (23 - reads) & 0x01,
+            _ => 0x01,
+        };
+        if !self.strobe {
+            self.reads[port] = (reads + 1).min(24);
+        }
+
+        Some(bit << line)
+    }
+}
+
+impl Savestate for Adapter {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.u8()? {
+            0 => Adapter::None,
+            1 => Adapter::FourScore,
+            _ => Adapter::Hori,
+        };
+
+        Ok(())
+    }
+}
+
+impl Savestate for Multitap {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.adapter.save_state(state);
+        state.bool(self.strobe);
+        state.bytes(&self.reads);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.adapter.load_state(state)?;
+        self.strobe = state.bool()?;
+        state.bytes_into(&mut self.reads)?;
+
+        Ok(())
+    }
+}
+
+impl Savestate for Device {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.u8()? {
+            0 => Device::None,
+            1 => Device::Joypad,
+            2 => Device::Zapper,
+            3 => Device::Vaus,
+            _ => Device::PowerPad,
+        };
+
+        Ok(())
+    }
+}
+
+impl Savestate for Controller {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.device.save_state(state);
+        state.u8(self.buttons);
+        state.u16(self.mat);
+        state.bool(self.aim.is_some());
+        let (x, y) = self.aim.unwrap_or_default();
+        state.u16(x);
+        state.u16(y);
+        state.bool(self.trigger);
+        state.u8(self.paddle);
+
+        state.bool(self.strobe);
+        state.u8(self.shift);
+        state.u8(self.shift_high);
+        state.u8(self.reads);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.device.load_state(state)?;
+        self.buttons = state.u8()?;
+        self.mat = state.u16()?;
+        let aimed = state.bool()?;
+        let aim = (state.u16()?, state.u16()?);
+        self.aim = aimed.then_some(aim);
+        self.trigger = state.bool()?;
+        self.paddle = state.u8()?;
+
+        self.strobe = state.bool()?;
+        self.shift = state.u8()?;
+        self.shift_high = state.u8()?;
+        self.reads = state.u8()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_bits(controller: &mut Controller, ppu: &Ppu, count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|_| controller.read(ppu, &Palette::default()))
+            .collect()
+    }
+
+    #[test]
+    fn joypad() {
+        let ppu = Ppu::default();
+        let mut controller = Controller {
+            buttons: A | START | RIGHT,
+            ..Controller::default()
+        };
+        controller.write(1);
+        controller.write(0);
+
+        assert_eq!(
+            read_bits(&mut controller, &ppu, 10),
+            [1, 0, 0, 1, 0, 0, 0, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn zapper() {
+        let mut ppu = Ppu::default();
+        // A white box around (100, 50).
+        for y in 45..55 {
+            ppu.frame[y * WIDTH + 95..y * WIDTH + 105].fill(0x30);
+        }
+        let mut zapper = Controller {
+            device: Device::Zapper,
+            aim: Some((100, 50)),
+            ..Controller::default()
+        };
+
+        let palette = Palette::default();
+
+        // Not drawn yet.
+        (ppu.scanline, ppu.dot) = (40, 0);
+        assert_eq!(zapper.read(&ppu, &palette), 0x08);
+        // Just drawn.
+        (ppu.scanline, ppu.dot) = (52, 0);
+        assert_eq!(zapper.read(&ppu, &palette), 0x00);
+        // Shown in the colors of a palette that makes it dark.
+        let dark = Palette::from_pal(&[0x10; 192]).unwrap();
+        assert_eq!(zapper.read(&ppu, &dark), 0x08);
+        // The photodiode has gone dark again.
+        (ppu.scanline, ppu.dot) = (90, 0);
+        assert_eq!(zapper.read(&ppu, &palette), 0x08);
+
+        (ppu.scanline, ppu.dot) = (52, 0);
+        zapper.aim = Some((200, 50));
+        zapper.trigger = true;
+        assert_eq!(zapper.read(&ppu, &palette), 0x18);
+        zapper.aim = None;
+        assert_eq!(zapper.read(&ppu, &palette), 0x18);
+    }
+
+    #[test]
+    fn vaus() {
+        let ppu = Ppu::default();
+        let mut vaus = Controller {
+            device: Device::Vaus,
+            paddle: 0xA5,
+            trigger: true,
+            ..Controller::default()
+        };
+        vaus.write(1);
+        vaus.write(0);
+
+        // $A5 inverted is $5A.
+        let bits: Vec<u8> = read_bits(&mut vaus, &ppu, 8)
+            .iter()
+            .map(|bits| bits >> 3)
+            .collect();
+        assert_eq!(bits, [1, 3, 1, 3, 3, 1, 3, 1]);
+    }
+
+    #[test]
+    fn power_pad() {
+        let ppu = Ppu::default();
+        let mut mat = Controller {
+            device: Device::PowerPad,
+            // Buttons 1, 9 and 12.
+            mat: 1 << 0 | 1 << 8 | 1 << 11,
+            ..Controller::default()
+        };
+        mat.write(1);
+        mat.write(0);
+
+        assert_eq!(
+            read_bits(&mut mat, &ppu, 9),
+            [0x00, 0x08, 0x10, 0x08, 0x10, 0x10, 0x10, 0x10, 0x18]
+        );
+    }
+
+    fn multitap_bits(
+        multitap: &mut Multitap,
+        controllers: &mut [Controller; 4],
+        port: usize,
+    ) -> Vec<u8> {
+        let ppu = Ppu::default();
+        (0..25)
+            .map(|_| {
+                multitap
+                    .read(port, controllers, &ppu, &Palette::default())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn four_score() {
+        let mut controllers: [Controller; 4] = Default::default();
+        controllers[0].buttons = A;
+        controllers[1].buttons = B;
+        controllers[2].buttons = RIGHT;
+        controllers[3].buttons = START;
+        let mut multitap = Multitap::default();
+        assert_eq!(
+            multitap.read(0, &mut controllers, &Ppu::default(), &Palette::default()),
+            None
+        );
+
+        multitap.adapter = Adapter::FourScore;
+        for controller in &mut controllers {
+            controller.write(1);
+            controller.write(0);
+        }
+        multitap.write(1);
+        multitap.write(0);
+
+        let mut expected = [0; 25];
+        expected[0] = 1;
+        expected[15] = 1;
+        // The signature 0x10, then ones.
+        expected[19] = 1;
+        expected[24] = 1;
+        assert_eq!(multitap_bits(&mut multitap, &mut controllers, 0), expected);
+
+        let mut expected = [0; 25];
+        expected[1] = 1;
+        expected[11] = 1;
+        // The signature 0x20.
+        expected[18] = 1;
+        expected[24] = 1;
+        assert_eq!(multitap_bits(&mut multitap, &mut controllers, 1), expected);
+    }
+
+    #[test]
+    fn hori() {
+        let mut controllers: [Controller; 4] = Default::default();
+        let mut multitap = Multitap {
+            adapter: Adapter::Hori,
+            ..Multitap::default()
+        };
+
+        // Games probe for the adapter by reading past the joypads, so the
+        // signature must come after exactly 16 reads.
+        multitap.write(1);
+        multitap.write(0);
+        let bits = multitap_bits(&mut multitap, &mut controllers, 0);
+        assert_eq!(bits[16..24], [0, 0, 2, 0, 0, 0, 0, 0]);
+        let bits = multitap_bits(&mut multitap, &mut controllers, 1);
+        assert_eq!(bits[16..24], [0, 0, 0, 2, 0, 0, 0, 0]);
+
+        // Holding the strobe keeps reading the first bit.
+        controllers[0].buttons = A;
+        controllers[0].write(1);
+        multitap.write(1);
+        let bits = multitap_bits(&mut multitap, &mut controllers, 0);
+        assert!(bits.iter().all(|&bit| bit == 2));
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use super::bus::Bus;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
 
 enum Flag {
     C = (1 << 0), // carry bit
@@ -354,13 +355,20 @@ impl Cpu {
         self.set_flag(Flag::I, true);
     }
 
-    fn irq(&mut self, bus: &mut Bus) {
+    /// Services a maskable interrupt, unless interrupts are disabled. Only
+    /// call between instructions, when [`Cpu::complete`] holds.
+    pub fn irq(&mut self, bus: &mut Bus) {
         if !self.get_flag(Flag::I) {
-            self.nmi(bus);
+            self.interrupt(bus, 0xFFFE);
         }
     }
 
-    fn nmi(&mut self, bus: &mut Bus) {
+    /// Services a non-maskable interrupt. Only call between instructions.
+    pub fn nmi(&mut self, bus: &mut Bus) {
+        self.interrupt(bus, 0xFFFA);
+    }
+
+    fn interrupt(&mut self, bus: &mut Bus, vector: u16) {
         bus.write(0x0100 + self.sp as u16, ((self.pc >> 8) & 0x00FF) as u8);
         self.sp = self.sp.wrapping_sub(1);
         bus.write(0x0100 + self.sp as u16, (self.pc & 0x00FF) as u8);
@@ -368,11 +376,11 @@ impl Cpu {
 
         self.set_flag(Flag::B, false);
         self.set_flag(Flag::U, true);
-        self.set_flag(Flag::I, true);
         bus.write(0x0100 + self.sp as u16, self.status);
         self.sp = self.sp.wrapping_sub(1);
+        self.set_flag(Flag::I, true);
 
-        self.addr_abs = 0xFFFE;
+        self.addr_abs = vector;
         let lo: u16 = bus.read(self.addr_abs) as u16;
         let hi: u16 = bus.read(self.addr_abs + 1) as u16;
         self.pc = (hi << 8) | lo;
@@ -552,9 +560,9 @@ impl Cpu {
 
     // OPCODE FUNCTIONS
 
-    fn fetch(&mut self, bus: &Bus) -> u8 {
+    fn fetch(&mut self, bus: &mut Bus) -> u8 {
         let instruction = &DISPATCH[self.opcode as usize];
-        if instruction.addressmode as usize != Cpu::imp as usize {
+        if instruction.addressmode as usize != Cpu::imp as *const () as usize {
             self.fetched = bus.read(self.addr_abs);
         }
 
@@ -616,7 +624,7 @@ impl Cpu {
         self.set_flag(Flag::N, (t & 0b10000000) > 0);
 
         let instruction = &DISPATCH[self.opcode as usize];
-        if (instruction.addressmode as usize) == (Cpu::imp as usize) {
+        if (instruction.addressmode as usize) == (Cpu::imp as *const () as usize) {
             self.a = t;
         } else {
             bus.write(self.addr_abs, t);
@@ -697,7 +705,7 @@ impl Cpu {
         self.set_flag(Flag::Z, operand == 0);
 
         let instruction = &DISPATCH[self.opcode as usize];
-        if (instruction.addressmode as usize) == (Cpu::imp as usize) {
+        if (instruction.addressmode as usize) == (Cpu::imp as *const () as usize) {
             self.a = operand;
         } else {
             bus.write(self.addr_abs, operand);
@@ -763,7 +771,7 @@ impl Cpu {
         self.set_flag(Flag::N, (t & 0b10000000) > 1);
 
         let instruction = &DISPATCH[self.opcode as usize];
-        if (instruction.addressmode as usize) == (Cpu::imp as usize) {
+        if (instruction.addressmode as usize) == (Cpu::imp as *const () as usize) {
             self.a = t;
         } else {
             bus.write(self.addr_abs, t);
@@ -835,7 +843,7 @@ impl Cpu {
         self.set_flag(Flag::N, (t & 0x0080) > 0);
 
         let instruction = &DISPATCH[self.opcode as usize];
-        if (instruction.addressmode as usize) == (Cpu::imp as usize) {
+        if (instruction.addressmode as usize) == (Cpu::imp as *const () as usize) {
             self.a = (t & 0x00FF) as u8;
         } else {
             bus.write(self.addr_abs, (t & 0x00FF) as u8);
@@ -1328,6 +1336,40 @@ impl Cpu {
     }
 }
 
+impl Savestate for Cpu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.a);
+        state.u8(self.x);
+        state.u8(self.y);
+        state.u8(self.sp);
+        state.u16(self.pc);
+        state.u8(self.status);
+
+        state.u8(self.fetched);
+        state.u16(self.addr_abs);
+        state.u16(self.addr_rel);
+        state.u8(self.opcode);
+        state.u64(self.cycles as u64);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.a = state.u8()?;
+        self.x = state.u8()?;
+        self.y = state.u8()?;
+        self.sp = state.u8()?;
+        self.pc = state.u16()?;
+        self.status = state.u8()?;
+
+        self.fetched = state.u8()?;
+        self.addr_abs = state.u16()?;
+        self.addr_rel = state.u16()?;
+        self.opcode = state.u8()?;
+        self.cycles = state.u64()? as usize;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate test_generator;
@@ -0,0 +1,352 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use super::cartridge::{Header, Mirroring};
+use super::checksum::{crc32, sha1};
+use super::movie::parse_digest;
+use super::region::Region;
+
+/// The database built in, in the same format as the files
+/// [`Database::parse`] reads.
+#[cfg(not(test))]
+const BUILTIN: &str = include_str!("database.xml");
+/// Tests get an entry of their own on top.
+#[cfg(test)]
+const BUILTIN: &str = concat!(
+    include_str!("database.xml"),
+    include_str!("test_database.xml")
+);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DatabaseError {
+    /// The line with this number, counting from 1, holds a malformed tag or
+    /// value.
+    Syntax(usize),
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Syntax(line) => write!(f, "game database line {} is malformed", line),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+/// What the database knows of a game. Fields it doesn't give are left as
+/// the image's header has them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Game {
+    /// From the comment before the entry, which names the image.
+    pub name: String,
+    /// Of the PRG and CHR ROM together, i.e. the image without its header
+    /// and trainer.
+    pub crc32: u32,
+    pub sha1: Option<[u8; 20]>,
+
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub battery: Option<bool>,
+    pub prg_rom_size: Option<usize>,
+    pub chr_rom_size: Option<usize>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub chr_nvram_size: Option<usize>,
+    pub region: Option<Region>,
+}
+
+impl Game {
+    /// Overrides the fields of `header` the database knows better, naming
+    /// those that changed.
+    pub fn correct(&self, header: &mut Header) -> Vec<&'static str> {
+        let mut corrected = Vec::new();
+
+        fn set<T: PartialEq>(
+            field: &mut T,
+            value: Option<T>,
+            name: &'static str,
+            corrected: &mut Vec<&'static str>,
+        ) {
+            if let Some(value) = value {
+                if *field != value {
+                    *field = value;
+                    corrected.push(name);
+                }
+            }
+        }
+
+        set(&mut header.mapper, self.mapper, "mapper", &mut corrected);
+        set(
+            &mut header.submapper,
+            self.submapper,
+            "submapper",
+            &mut corrected,
+        );
+        set(
+            &mut header.mirroring,
+            self.mirroring,
+            "mirroring",
+            &mut corrected,
+        );
+        set(&mut header.battery, self.battery, "battery", &mut corrected);
+        #[rustfmt::skip]
+        {
+            set(&mut header.prg_rom_size, self.prg_rom_size, "PRG ROM size", &mut corrected);
+            set(&mut header.chr_rom_size, self.chr_rom_size, "CHR ROM size", &mut corrected);
+            set(&mut header.prg_ram_size, self.prg_ram_size, "PRG RAM size", &mut corrected);
+            set(&mut header.prg_nvram_size, self.prg_nvram_size, "PRG NVRAM size", &mut corrected);
+            set(&mut header.chr_ram_size, self.chr_ram_size, "CHR RAM size", &mut corrected);
+            set(&mut header.chr_nvram_size, self.chr_nvram_size, "CHR NVRAM size", &mut corrected);
+        };
+        set(&mut header.region, self.region, "region", &mut corrected);
+
+        corrected
+    }
+}
+
+/// Games known by the checksums of their ROM, to fix the many iNES images
+/// with wrong mapper numbers, mirroring or RAM sizes in their headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Database {
+    pub games: Vec<Game>,
+}
+
+impl Database {
+    /// The database built into the emulator.
+    pub fn builtin() -> &'static Database {
+        static DATABASE: OnceLock<Database> = OnceLock::new();
+        DATABASE.get_or_init(|| Database::parse(BUILTIN).expect("the builtin database is valid"))
+    }
+
+    /// Reads a database in the XML format of the NES 2.0 database
+    /// (`nes20db.xml`): a `<game>` per image, holding a `<rom>` tag with the
+    /// checksums, and `<pcb>`, `<prgrom>`, `<chrrom>`, `<prgram>`,
+    /// `<prgnvram>`, `<chrram>`, `<chrnvram>` and `<console>` tags with what
+    /// goes in the header. Other tags are skipped.
+    pub fn parse(text: &str) -> Result<Self, DatabaseError> {
+        let mut database = Self::default();
+        let mut game: Option<Game> = None;
+        let mut game_offset = 0;
+        let mut name = String::new();
+        let mut rest = text;
+
+        while let Some(start) = rest.find('<') {
+            let offset = text.len() - rest.len() + start;
+            let syntax = || DatabaseError::Syntax(line(text, offset));
+            rest = &rest[start..];
+
+            if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").ok_or_else(syntax)?;
+                name = unescape(comment[..end].trim());
+                rest = &comment[end + 3..];
+                continue;
+            }
+
+            let end = rest.find('>').ok_or_else(syntax)?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            if tag == "game" {
+                game_offset = offset;
+                game = Some(Game {
+                    name: std::mem::take(&mut name),
+                    ..Game::default()
+                });
+                continue;
+            }
+            if tag == "/game" {
+                database.games.push(game.take().ok_or_else(syntax)?);
+                continue;
+            }
+
+            let Some(game) = &mut game else {
+                continue;
+            };
+            let tag = tag.strip_suffix('/').unwrap_or(tag);
+            let (element, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+            let attributes = parse_attributes(attributes).ok_or_else(syntax)?;
+            let attribute = |key: &str| {
+                attributes
+                    .iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| value.as_str())
+            };
+            let number = |key: &str| match attribute(key) {
+                Some(value) => value.parse::<usize>().map(Some).map_err(|_| syntax()),
+                None => Ok(None),
+            };
+
+            match element {
+                "rom" => {
+                    let crc = attribute("crc32").ok_or_else(syntax)?;
+                    game.crc32 = u32::from_str_radix(crc, 16).map_err(|_| syntax())?;
+                    game.sha1 = match attribute("sha1") {
+                        Some(digest) => Some(parse_digest(digest).ok_or_else(syntax)?),
+                        None => None,
+                    };
+                }
+                "prgrom" => game.prg_rom_size = number("size")?,
+                "chrrom" => game.chr_rom_size = number("size")?,
+                "prgram" => game.prg_ram_size = number("size")?,
+                "prgnvram" => game.prg_nvram_size = number("size")?,
+                "chrram" => game.chr_ram_size = number("size")?,
+                "chrnvram" => game.chr_nvram_size = number("size")?,
+                "pcb" => {
+                    game.mapper = number("mapper")?.map(|mapper| mapper as u16);
+                    game.submapper = number("submapper")?.map(|submapper| submapper as u8);
+                    game.battery = number("battery")?.map(|battery| battery != 0);
+                    // Boards that switch mirroring themselves are left be.
+                    game.mirroring = match attribute("mirroring") {
+                        Some("H") => Some(Mirroring::Horizontal),
+                        Some("V") => Some(Mirroring::Vertical),
+                        Some("4") => Some(Mirroring::FourScreen),
+                        _ => None,
+                    };
+                }
+                "console" => {
+                    game.region = number("region")?.map(|region| Region::from_timing(region as u8));
+                }
+                _ => {}
+            }
+        }
+
+        if game.is_some() {
+            return Err(DatabaseError::Syntax(line(text, game_offset)));
+        }
+
+        Ok(database)
+    }
+
+    /// Finds the game whose PRG and CHR ROM are `rom`, the image without its
+    /// header and trainer.
+    pub fn find(&self, rom: &[u8]) -> Option<&Game> {
+        let crc = crc32(rom);
+        let mut digest = None;
+
+        self.games.iter().find(|game| {
+            game.crc32 == crc
+                && game
+                    .sha1
+                    .is_none_or(|expected| *digest.get_or_insert_with(|| sha1(rom)) == expected)
+        })
+    }
+}
+
+/// The number of the line `offset` is on, counting from 1.
+fn line(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+/// Splits `key="value"` pairs, quoted with either kind of quote.
+fn parse_attributes(text: &str) -> Option<Vec<(&str, String)>> {
+    let mut attributes = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let end = value[1..].find(quote)? + 1;
+
+        attributes.push((key.trim(), unescape(&value[1..end])));
+        rest = value[end + 1..].trim_start();
+    }
+
+    Some(attributes)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATABASE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+  <!-- Test &amp; Fix.nes -->
+  <game>
+    <prgrom size="32768" crc32="00000000"/>
+    <chrrom size="8192" crc32="00000000"/>
+    <rom size="40960" crc32="CBF43926" sha1="7C222FB2927D828AF22F592134E8932480637C0D"/>
+    <prgnvram size='8192'/>
+    <console type="0" region="1"/>
+    <pcb mapper="1" submapper="0" mirroring="V" battery="1"/>
+  </game>
+  <game>
+    <rom size="3" crc32="352441C2"/>
+    <pcb mapper="4" mirroring="M" battery="0"/>
+  </game>
+</nes20db>
+"#;
+
+    #[test]
+    fn parse_database() {
+        let database = Database::parse(DATABASE).unwrap();
+        assert_eq!(database.games.len(), 2);
+
+        let game = &database.games[0];
+        assert_eq!(game.name, "Test & Fix.nes");
+        assert_eq!(game.crc32, 0xCBF43926);
+        assert_eq!(game.sha1.unwrap()[..2], [0x7C, 0x22]);
+        assert_eq!(game.prg_rom_size, Some(32768));
+        assert_eq!(game.prg_nvram_size, Some(8192));
+        assert_eq!(game.chr_ram_size, None);
+        assert_eq!(game.region, Some(Region::Pal));
+        assert_eq!(game.mirroring, Some(Mirroring::Vertical));
+        assert_eq!(game.battery, Some(true));
+
+        assert_eq!(database.games[1].mirroring, None);
+        assert!(Database::builtin().games.iter().all(|game| game.crc32 != 0));
+        let shipped = Database::parse(include_str!("database.xml")).unwrap();
+        assert!(shipped.games.iter().any(|game| game.crc32 == 0x3337EC46));
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            Database::parse("<game>\n<rom crc32=\"XYZ\"/>\n</game>"),
+            Err(DatabaseError::Syntax(2))
+        );
+        assert_eq!(
+            Database::parse("<game>\n<rom crc32=0/>"),
+            Err(DatabaseError::Syntax(2))
+        );
+        assert_eq!(
+            Database::parse("<a/>\n<game>\n"),
+            Err(DatabaseError::Syntax(2))
+        );
+    }
+
+    #[test]
+    fn corrections() {
+        let database = Database::parse(DATABASE).unwrap();
+        assert!(database.find(b"12345678").is_none());
+        // The first entry's CRC-32 matches, but not its SHA-1.
+        assert!(database.find(b"123456789").is_none());
+        let game = database.find(b"abc").unwrap();
+
+        let mut header = Header {
+            mapper: 1,
+            mirroring: Mirroring::Vertical,
+            battery: true,
+            ..Header::default()
+        };
+        assert_eq!(game.correct(&mut header), ["mapper", "battery"]);
+        assert_eq!(header.mapper, 4);
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(!header.battery);
+        assert!(game.correct(&mut header).is_empty());
+    }
+}
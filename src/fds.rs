@@ -0,0 +1,283 @@
+use super::cartridge::CartridgeError;
+
+/// Usable bytes on one side of a disk in the `.fds` format.
+pub const SIDE_SIZE: usize = 65500;
+
+/// Blank disk surface the drive passes over before the first block.
+const LEADING_GAP: usize = 28300 / 8;
+
+/// Blank disk surface written after every block.
+const BLOCK_GAP: usize = 976 / 8;
+
+/// A Famicom Disk System image.
+///
+/// `.fds` files only store the contents of each block. The drive on the other
+/// hand sees the raw surface of the disk, where blocks are separated by gaps,
+/// start with a $80 mark and end with a CRC. The sides are kept in that raw
+/// layout so the drive can read and write them byte by byte, and are converted
+/// back when the image is saved.
+pub struct DiskImage {
+    header: Option<[u8; 16]>,
+    original: Vec<u8>,
+    sides: Vec<Vec<u8>>,
+}
+
+impl DiskImage {
+    /// Parses an image with or without the 16 byte fwNES header.
+    pub fn parse(data: &[u8]) -> Result<Self, CartridgeError> {
+        let (header, body) = if data.starts_with(b"FDS\x1A") {
+            if data.len() < 16 {
+                return Err(CartridgeError::InvalidDiskImage);
+            }
+
+            let mut header = [0; 16];
+            header.copy_from_slice(&data[..16]);
+
+            (Some(header), &data[16..])
+        } else {
+            (None, data)
+        };
+
+        if body.is_empty() || body.len() % SIDE_SIZE != 0 {
+            return Err(CartridgeError::InvalidDiskImage);
+        }
+
+        let sides: Vec<Vec<u8>> = body.chunks(SIDE_SIZE).map(add_gaps).collect();
+
+        // Every side starts with the disk info block and its verification string.
+        if sides.iter().any(|side| {
+            side.get(LEADING_GAP + 1..LEADING_GAP + 16) != Some(&b"\x01*NINTENDO-HVC*"[..])
+        }) {
+            return Err(CartridgeError::InvalidDiskImage);
+        }
+
+        Ok(Self {
+            header,
+            original: data.to_vec(),
+            sides,
+        })
+    }
+
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    pub fn side(&self, side: usize) -> &[u8] {
+        &self.sides[side]
+    }
+
+    pub fn side_mut(&mut self, side: usize) -> &mut [u8] {
+        &mut self.sides[side]
+    }
+
+    /// Rebuilds the image in the layout it was loaded from, including any
+    /// changes the game has written to the disk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.original.len());
+
+        if let Some(header) = self.header {
+            data.extend_from_slice(&header);
+        }
+
+        for side in self.sides.iter() {
+            data.extend(remove_gaps(side));
+        }
+
+        data
+    }
+
+    /// Returns the changes made to the disk as an IPS patch against the image
+    /// that was loaded, which keeps save files small and the original intact.
+    pub fn diff(&self) -> Vec<u8> {
+        ips(&self.original, &self.to_bytes())
+    }
+}
+
+/// Blocks are identified by their first byte. File data blocks take their
+/// length from the file header block that precedes them.
+fn block_length(side: &[u8], start: usize, file_size: usize) -> Option<usize> {
+    match side.get(start)? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+fn file_size(block: &[u8]) -> usize {
+    if block[0] == 3 {
+        block[13] as usize | (block[14] as usize) << 8
+    } else {
+        0
+    }
+}
+
+fn add_gaps(raw: &[u8]) -> Vec<u8> {
+    let mut side = vec![0; LEADING_GAP];
+    let mut position = 0;
+    let mut size = 0;
+
+    while let Some(length) = block_length(raw, position, size) {
+        let Some(block) = raw.get(position..position + length) else {
+            break;
+        };
+
+        let mut marked = vec![0x80];
+        marked.extend_from_slice(block);
+        let checksum = crc(&[&marked[..], &[0, 0]].concat());
+
+        side.extend(marked);
+        side.extend(checksum.to_le_bytes());
+        side.extend([0; BLOCK_GAP]);
+
+        size = file_size(block);
+        position += length;
+    }
+
+    side.resize(side.len().max(LEADING_GAP + SIDE_SIZE), 0);
+
+    side
+}
+
+fn remove_gaps(side: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(SIDE_SIZE);
+    let mut position = 0;
+    let mut size = 0;
+
+    while let Some(mark) = side
+        .get(position..)
+        .and_then(|rest| rest.iter().position(|&byte| byte != 0))
+    {
+        position += mark;
+        if side[position] != 0x80 {
+            break;
+        }
+        position += 1;
+
+        let Some(length) = block_length(side, position, size) else {
+            break;
+        };
+        let Some(block) = side.get(position..position + length) else {
+            break;
+        };
+
+        raw.extend_from_slice(block);
+        size = file_size(block);
+        position += length + 2;
+    }
+
+    raw.resize(SIDE_SIZE, 0);
+
+    raw
+}
+
+/// The drive's CRC-16, which processes bits least significant first. The
+/// checksum stored after a block is the CRC of the block followed by two zero
+/// bytes, so that the CRC over a block and its checksum comes out as zero.
+pub fn crc(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| crc_update(crc, byte))
+}
+
+pub fn crc_update(mut crc: u16, byte: u8) -> u16 {
+    for bit in 0..8 {
+        let carry = crc & 0x01 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if byte & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+
+    crc
+}
+
+/// Encodes the differences between two buffers as an IPS patch.
+fn ips(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    let mut offset = 0;
+
+    while offset < target.len() {
+        if source.get(offset) == Some(&target[offset]) {
+            offset += 1;
+            continue;
+        }
+
+        // An offset of $454F46 would read as the EOF marker.
+        if offset == 0x454F46 {
+            offset -= 1;
+        }
+
+        let start = offset;
+        while offset < target.len()
+            && offset - start < 0xFFFF
+            && source.get(offset) != Some(&target[offset])
+        {
+            offset += 1;
+        }
+
+        patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+        patch.extend_from_slice(&((offset - start) as u16).to_be_bytes());
+        patch.extend_from_slice(&target[start..offset]);
+    }
+
+    patch.extend_from_slice(b"EOF");
+
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn side() -> Vec<u8> {
+        let mut raw = b"\x01*NINTENDO-HVC*".to_vec();
+        raw.resize(56, 0);
+        raw.extend([2, 1]);
+
+        let mut file_header = vec![
+            3, 0, 0, b'F', b'I', b'L', b'E', b' ', b' ', b' ', b' ', 0, 0,
+        ];
+        file_header.extend([4, 0, 0]);
+        raw.extend(file_header);
+        raw.extend([4, 0xDE, 0xAD, 0xBE, 0xEF]);
+        raw.resize(SIDE_SIZE, 0);
+
+        raw
+    }
+
+    #[test]
+    fn gaps_round_trip() {
+        let raw = side();
+        let image = DiskImage::parse(&raw).unwrap();
+
+        assert_eq!(image.side(0)[LEADING_GAP], 0x80);
+        assert_eq!(image.to_bytes(), raw);
+        assert_eq!(image.diff(), b"PATCHEOF");
+    }
+
+    #[test]
+    fn block_crc() {
+        let image = DiskImage::parse(&side()).unwrap();
+        let start = LEADING_GAP;
+
+        // Running the CRC over a block including its stored checksum leaves zero.
+        assert_eq!(crc(&image.side(0)[start..start + 1 + 56 + 2]), 0);
+    }
+
+    #[test]
+    fn diff() {
+        let mut image = DiskImage::parse(&side()).unwrap();
+
+        let offset = image.side(0).len() - 1;
+        let data = LEADING_GAP + 1 + 56 + 2 + BLOCK_GAP + 1 + 2 + 2 + BLOCK_GAP + 1 + 16 + 2;
+        image.side_mut(0)[data + BLOCK_GAP + 3] = 0x00;
+        image.side_mut(0)[offset] = 0xFF;
+
+        let mut expected = b"PATCH".to_vec();
+        expected.extend([0x00, 0x00, 0x4C, 0x00, 0x01, 0x00, b'E', b'O', b'F']);
+        assert_eq!(image.diff(), expected);
+    }
+}
@@ -0,0 +1,296 @@
+use std::fmt::Write;
+
+use super::movie::{Frame, Movie, MovieError, POWER_CYCLE, SOFT_RESET};
+use super::region::Region;
+
+/// Gamepad buttons in the order FM2 input logs list them, from bit 7 down.
+const BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+/// FM2 commands beyond resets: FDS disk swaps and VS System coins.
+const UNSUPPORTED_COMMANDS: u8 = !(SOFT_RESET | POWER_CYCLE);
+
+/// Reads a text FCEUX movie.
+///
+/// Movies that start from a savestate, use the binary input log, need
+/// anything but gamepads in the ports or swap disks and insert coins are
+/// refused.
+pub fn parse(text: &str) -> Result<Movie, MovieError> {
+    let mut movie = Movie::default();
+    let mut ports = [true, true];
+
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = line.trim_end_matches('\r');
+
+        if line.starts_with('|') {
+            movie.frames.push(parse_frame(line, ports, number)?);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let number_value = || {
+            value
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| MovieError::Syntax(number))
+        };
+
+        match key {
+            "version" if value.trim() != "3" => {
+                return Err(MovieError::Unsupported(format!("FM2 version {}", value)));
+            }
+            "rerecordCount" => movie.rerecords = number_value()?,
+            "palFlag" => {
+                movie.region = if number_value()? != 0 {
+                    Region::Pal
+                } else {
+                    Region::Ntsc
+                };
+            }
+            "romFilename" => movie.rom_name = value.to_string(),
+            "romChecksum" => {
+                let digest = value
+                    .strip_prefix("base64:")
+                    .and_then(base64_decode)
+                    .and_then(|digest| digest.try_into().ok())
+                    .ok_or(MovieError::Syntax(number))?;
+                movie.rom_checksum = Some(digest);
+            }
+            "comment" => movie.comments.push(value.to_string()),
+            "port0" | "port1" => {
+                let port = (key == "port1") as usize;
+                ports[port] = match number_value()? {
+                    0 => false,
+                    1 => true,
+                    device => {
+                        return Err(MovieError::Unsupported(format!(
+                            "device {} in port {}",
+                            device, port
+                        )));
+                    }
+                };
+            }
+            "fourscore" if number_value()? != 0 => {
+                return Err(MovieError::Unsupported("the Four Score".to_string()));
+            }
+            "binary" if number_value()? != 0 => {
+                return Err(MovieError::Unsupported("a binary input log".to_string()));
+            }
+            "savestate" => {
+                return Err(MovieError::Unsupported("a savestate to start".to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(movie)
+}
+
+/// Reads a `|commands|port0|port1|port2|` input line, line `number` of the
+/// file.
+fn parse_frame(line: &str, ports: [bool; 2], number: usize) -> Result<Frame, MovieError> {
+    let syntax = || MovieError::Syntax(number);
+    let mut fields = line.split('|').skip(1);
+    let commands: u8 = fields
+        .next()
+        .and_then(|field| field.trim().parse().ok())
+        .ok_or_else(syntax)?;
+    if commands & UNSUPPORTED_COMMANDS != 0 {
+        return Err(MovieError::Unsupported("FDS/VS commands".to_string()));
+    }
+
+    let mut frame = Frame {
+        commands,
+        ..Default::default()
+    };
+
+    for (port, buttons) in frame.buttons.iter_mut().enumerate() {
+        let field = fields.next().ok_or_else(syntax)?;
+        if !ports[port] {
+            continue;
+        }
+        if field.len() != 8 {
+            return Err(syntax());
+        }
+
+        for (index, flag) in field.bytes().enumerate() {
+            if flag != b'.' && flag != b' ' {
+                *buttons |= 0x80 >> index;
+            }
+        }
+    }
+
+    Ok(frame)
+}
+
+/// Writes `movie` as a text FCEUX movie.
+pub fn write(movie: &Movie) -> String {
+    let mut text = String::new();
+    let checksum = movie.rom_checksum.unwrap_or_default();
+
+    let _ = writeln!(text, "version 3");
+    let _ = writeln!(text, "emuVersion 0");
+    let _ = writeln!(text, "rerecordCount {}", movie.rerecords);
+    let _ = writeln!(text, "palFlag {}", (movie.region == Region::Pal) as u8);
+    let _ = writeln!(text, "romFilename {}", movie.rom_name);
+    let _ = writeln!(text, "romChecksum base64:{}", base64_encode(&checksum));
+    // Derived from the checksum, so the same movie always writes the same file.
+    let _ = writeln!(
+        text,
+        "guid {:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+        u32::from_be_bytes(checksum[0..4].try_into().unwrap()),
+        u16::from_be_bytes(checksum[4..6].try_into().unwrap()),
+        u16::from_be_bytes(checksum[6..8].try_into().unwrap()),
+        u16::from_be_bytes(checksum[8..10].try_into().unwrap()),
+        u64::from_be_bytes([0, 0, 0, 0, 0, 0, checksum[10], checksum[11]]) << 32
+            | u32::from_be_bytes(checksum[12..16].try_into().unwrap()) as u64,
+    );
+    let _ = writeln!(text, "fourscore 0");
+    let _ = writeln!(text, "microphone 0");
+    let _ = writeln!(text, "port0 1");
+    let _ = writeln!(text, "port1 1");
+    let _ = writeln!(text, "port2 0");
+    let _ = writeln!(text, "FDS 0");
+    let _ = writeln!(text, "NewPPU 0");
+    for comment in &movie.comments {
+        let _ = writeln!(text, "comment {}", comment);
+    }
+
+    for frame in &movie.frames {
+        text.push('|');
+        text.push_str(&frame.commands.to_string());
+        for buttons in frame.buttons {
+            text.push('|');
+            for (index, &name) in BUTTONS.iter().enumerate() {
+                let held = buttons & (0x80 >> index) != 0;
+                text.push(if held { name as char } else { '.' });
+            }
+        }
+        text.push_str("||\n");
+    }
+
+    text
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut text = String::new();
+
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - index * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+
+    for byte in text.trim().trim_end_matches('=').bytes() {
+        let value = BASE64.iter().position(|&digit| digit == byte)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+
+        if count >= 8 {
+            count -= 8;
+            data.push((bits >> count) as u8);
+        }
+    }
+
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller;
+
+    const MOVIE: &str = "version 3
+emuVersion 22020
+rerecordCount 12
+palFlag 0
+romFilename Test
+romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+microphone 0
+port0 1
+port1 1
+port2 0
+FDS 0
+NewPPU 0
+comment author Someone
+|2|........|........||
+|0|....T...|........||
+|0|R......A|.L....B.||
+|1|........|........||
+";
+
+    #[test]
+    fn parse_movie() {
+        let movie = parse(MOVIE).unwrap();
+
+        assert_eq!(movie.rerecords, 12);
+        assert_eq!(movie.rom_name, "Test");
+        assert_eq!(movie.region, Region::Ntsc);
+        assert_eq!(movie.comments, ["author Someone"]);
+        // The MD5 of nothing.
+        assert_eq!(movie.rom_checksum.unwrap()[..4], [0xD4, 0x1D, 0x8C, 0xD9]);
+
+        assert_eq!(movie.frames.len(), 4);
+        assert_eq!(movie.frames[0].commands, POWER_CYCLE);
+        assert_eq!(movie.frames[1].buttons, [controller::START, 0]);
+        assert_eq!(
+            movie.frames[2].buttons,
+            [
+                controller::RIGHT | controller::A,
+                controller::LEFT | controller::B
+            ]
+        );
+        assert_eq!(movie.frames[3].commands, SOFT_RESET);
+    }
+
+    #[test]
+    fn round_trip() {
+        let movie = parse(MOVIE).unwrap();
+        assert_eq!(parse(&write(&movie)).unwrap(), movie);
+
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_decode("YWI=").unwrap(), b"ab");
+    }
+
+    #[test]
+    fn refused() {
+        assert_eq!(
+            parse("version 3\n|0|......|........||\n").unwrap_err(),
+            MovieError::Syntax(2)
+        );
+        assert!(matches!(
+            parse("version 3\nport0 2\n"),
+            Err(MovieError::Unsupported(_))
+        ));
+        assert_eq!(
+            parse("version 3\n|4|........|........||\n").unwrap_err(),
+            MovieError::Unsupported("FDS/VS commands".to_string())
+        );
+    }
+}
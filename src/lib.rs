@@ -1,2 +1,33 @@
+pub mod apu;
+pub mod battery;
+pub mod bk2;
 pub mod bus;
+pub mod cartridge;
+pub mod cheats;
+pub mod checksum;
+pub mod controller;
 pub mod cpu;
+pub mod database;
+pub mod fds;
+pub mod fm2;
+pub mod mapper;
+pub mod mmo;
+pub mod movie;
+pub mod nes;
+pub mod nsf;
+pub mod ntsc;
+pub mod palette;
+pub mod patch;
+pub mod png;
+pub mod ppu;
+pub mod recorder;
+pub mod region;
+pub mod resampler;
+pub mod rewind;
+pub mod savestate;
+pub mod test_rom;
+pub mod wav;
+pub mod zip;
+
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,535 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::process::ExitCode;
+
+use nes::battery::SaveFile;
+use nes::cartridge::Cartridge;
+use nes::cheats::{Cheat, Cheats};
+use nes::controller::{self, Adapter};
+use nes::database::Database;
+use nes::fm2;
+use nes::movie::{self, Movie, MovieRecorder};
+use nes::nes::Nes;
+use nes::ntsc::{self, NtscFilter};
+use nes::palette::Palette;
+use nes::patch;
+use nes::png;
+use nes::recorder::Recorder;
+use nes::region::Region;
+use nes::test_rom::{self, Monitor, TestResult};
+
+const USAGE: &str = "\
+Usage: nes <ROM> [OPTIONS]
+
+Runs a ROM without a display.
+
+Options:
+  --frames <N>         Frames to run, or the limit for --until-* (default 600)
+  --until-test         Stop once a test ROM reports its result at $6000
+  --until-pc <ADDR>    Stop once the CPU reaches ADDR (hexadecimal)
+  --input <FILE>       Controller input script
+  --movie <FILE>       Play a .fm2, .bk2 or .mmo movie from power on, until it ends
+  --convert <FILE>     Write the --movie as .fm2 instead of playing it
+  --record <FILE>      Record the input as an FCEUX .fm2 movie
+  --cheat <CODE>       Apply a Game Genie or AAAA:VV RAM code, may be repeated
+  --cheats <FILE>      Apply the cheats of an FCEUX .cht file
+  --png <FILE>         Write the final frame as PNG
+  --ntsc               Pass the PNG through the NTSC composite filter
+  --palette <FILE>     Colors for the PNG, from a 192 or 1536 byte .pal file
+  --wav <FILE>         Record the audio as WAV
+  --y4m <FILE>         Record every frame as Y4M video
+  --sample-rate <HZ>   Audio sample rate (default 44100)
+  --cpu                Print the CPU state when done
+  --bios <FILE>        Famicom Disk System BIOS, for .fds images
+  --patch <FILE>       Apply an .ips, .bps or .ups patch to the ROM first
+  --database <FILE>    Correct headers from this NES 2.0 XML game database
+  --save               Keep battery-backed RAM in a .sav file beside the ROM
+  --autosave <FRAMES>  Also write the .sav every FRAMES frames, implies --save
+  --region <REGION>    ntsc, pal or dendy, overriding the ROM header
+  --adapter <ADAPTER>  Connect a fourscore or hori four player adapter
+
+Input scripts hold one `<frame> [<player>:]<buttons>` entry per line, where
+players 3 and 4 are read through an --adapter, and buttons are joined with
+`+` from A, B, SELECT, START, UP, DOWN, LEFT and RIGHT, or `-` for none.
+Buttons stay held until the player's next entry. Everything after `#` is a
+comment.
+
+Exit status:
+  0  Ran to completion, or the test passed
+  1  The test failed
+  2  Invalid arguments or the ROM could not be loaded
+  3  The --until-* condition was not met in time";
+
+const EXIT_PASSED: u8 = 0;
+const EXIT_FAILED: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+const EXIT_TIMEOUT: u8 = 3;
+
+enum Until {
+    Frames,
+    Test,
+    Pc(u16),
+}
+
+struct Options {
+    rom: String,
+    bios: Option<String>,
+    patch: Option<String>,
+    database: Option<String>,
+    save: bool,
+    autosave: Option<u32>,
+    region: Option<Region>,
+    adapter: Adapter,
+    frames: u64,
+    until: Until,
+    input: Option<String>,
+    movie: Option<String>,
+    convert: Option<String>,
+    record: Option<String>,
+    cheats: Vec<Cheat>,
+    cheat_file: Option<String>,
+    png: Option<String>,
+    ntsc: bool,
+    palette: Option<String>,
+    wav: Option<String>,
+    y4m: Option<String>,
+    sample_rate: u32,
+    cpu: bool,
+}
+
+/// Sets the buttons of `player` from `frame` on.
+struct Input {
+    frame: u64,
+    player: usize,
+    buttons: u8,
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::from(EXIT_PASSED);
+        }
+        Err(error) => {
+            eprintln!("nes: {}\n\n{}", error, USAGE);
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+
+    match run(&options) {
+        Ok(code) => ExitCode::from(code),
+        Err(error) => {
+            eprintln!("nes: {}", error);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+/// Reads the command line, or returns `None` when it asks for help.
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        rom: String::new(),
+        bios: None,
+        patch: None,
+        database: None,
+        save: false,
+        autosave: None,
+        region: None,
+        adapter: Adapter::None,
+        frames: 600,
+        until: Until::Frames,
+        input: None,
+        movie: None,
+        convert: None,
+        record: None,
+        cheats: Vec::new(),
+        cheat_file: None,
+        png: None,
+        ntsc: false,
+        palette: None,
+        wav: None,
+        y4m: None,
+        sample_rate: 44100,
+        cpu: false,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--frames" => options.frames = parse_number(&value()?)?,
+            "--until-test" => options.until = Until::Test,
+            "--until-pc" => {
+                let addr = value()?;
+                let addr = addr.trim_start_matches('$').trim_start_matches("0x");
+                options.until = Until::Pc(
+                    u16::from_str_radix(addr, 16).map_err(|_| "invalid address".to_string())?,
+                );
+            }
+            "--input" => options.input = Some(value()?),
+            "--movie" => options.movie = Some(value()?),
+            "--convert" => options.convert = Some(value()?),
+            "--record" => options.record = Some(value()?),
+            "--cheat" => {
+                let cheat = Cheat::parse(&value()?).map_err(|error| error.to_string())?;
+                options.cheats.push(cheat);
+            }
+            "--cheats" => options.cheat_file = Some(value()?),
+            "--png" => options.png = Some(value()?),
+            "--ntsc" => options.ntsc = true,
+            "--palette" => options.palette = Some(value()?),
+            "--wav" => options.wav = Some(value()?),
+            "--y4m" => options.y4m = Some(value()?),
+            "--sample-rate" => options.sample_rate = parse_number(&value()?)?,
+            "--cpu" => options.cpu = true,
+            "--bios" => options.bios = Some(value()?),
+            "--patch" => options.patch = Some(value()?),
+            "--database" => options.database = Some(value()?),
+            "--save" => options.save = true,
+            "--autosave" => {
+                options.save = true;
+                options.autosave = Some(parse_number(&value()?)?);
+            }
+            "--region" => {
+                options.region = Some(match value()?.to_ascii_lowercase().as_str() {
+                    "ntsc" => Region::Ntsc,
+                    "pal" => Region::Pal,
+                    "dendy" => Region::Dendy,
+                    region => return Err(format!("unknown region {}", region)),
+                })
+            }
+            "--adapter" => {
+                options.adapter = match value()?.to_ascii_lowercase().as_str() {
+                    "fourscore" => Adapter::FourScore,
+                    "hori" => Adapter::Hori,
+                    adapter => return Err(format!("unknown adapter {}", adapter)),
+                }
+            }
+            "-h" | "--help" => return Ok(None),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if options.rom.is_empty() => options.rom = arg,
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if options.rom.is_empty() {
+        return Err("no ROM given".to_string());
+    }
+    if options.movie.is_some() && options.input.is_some() {
+        return Err("--movie and --input both give the input".to_string());
+    }
+    // Movies start from a console without saved games.
+    if options.save && (options.movie.is_some() || options.record.is_some()) {
+        return Err("--save can't be used with movies".to_string());
+    }
+    if options.convert.is_some() && options.movie.is_none() {
+        return Err("--convert needs a --movie".to_string());
+    }
+
+    Ok(Some(options))
+}
+
+fn parse_number<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid number {}", text))
+}
+
+fn parse_script(script: &str) -> Result<Vec<Input>, String> {
+    let mut inputs = Vec::new();
+
+    for (number, line) in script.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let error = || format!("input script line {}: {}", number + 1, line);
+
+        let mut fields = line.split_whitespace();
+        let frame = fields
+            .next()
+            .ok_or_else(error)?
+            .parse()
+            .map_err(|_| error())?;
+        let field = fields.next().ok_or_else(error)?;
+        if fields.next().is_some() {
+            return Err(error());
+        }
+
+        let (player, buttons) = match field.split_once(':') {
+            Some(("1", buttons)) => (0, buttons),
+            Some(("2", buttons)) => (1, buttons),
+            Some(("3", buttons)) => (2, buttons),
+            Some(("4", buttons)) => (3, buttons),
+            Some(_) => return Err(error()),
+            None => (0, field),
+        };
+
+        let buttons = if buttons == "-" {
+            0
+        } else {
+            buttons.split('+').try_fold(0, |held, name| {
+                let button = match name.to_ascii_uppercase().as_str() {
+                    "A" => controller::A,
+                    "B" => controller::B,
+                    "SELECT" => controller::SELECT,
+                    "START" => controller::START,
+                    "UP" => controller::UP,
+                    "DOWN" => controller::DOWN,
+                    "LEFT" => controller::LEFT,
+                    "RIGHT" => controller::RIGHT,
+                    _ => return Err(error()),
+                };
+                Ok(held | button)
+            })?
+        };
+
+        inputs.push(Input {
+            frame,
+            player,
+            buttons,
+        });
+    }
+
+    inputs.sort_by_key(|input| input.frame);
+
+    Ok(inputs)
+}
+
+/// The ROM, patched when asked to.
+fn read_rom(options: &Options) -> Result<Vec<u8>, Box<dyn Error>> {
+    let rom = fs::read(&options.rom)?;
+    match &options.patch {
+        Some(path) => Ok(patch::apply(&rom, &fs::read(path)?)?),
+        None => Ok(rom),
+    }
+}
+
+fn load(options: &Options, rom: &[u8]) -> Result<Cartridge, Box<dyn Error>> {
+    if options.rom.to_ascii_lowercase().ends_with(".fds") {
+        let bios = options
+            .bios
+            .as_ref()
+            .ok_or("disk images need the BIOS, pass it with --bios")?;
+        return Ok(Cartridge::from_fds(rom, &fs::read(bios)?)?);
+    }
+
+    let cartridge = match &options.database {
+        Some(path) => Cartridge::with_database(rom, &Database::parse(&fs::read_to_string(path)?)?)?,
+        None => Cartridge::from_bytes(rom)?,
+    };
+    if !cartridge.corrected.is_empty() {
+        eprintln!(
+            "nes: the game database corrected the header's {}",
+            cartridge.corrected.join(", ")
+        );
+    }
+
+    Ok(cartridge)
+}
+
+fn run(options: &Options) -> Result<u8, Box<dyn Error>> {
+    let script = match &options.input {
+        Some(path) => parse_script(&fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+
+    let rom = read_rom(options)?;
+    let mut cartridge = load(options, &rom)?;
+    let mut save_file = match options.save {
+        true => SaveFile::open(SaveFile::path_for(&options.rom), &mut cartridge)?,
+        false => None,
+    };
+    if let Some(save_file) = &mut save_file {
+        save_file.set_autosave(options.autosave);
+    }
+
+    let mut nes = Nes::new(cartridge);
+    if let Some(path) = &options.palette {
+        nes.bus.palette = Palette::from_pal(&fs::read(path)?)?;
+    }
+    if let Some(region) = options.region {
+        nes.set_region(region);
+    }
+    nes.bus.multitap.adapter = options.adapter;
+    nes.set_sample_rate(options.sample_rate);
+    if let Some(path) = &options.cheat_file {
+        nes.bus.cheats = Cheats::from_cht(&fs::read_to_string(path)?)?;
+    }
+    for cheat in &options.cheats {
+        nes.bus.cheats.add(cheat.clone());
+    }
+
+    let recorder = if options.y4m.is_some() || options.wav.is_some() {
+        Some(Recorder::create(
+            &mut nes,
+            options.y4m.as_deref(),
+            options.wav.as_deref(),
+        )?)
+    } else {
+        None
+    };
+
+    let movie = match &options.movie {
+        Some(path) => {
+            let movie = movie::import(&fs::read(path)?)?;
+            movie.verify(&rom)?;
+            Some(movie)
+        }
+        None => None,
+    };
+    if let (Some(path), Some(movie)) = (&options.convert, &movie) {
+        // FM2 identifies the ROM by MD5, which other formats may not record.
+        let movie = Movie {
+            rom_checksum: Some(movie::rom_checksum(&rom)),
+            ..movie.clone()
+        };
+        fs::write(path, fm2::write(&movie))?;
+        return Ok(EXIT_PASSED);
+    }
+
+    let mut player = movie.as_ref().map(|movie| movie.play(&mut nes));
+    let mut movie_recorder = options
+        .record
+        .is_some()
+        .then(|| MovieRecorder::new(&mut nes, &rom));
+
+    let mut monitor = Monitor::default();
+    let mut inputs = script.iter().peekable();
+    let mut outcome = match options.until {
+        Until::Frames => Some(EXIT_PASSED),
+        _ => None,
+    };
+
+    for frame in 0..options.frames {
+        while let Some(input) = inputs.next_if(|input| input.frame <= frame) {
+            nes.bus.controllers[input.player].buttons = input.buttons;
+        }
+        if let Some(player) = &mut player {
+            if !player.apply(&mut nes) {
+                break;
+            }
+        }
+        if let Some(movie_recorder) = &mut movie_recorder {
+            movie_recorder.record(&nes)?;
+        }
+
+        let reached = match options.until {
+            Until::Pc(addr) => run_frame_until(&mut nes, addr),
+            _ => {
+                nes.run_frame();
+                false
+            }
+        };
+
+        // The recorder gets its audio through the callbacks.
+        nes.audio.clear();
+
+        if let (Some(save_file), Some(cartridge)) = (&mut save_file, &nes.bus.cartridge) {
+            save_file.frame(cartridge)?;
+        }
+
+        match options.until {
+            Until::Frames => {}
+            Until::Pc(_) if reached => {
+                outcome = Some(EXIT_PASSED);
+                break;
+            }
+            Until::Pc(_) => {}
+            Until::Test => match monitor.poll(&mut nes, frame + 1) {
+                Some(TestResult::Passed) => {
+                    outcome = Some(EXIT_PASSED);
+                    break;
+                }
+                Some(result) => {
+                    if let TestResult::Failed(code) = result {
+                        eprintln!("nes: test failed with result code {}", code);
+                    }
+                    outcome = Some(EXIT_FAILED);
+                    break;
+                }
+                None => {}
+            },
+        }
+    }
+
+    if let Until::Test = options.until {
+        let text = test_rom::text(&mut nes);
+        if !text.is_empty() {
+            println!("{}", text.trim_end());
+        }
+    }
+
+    if options.cpu {
+        let cpu = &nes.cpu;
+        println!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{} FRAME:{}",
+            cpu.pc,
+            cpu.a,
+            cpu.x,
+            cpu.y,
+            cpu.status,
+            cpu.sp,
+            nes.bus.cycles,
+            nes.bus.ppu.frame_count
+        );
+    }
+
+    if let Some(path) = &options.png {
+        let image = if options.ntsc {
+            let rgba = NtscFilter::default().apply(&nes.bus.ppu).to_vec();
+            let rgb: Vec<u8> = rgba
+                .chunks(4)
+                .flat_map(|pixel| &pixel[..3])
+                .copied()
+                .collect();
+            png::encode(ntsc::WIDTH as u32, ntsc::HEIGHT as u32, &rgb)
+        } else {
+            nes.screenshot()
+        };
+        fs::write(path, image)?;
+    }
+
+    if let Some(recorder) = recorder {
+        recorder.finish()?;
+    }
+
+    if let (Some(save_file), Some(cartridge)) = (&mut save_file, &nes.bus.cartridge) {
+        save_file.flush(cartridge)?;
+    }
+
+    if let (Some(path), Some(movie_recorder)) = (&options.record, movie_recorder) {
+        let movie = Movie {
+            rom_name: rom_name(&options.rom),
+            ..movie_recorder.finish()
+        };
+        fs::write(path, fm2::write(&movie))?;
+    }
+
+    Ok(outcome.unwrap_or(EXIT_TIMEOUT))
+}
+
+/// The file name of a ROM without its directory or extension.
+fn rom_name(path: &str) -> String {
+    let path = std::path::Path::new(path);
+    path.file_stem()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Runs a frame instruction by instruction, stopping early when the CPU
+/// reaches `addr`.
+fn run_frame_until(nes: &mut Nes, addr: u16) -> bool {
+    nes.bus.ppu.frame_complete = false;
+
+    while !nes.bus.ppu.frame_complete {
+        nes.step();
+        if nes.cpu.pc == addr {
+            return true;
+        }
+    }
+
+    false
+}
@@ -0,0 +1,874 @@
+use super::Mapper;
+use crate::cartridge::Mirroring;
+use crate::fds::{self, DiskImage};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// CPU cycles the drive takes to move one byte past the head.
+const BYTE_CYCLES: u32 = 150;
+
+/// CPU cycles the head takes to return to the start of the disk.
+const REWIND_CYCLES: u32 = 50000;
+
+/// Modulation table steps, where 4 resets the counter instead.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+/// Master volume multipliers selected through $4089.
+const MASTER_VOLUME: [u32; 4] = [36, 24, 17, 14];
+
+/// The volume and modulation envelopes of the FDS sound channel.
+#[derive(Default)]
+struct Envelope {
+    speed: u8,
+    increase: bool,
+    disabled: bool,
+    gain: u8,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, data: u8) {
+        self.speed = data & 0x3F;
+        self.increase = data & 0x40 != 0;
+        self.disabled = data & 0x80 != 0;
+        self.timer = 0;
+
+        if self.disabled {
+            self.gain = self.speed;
+        }
+    }
+
+    fn tick(&mut self, master_speed: u8) {
+        if self.disabled || master_speed == 0 {
+            return;
+        }
+
+        self.timer += 1;
+        if self.timer < 8 * (self.speed as u32 + 1) * master_speed as u32 {
+            return;
+        }
+        self.timer = 0;
+
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+}
+
+impl Savestate for Envelope {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.speed);
+        state.bool(self.increase);
+        state.bool(self.disabled);
+        state.u8(self.gain);
+        state.u32(self.timer);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.speed = state.u8()?;
+        self.increase = state.bool()?;
+        self.disabled = state.bool()?;
+        self.gain = state.u8()?;
+        self.timer = state.u32()?;
+
+        Ok(())
+    }
+}
+
+/// The FDS sound channel: a 64 step, 6 bit wavetable whose pitch is bent by
+/// a second table of modulation steps.
+pub(super) struct FdsAudio {
+    wave: [u8; 64],
+    wave_write: bool,
+    wave_position: u8,
+    wave_accumulator: u16,
+    frequency: u16,
+    halt: bool,
+    envelopes_disabled: bool,
+    master_volume: u8,
+    master_speed: u8,
+    volume: Envelope,
+
+    modulation: Envelope,
+    mod_table: [u8; 64],
+    mod_position: u8,
+    mod_accumulator: u16,
+    mod_frequency: u16,
+    mod_halt: bool,
+    mod_counter: i8,
+    mod_output: i32,
+
+    output: u8,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        Self {
+            wave: [0; 64],
+            wave_write: false,
+            wave_position: 0,
+            wave_accumulator: 0,
+            frequency: 0,
+            halt: false,
+            envelopes_disabled: false,
+            master_volume: 0,
+            master_speed: 0,
+            volume: Envelope::default(),
+
+            modulation: Envelope::default(),
+            mod_table: [0; 64],
+            mod_position: 0,
+            mod_accumulator: 0,
+            mod_frequency: 0,
+            mod_halt: false,
+            mod_counter: 0,
+            mod_output: 0,
+
+            output: 0,
+        }
+    }
+}
+
+impl FdsAudio {
+    pub(super) fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave[addr as usize & 0x3F]),
+            0x4090 => Some(self.volume.gain | 0x40),
+            0x4092 => Some(self.modulation.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    pub(super) fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4040..=0x407F if self.wave_write => self.wave[addr as usize & 0x3F] = data & 0x3F,
+            0x4080 => self.volume.write(data),
+            0x4082 => self.frequency = (self.frequency & 0x0F00) | data as u16,
+            0x4083 => {
+                self.frequency = (self.frequency & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.halt = data & 0x80 != 0;
+                self.envelopes_disabled = data & 0x40 != 0;
+
+                if self.halt {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+                if self.envelopes_disabled {
+                    self.volume.timer = 0;
+                    self.modulation.timer = 0;
+                }
+            }
+            0x4084 => self.modulation.write(data),
+            0x4085 => self.set_mod_counter(data & 0x7F),
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0F00) | data as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((data & 0x0F) as u16) << 8;
+                self.mod_halt = data & 0x80 != 0;
+
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            0x4088 if self.mod_halt => {
+                // Each write fills two consecutive entries.
+                for _ in 0..2 {
+                    self.mod_table[self.mod_position as usize] = data & 0x07;
+                    self.mod_position = (self.mod_position + 1) & 0x3F;
+                }
+            }
+            0x4089 => {
+                self.master_volume = data & 0x03;
+                self.wave_write = data & 0x80 != 0;
+            }
+            0x408A => self.master_speed = data,
+            _ => {}
+        }
+    }
+
+    /// The counter is a 7 bit signed value.
+    fn set_mod_counter(&mut self, value: u8) {
+        self.mod_counter = ((value << 1) as i8) >> 1;
+    }
+
+    pub(super) fn clock(&mut self) {
+        if !self.halt && !self.envelopes_disabled {
+            self.volume.tick(self.master_speed);
+            self.modulation.tick(self.master_speed);
+        }
+
+        if !self.mod_halt && self.mod_frequency > 0 {
+            let (accumulator, overflow) = self.mod_accumulator.overflowing_add(self.mod_frequency);
+            self.mod_accumulator = accumulator;
+
+            if overflow {
+                let step = self.mod_table[self.mod_position as usize];
+                let counter = if step == 4 {
+                    0
+                } else {
+                    self.mod_counter.wrapping_add(MOD_STEPS[step as usize]) as u8
+                };
+
+                self.set_mod_counter(counter & 0x7F);
+                self.mod_position = (self.mod_position + 1) & 0x3F;
+                self.update_modulation();
+            }
+        }
+
+        if self.halt {
+            self.wave_position = 0;
+        } else if !self.wave_write {
+            let pitch = self.frequency as i32 + self.mod_output;
+            if pitch > 0 {
+                let (accumulator, overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+                self.wave_accumulator = accumulator;
+
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) & 0x3F;
+                }
+            }
+        }
+
+        let gain = self.volume.gain.min(32) as u32 * MASTER_VOLUME[self.master_volume as usize];
+        self.output = (self.wave[self.wave_position as usize] as u32 * gain / 1152) as u8;
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        self.output as f32 / 63.0
+    }
+
+    /// Computes the pitch offset from the modulation counter and gain, with the
+    /// rounding quirks of the hardware.
+    fn update_modulation(&mut self) {
+        let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+
+        temp *= self.frequency as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+
+        self.mod_output = temp;
+    }
+}
+
+impl Savestate for FdsAudio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.wave);
+        state.bool(self.wave_write);
+        state.u8(self.wave_position);
+        state.u16(self.wave_accumulator);
+        state.u16(self.frequency);
+        state.bool(self.halt);
+        state.bool(self.envelopes_disabled);
+        state.u8(self.master_volume);
+        state.u8(self.master_speed);
+        self.volume.save_state(state);
+
+        self.modulation.save_state(state);
+        state.bytes(&self.mod_table);
+        state.u8(self.mod_position);
+        state.u16(self.mod_accumulator);
+        state.u16(self.mod_frequency);
+        state.bool(self.mod_halt);
+        state.u8(self.mod_counter as u8);
+        state.u32(self.mod_output as u32);
+
+        state.u8(self.output);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.wave)?;
+        self.wave_write = state.bool()?;
+        self.wave_position = state.u8()?;
+        self.wave_accumulator = state.u16()?;
+        self.frequency = state.u16()?;
+        self.halt = state.bool()?;
+        self.envelopes_disabled = state.bool()?;
+        self.master_volume = state.u8()?;
+        self.master_speed = state.u8()?;
+        self.volume.load_state(state)?;
+
+        self.modulation.load_state(state)?;
+        state.bytes_into(&mut self.mod_table)?;
+        self.mod_position = state.u8()?;
+        self.mod_accumulator = state.u16()?;
+        self.mod_frequency = state.u16()?;
+        self.mod_halt = state.bool()?;
+        self.mod_counter = state.u8()? as i8;
+        self.mod_output = state.u32()? as i32;
+
+        self.output = state.u8()?;
+
+        Ok(())
+    }
+}
+
+/// The Famicom Disk System RAM adapter and disk drive.
+///
+/// The adapter replaces the cartridge with 32 KiB of PRG RAM at $6000-$DFFF,
+/// 8 KiB of CHR RAM and the BIOS at $E000-$FFFF. The disk is reached through
+/// registers at $4020-$4033, and the sound channel at $4040-$4092.
+pub struct Fds {
+    bios: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_ram: Vec<u8>,
+    disk: DiskImage,
+    side: Option<usize>,
+    mirroring: Mirroring,
+
+    disk_registers: bool,
+    sound_registers: bool,
+
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    timer_irq: bool,
+
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    crc_control: bool,
+    previous_crc_control: bool,
+    disk_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+    read_data: u8,
+    write_data: u8,
+    external: u8,
+
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    crc: u16,
+
+    audio: FdsAudio,
+}
+
+impl Fds {
+    pub fn new(bios: Vec<u8>, disk: DiskImage) -> Self {
+        Self {
+            bios,
+            prg_ram: vec![0; 32 * 1024],
+            chr_ram: vec![0; 8 * 1024],
+            disk,
+            side: Some(0),
+            mirroring: Mirroring::Horizontal,
+
+            disk_registers: false,
+            sound_registers: false,
+
+            timer_reload: 0,
+            timer_counter: 0,
+            timer_repeat: false,
+            timer_enabled: false,
+            timer_irq: false,
+
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            previous_crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            transfer_complete: false,
+            read_data: 0,
+            write_data: 0,
+            external: 0,
+
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+
+            audio: FdsAudio::default(),
+        }
+    }
+
+    pub fn disk(&self) -> &DiskImage {
+        &self.disk
+    }
+
+    /// The side currently in the drive, if any.
+    pub fn side(&self) -> Option<usize> {
+        self.side
+    }
+
+    /// Inserts `side` into the drive. Games expect the drive to be empty for a
+    /// moment before a new side shows up, so eject first and let a few frames
+    /// pass before switching sides. Returns `false` if the image has no such
+    /// side.
+    pub fn insert(&mut self, side: usize) -> bool {
+        if side >= self.disk.side_count() {
+            return false;
+        }
+
+        self.side = Some(side);
+
+        true
+    }
+
+    pub fn eject(&mut self) {
+        self.side = None;
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.timer_enabled {
+            return;
+        }
+
+        if self.timer_counter == 0 {
+            self.timer_irq = true;
+            self.timer_counter = self.timer_reload;
+
+            if !self.timer_repeat {
+                self.timer_enabled = false;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// Moves the disk under the head one CPU cycle at a time. Every
+    /// `BYTE_CYCLES` a byte is transferred between the disk and the data
+    /// registers, and the disk IRQ is raised if enabled.
+    fn clock_drive(&mut self) {
+        let Some(side) = self.side.filter(|_| self.motor_on) else {
+            self.end_of_head = true;
+            self.scanning = false;
+            return;
+        };
+
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+
+        if self.read_mode {
+            let data = self.disk.side(side)[self.position];
+
+            if !self.previous_crc_control {
+                self.crc = fds::crc_update(self.crc, data);
+            }
+
+            let mut irq = self.disk_irq_enabled;
+            if !self.disk_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                // The $80 mark ending the gap is consumed without a transfer.
+                self.gap_ended = true;
+                irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let data = if !self.crc_control {
+                self.transfer_complete = true;
+                self.disk_irq |= self.disk_irq_enabled;
+
+                let data = if self.disk_ready { self.write_data } else { 0 };
+                self.crc = fds::crc_update(self.crc, data);
+
+                data
+            } else {
+                // Flushing two zero bytes through the CRC leaves the checksum
+                // in the accumulator, which is then shifted out byte by byte.
+                if !self.previous_crc_control {
+                    self.crc = fds::crc_update(self.crc, 0);
+                    self.crc = fds::crc_update(self.crc, 0);
+                }
+
+                let data = self.crc as u8;
+                self.crc >>= 8;
+
+                data
+            };
+
+            self.disk.side_mut(side)[self.position] = data;
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+        self.position += 1;
+
+        if self.position >= self.disk.side(side).len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    fn write_control(&mut self, data: u8) {
+        self.motor_on = data & 0x01 != 0;
+        self.reset_transfer = data & 0x02 != 0;
+        self.read_mode = data & 0x04 != 0;
+        self.mirroring = if data & 0x08 != 0 {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        };
+        self.crc_control = data & 0x10 != 0;
+        self.disk_ready = data & 0x40 != 0;
+        self.disk_irq_enabled = data & 0x80 != 0;
+
+        self.disk_irq = false;
+    }
+}
+
+impl Mapper for Fds {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4030 if self.disk_registers => {
+                let mut status = 0;
+                status |= self.timer_irq as u8;
+                status |= (self.transfer_complete as u8) << 1;
+                status |= (self.end_of_head as u8) << 6;
+
+                self.transfer_complete = false;
+                self.timer_irq = false;
+                self.disk_irq = false;
+
+                Some(status)
+            }
+            0x4031 if self.disk_registers => {
+                self.transfer_complete = false;
+                self.disk_irq = false;
+
+                Some(self.read_data)
+            }
+            0x4032 if self.disk_registers => {
+                let inserted = self.side.is_some();
+
+                let mut status = 0x40;
+                status |= !inserted as u8;
+                status |= (!inserted || !self.scanning) as u8 * 0x02;
+                status |= !inserted as u8 * 0x04;
+
+                Some(status)
+            }
+            // Bit 7 reports the battery as good.
+            0x4033 if self.disk_registers => Some(0x80 | (self.external & 0x7F)),
+            0x4040..=0x4097 if self.sound_registers => self.audio.read(addr),
+            0x6000..=0xDFFF => Some(self.prg_ram[addr as usize - 0x6000]),
+            0xE000..=0xFFFF => Some(self.bios[addr as usize & 0x1FFF]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | data as u16,
+            0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                self.timer_repeat = data & 0x01 != 0;
+                self.timer_enabled = data & 0x02 != 0 && self.disk_registers;
+
+                if self.timer_enabled {
+                    self.timer_counter = self.timer_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_registers = data & 0x01 != 0;
+                self.sound_registers = data & 0x02 != 0;
+
+                if !self.disk_registers {
+                    self.timer_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_registers => {
+                self.write_data = data;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_registers => self.write_control(data),
+            0x4026 if self.disk_registers => self.external = data,
+            0x4040..=0x4097 if self.sound_registers => self.audio.write(addr, data),
+            0x6000..=0xDFFF => self.prg_ram[addr as usize - 0x6000] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr_ram[addr as usize & 0x1FFF]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.chr_ram[addr as usize & 0x1FFF] = data;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock(&mut self) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+}
+
+impl Savestate for Fds {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        state.bytes(&self.chr_ram);
+        for side in 0..self.disk.side_count() {
+            state.bytes(self.disk.side(side));
+        }
+        state.u8(self.side.map_or(0xFF, |side| side as u8));
+        self.mirroring.save_state(state);
+
+        state.bool(self.disk_registers);
+        state.bool(self.sound_registers);
+
+        state.u16(self.timer_reload);
+        state.u16(self.timer_counter);
+        state.bool(self.timer_repeat);
+        state.bool(self.timer_enabled);
+        state.bool(self.timer_irq);
+
+        state.bool(self.motor_on);
+        state.bool(self.reset_transfer);
+        state.bool(self.read_mode);
+        state.bool(self.crc_control);
+        state.bool(self.previous_crc_control);
+        state.bool(self.disk_ready);
+        state.bool(self.disk_irq_enabled);
+        state.bool(self.disk_irq);
+        state.bool(self.transfer_complete);
+        state.u8(self.read_data);
+        state.u8(self.write_data);
+        state.u8(self.external);
+
+        state.u32(self.position as u32);
+        state.u32(self.delay);
+        state.bool(self.end_of_head);
+        state.bool(self.scanning);
+        state.bool(self.gap_ended);
+        state.u16(self.crc);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.prg_ram)?;
+        state.bytes_into(&mut self.chr_ram)?;
+        for side in 0..self.disk.side_count() {
+            state.bytes_into(self.disk.side_mut(side))?;
+        }
+        self.side = match state.u8()? {
+            0xFF => None,
+            side => Some(side as usize),
+        };
+        self.mirroring.load_state(state)?;
+
+        self.disk_registers = state.bool()?;
+        self.sound_registers = state.bool()?;
+
+        self.timer_reload = state.u16()?;
+        self.timer_counter = state.u16()?;
+        self.timer_repeat = state.bool()?;
+        self.timer_enabled = state.bool()?;
+        self.timer_irq = state.bool()?;
+
+        self.motor_on = state.bool()?;
+        self.reset_transfer = state.bool()?;
+        self.read_mode = state.bool()?;
+        self.crc_control = state.bool()?;
+        self.previous_crc_control = state.bool()?;
+        self.disk_ready = state.bool()?;
+        self.disk_irq_enabled = state.bool()?;
+        self.disk_irq = state.bool()?;
+        self.transfer_complete = state.bool()?;
+        self.read_data = state.u8()?;
+        self.write_data = state.u8()?;
+        self.external = state.u8()?;
+
+        self.position = state.u32()? as usize;
+        self.delay = state.u32()?;
+        self.end_of_head = state.bool()?;
+        self.scanning = state.bool()?;
+        self.gap_ended = state.bool()?;
+        self.crc = state.u16()?;
+
+        self.audio.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fds::SIDE_SIZE;
+
+    fn fds(sides: usize) -> Fds {
+        let mut image = Vec::new();
+        for _ in 0..sides {
+            let mut side = b"\x01*NINTENDO-HVC*".to_vec();
+            side.resize(SIDE_SIZE, 0);
+            image.extend(side);
+        }
+
+        let mut fds = Fds::new(vec![0; 8 * 1024], DiskImage::parse(&image).unwrap());
+        fds.cpu_write(0x4023, 0x01);
+        fds
+    }
+
+    /// Clocks until an IRQ, returning the cycles it took.
+    fn run_to_irq(fds: &mut Fds, limit: u32) -> Option<u32> {
+        (1..=limit).find(|_| {
+            fds.clock();
+            fds.irq()
+        })
+    }
+
+    #[test]
+    fn timer_irq() {
+        let mut fds = fds(1);
+        fds.cpu_write(0x4020, 0x02);
+        fds.cpu_write(0x4021, 0x00);
+        fds.cpu_write(0x4022, 0x03);
+
+        // Counts down to zero and fires on the cycle after.
+        assert_eq!(run_to_irq(&mut fds, 10), Some(3));
+        assert_eq!(fds.cpu_read(0x4030).map(|status| status & 0x01), Some(0x01));
+        assert!(!fds.irq());
+
+        // Reloaded, as it repeats.
+        assert_eq!(run_to_irq(&mut fds, 10), Some(3));
+        fds.cpu_write(0x4022, 0x00);
+        assert!(!fds.irq());
+
+        // Once only.
+        fds.cpu_write(0x4022, 0x02);
+        assert_eq!(run_to_irq(&mut fds, 10), Some(3));
+        fds.cpu_read(0x4030);
+        assert_eq!(run_to_irq(&mut fds, 10), None);
+
+        // Not without the disk registers enabled.
+        fds.cpu_write(0x4023, 0x00);
+        fds.cpu_write(0x4022, 0x03);
+        assert_eq!(run_to_irq(&mut fds, 10), None);
+    }
+
+    #[test]
+    fn transfer_irq() {
+        let mut fds = fds(1);
+        // Motor on, reading, ready, with the IRQ enabled.
+        fds.cpu_write(0x4025, 0xC5);
+
+        // The gap and the mark ending it go by without a transfer.
+        let limit = REWIND_CYCLES + (BYTE_CYCLES + 1) * 4000;
+        assert!(run_to_irq(&mut fds, limit).is_some());
+        assert_eq!(fds.cpu_read(0x4030).map(|status| status & 0x02), Some(0x02));
+        assert_eq!(fds.cpu_read(0x4031), Some(0x01));
+        assert!(!fds.irq());
+
+        // A byte every BYTE_CYCLES, acknowledged by reading it.
+        assert_eq!(run_to_irq(&mut fds, 1000), Some(BYTE_CYCLES + 1));
+        assert_eq!(fds.cpu_read(0x4031), Some(b'*'));
+        assert!(!fds.irq());
+
+        fds.cpu_write(0x4025, 0x45);
+        assert_eq!(run_to_irq(&mut fds, 1000), None);
+        assert_eq!(fds.cpu_read(0x4031), Some(b'N'));
+    }
+
+    #[test]
+    fn status_registers() {
+        let mut fds = fds(1);
+        fds.cpu_write(0x4026, 0x05);
+
+        // Still at the end of the head, with the disk in but not moving.
+        assert_eq!(fds.cpu_read(0x4030), Some(0x40));
+        assert_eq!(fds.cpu_read(0x4032), Some(0x42));
+        assert_eq!(fds.cpu_read(0x4033), Some(0x85));
+
+        fds.cpu_write(0x4025, 0x05);
+        for _ in 0..=REWIND_CYCLES + 1 {
+            fds.clock();
+        }
+        assert_eq!(fds.cpu_read(0x4030), Some(0x00));
+        assert_eq!(fds.cpu_read(0x4032), Some(0x40));
+
+        fds.cpu_write(0x4023, 0x00);
+        assert_eq!(fds.cpu_read(0x4030), None);
+        assert_eq!(fds.cpu_read(0x4032), None);
+    }
+
+    #[test]
+    fn eject_and_insert() {
+        let mut fds = fds(2);
+        assert_eq!(fds.side(), Some(0));
+
+        fds.eject();
+        assert_eq!(fds.side(), None);
+        assert_eq!(fds.cpu_read(0x4032), Some(0x47));
+
+        // Nothing is read from an empty drive.
+        fds.cpu_write(0x4025, 0xC5);
+        assert_eq!(run_to_irq(&mut fds, REWIND_CYCLES + 1000), None);
+        assert_eq!(fds.cpu_read(0x4032), Some(0x47));
+
+        assert!(!fds.insert(2));
+        assert_eq!(fds.side(), None);
+        assert!(fds.insert(1));
+        assert_eq!(fds.side(), Some(1));
+        assert_eq!(fds.cpu_read(0x4032), Some(0x42));
+
+        for _ in 0..=REWIND_CYCLES + 1 {
+            fds.clock();
+        }
+        assert_eq!(fds.cpu_read(0x4032), Some(0x40));
+    }
+}
@@ -0,0 +1,261 @@
+use super::{banked, chr_memory, load_memory, Mapper};
+use crate::cartridge::{Header, Mirroring};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// Mappers 9 and 10: the Nintendo MMC2 and MMC4.
+///
+/// Each 4 KiB pattern table has two CHR bank registers and a latch choosing
+/// between them. The PPU flips a latch by fetching tile $FD or $FE from its
+/// pattern table, which lets a game switch graphics halfway through a frame
+/// without any CPU involvement. The bank only changes after the triggering
+/// fetch has completed.
+pub struct Mmc2 {
+    mmc4: bool,
+
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+
+    prg_bank: u8,
+    chr_banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let (chr, chr_ram) = chr_memory(header, chr);
+
+        Self {
+            mmc4: header.mapper == 10,
+
+            prg,
+            chr,
+            chr_ram,
+            prg_ram: vec![0; 8 * 1024],
+
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1; 2],
+            mirroring: Mirroring::Vertical,
+        }
+    }
+
+    fn chr_index(&self, addr: u16) -> usize {
+        let table = (addr as usize >> 12) & 0x01;
+        let bank = self.chr_banks[table][self.latches[table]];
+
+        banked(&self.chr, bank as usize, 0x1000, addr)
+    }
+
+    /// The MMC2 only reacts to $0FD8 and $0FE8 in the first pattern table,
+    /// but to the whole 8 byte ranges in the second. The MMC4 uses the ranges
+    /// for both.
+    fn update_latch(&mut self, addr: u16) {
+        let table = (addr as usize >> 12) & 0x01;
+        let tile = addr & 0x0FF8;
+        let exact = table == 0 && !self.mmc4;
+
+        if exact && addr & 0x0007 != 0 {
+            return;
+        }
+
+        match tile {
+            0x0FD8 => self.latches[table] = 0,
+            0x0FE8 => self.latches[table] = 1,
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match (addr, self.mmc4) {
+            (0x6000..=0x7FFF, true) => Some(self.prg_ram[addr as usize & 0x1FFF]),
+            (0x8000..=0x9FFF, false) => {
+                Some(self.prg[banked(&self.prg, self.prg_bank as usize, 0x2000, addr)])
+            }
+            (0xA000..=0xFFFF, false) => {
+                // The last three 8 KiB banks are fixed.
+                let bank = self.prg.len() / 0x2000 - 4 + (addr as usize - 0x8000) / 0x2000;
+                Some(self.prg[banked(&self.prg, bank, 0x2000, addr)])
+            }
+            (0x8000..=0xBFFF, true) => {
+                Some(self.prg[banked(&self.prg, self.prg_bank as usize, 0x4000, addr)])
+            }
+            (0xC000..=0xFFFF, true) => {
+                let last = self.prg.len() / 0x4000 - 1;
+                Some(self.prg[banked(&self.prg, last, 0x4000, addr)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.mmc4 => self.prg_ram[addr as usize & 0x1FFF] = data,
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 0x01 != 0 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let data = self.chr[self.chr_index(addr)];
+        self.update_latch(addr);
+
+        data
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let index = self.chr_index(addr);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Only the MMC4 has PRG RAM.
+    fn save_ram(&self) -> Vec<u8> {
+        match self.mmc4 {
+            true => self.prg_ram.clone(),
+            false => Vec::new(),
+        }
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        load_memory(&mut self.prg_ram, data);
+    }
+}
+
+impl Savestate for Mmc2 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_ram {
+            state.bytes(&self.chr);
+        }
+
+        state.u8(self.prg_bank);
+        for banks in self.chr_banks.iter() {
+            state.bytes(banks);
+        }
+        state.u8(self.latches[0] as u8);
+        state.u8(self.latches[1] as u8);
+        self.mirroring.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.prg_ram)?;
+        if self.chr_ram {
+            state.bytes_into(&mut self.chr)?;
+        }
+
+        self.prg_bank = state.u8()?;
+        for banks in self.chr_banks.iter_mut() {
+            state.bytes_into(banks)?;
+        }
+        self.latches[0] = state.u8()? as usize & 0x01;
+        self.latches[1] = state.u8()? as usize & 0x01;
+        self.mirroring.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::ppu::Ppu;
+    use std::any::Any;
+
+    fn mapper(id: u8) -> Mmc2 {
+        let mut data = b"NES\x1A\x08\x10".to_vec();
+        data.extend_from_slice(&[id << 4, 0]);
+        data.resize(16, 0);
+        let header = Header::parse(&data).unwrap();
+
+        let chr = (0..128 * 1024).map(|i| (i / 0x1000) as u8).collect();
+        Mmc2::new(&header, vec![0; 128 * 1024], chr)
+    }
+
+    #[test]
+    fn latch_switches_after_fetch() {
+        let mut mapper = mapper(9);
+        mapper.cpu_write(0xB000, 0x04);
+        mapper.cpu_write(0xC000, 0x05);
+
+        assert_eq!(mapper.ppu_read(0x0FD8), 5);
+        assert_eq!(mapper.ppu_read(0x0000), 4);
+        assert_eq!(mapper.ppu_read(0x0FE8), 4);
+        assert_eq!(mapper.ppu_read(0x0000), 5);
+    }
+
+    #[test]
+    fn latch_ranges() {
+        let mut mmc2 = mapper(9);
+        mmc2.cpu_write(0xB000, 0x04);
+        mmc2.cpu_write(0xD000, 0x06);
+        mmc2.cpu_write(0xE000, 0x07);
+
+        mmc2.ppu_read(0x0FDA);
+        assert_eq!(mmc2.latches[0], 1);
+        mmc2.ppu_read(0x1FDA);
+        assert_eq!(mmc2.ppu_read(0x1000), 6);
+
+        let mut mmc4 = mapper(10);
+        mmc4.ppu_read(0x0FDA);
+        assert_eq!(mmc4.latches[0], 0);
+    }
+
+    #[test]
+    fn sprite_fetch_switches_latch() {
+        let mut mapper = mapper(9);
+        mapper.cpu_write(0xD000, 0x06);
+        mapper.cpu_write(0xE000, 0x07);
+        let mut cartridge = Cartridge {
+            header: Header::default(),
+            mapper: Box::new(mapper),
+            corrected: Vec::new(),
+        };
+        let latch = |cartridge: &Cartridge| {
+            let mapper = cartridge.mapper.as_ref() as &dyn Any;
+            mapper.downcast_ref::<Mmc2>().unwrap().latches[1]
+        };
+
+        let mut ppu = Ppu::default();
+        ppu.write_register(&mut cartridge, 0x2000, 0x08);
+        ppu.write_register(&mut cartridge, 0x2001, 0x18);
+        // The fourth sprite on scanline 4 is tile $FD.
+        ppu.oam.fill(0xFF);
+        for (index, tile) in [0x10, 0x11, 0x12, 0xFD].into_iter().enumerate() {
+            ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[4, tile, 0x00, 0x10]);
+        }
+
+        while (ppu.scanline, ppu.dot) != (4, 257) {
+            ppu.clock(&mut cartridge);
+        }
+        assert_eq!(latch(&cartridge), 1);
+
+        // The latch flips with the high pattern byte of the fourth slot,
+        // which is read from $1FD8-$1FDF.
+        while ppu.dot <= 257 + 3 * 8 + 6 {
+            assert_eq!(latch(&cartridge), 1, "dot {}", ppu.dot);
+            ppu.clock(&mut cartridge);
+        }
+        assert_eq!(latch(&cartridge), 0);
+        assert_eq!(cartridge.mapper.ppu_read(0x1000), 6);
+    }
+}
@@ -0,0 +1,129 @@
+mod fds;
+mod mmc2;
+mod namco163;
+mod nrom;
+mod nsf;
+mod sunsoft_fme7;
+
+pub use fds::Fds;
+pub use mmc2::Mmc2;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use nsf::NsfMapper;
+pub use sunsoft_fme7::SunsoftFme7;
+
+use std::any::Any;
+
+use super::cartridge::{CartridgeError, Header, Mirroring};
+use super::savestate::Savestate;
+
+/// The circuitry on a cartridge that decodes CPU and PPU addresses onto its
+/// ROM and RAM chips, together with anything else the board carries, such as
+/// IRQ counters and expansion audio.
+pub trait Mapper: Savestate + Any {
+    /// Reads from $4020-$FFFF. Returns `None` when the cartridge does not
+    /// drive the data bus for `addr`.
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+
+    /// Writes to $4020-$FFFF.
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    /// Reads from the PPU address space for any address that
+    /// [`Mapper::ciram_address`] does not map onto the console's nametable RAM.
+    ///
+    /// Boards such as the MMC2 snoop on these reads, so the PPU has to issue
+    /// every pattern table fetch through here, sprite fetches included, in the
+    /// order and on the dot it performs them.
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Maps a PPU address onto an offset in the console's nametable RAM, or
+    /// `None` when the cartridge supplies the data through [`Mapper::ppu_read`].
+    fn ciram_address(&self, addr: u16) -> Option<u16> {
+        if addr < 0x2000 {
+            return None;
+        }
+
+        let table = (addr >> 10) & 0x03;
+        let page = match self.mirroring() {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 0x01,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+
+        Some(page << 10 | (addr & 0x03FF))
+    }
+
+    /// Advances the board by one CPU cycle.
+    fn clock(&mut self) {}
+
+    /// Whether the board is asserting the CPU's IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The current level of the board's expansion audio in the range
+    /// -1.0..=1.0, to be mixed with the APU output.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// The memory a battery may keep powered, such as PRG RAM or an EEPROM,
+    /// to be saved to disk. Whether the board has a battery is up to the
+    /// cartridge's header.
+    fn save_ram(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores memory returned by [`Mapper::save_ram`], possibly of another
+    /// size, copying what fits.
+    fn load_save_ram(&mut self, _data: &[u8]) {}
+}
+
+pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Result<Box<dyn Mapper>, CartridgeError> {
+    let size = prg.len();
+    // The smallest PRG ROM that holds the banks the board fixes in place.
+    let (mapper, minimum): (Box<dyn Mapper>, usize) = match header.mapper {
+        0 => (Box::new(Nrom::new(header, prg, chr)), 0x2000),
+        9 => (Box::new(Mmc2::new(header, prg, chr)), 0x8000),
+        10 => (Box::new(Mmc2::new(header, prg, chr)), 0x4000),
+        19 => (Box::new(Namco163::new(header, prg, chr)), 0x2000),
+        69 => (Box::new(SunsoftFme7::new(header, prg, chr)), 0x2000),
+        id => return Err(CartridgeError::UnsupportedMapper(id)),
+    };
+    if size < minimum {
+        return Err(CartridgeError::PrgTooSmall(size));
+    }
+
+    Ok(mapper)
+}
+
+/// Returns the offset of `addr` in a bank switched memory, where `bank` is
+/// selected for a window of `size` bytes. Out of range banks wrap around.
+fn banked(memory: &[u8], bank: usize, size: usize, addr: u16) -> usize {
+    let banks = (memory.len() / size).max(1);
+
+    (bank % banks) * size + (addr as usize & (size - 1))
+}
+
+/// Copies saved memory into `memory`, as much of it as fits.
+fn load_memory(memory: &mut [u8], data: &[u8]) {
+    let length = memory.len().min(data.len());
+    memory[..length].copy_from_slice(&data[..length]);
+}
+
+/// CHR ROM when the cartridge has it, otherwise CHR RAM of the size the
+/// header asks for.
+fn chr_memory(header: &Header, chr: Vec<u8>) -> (Vec<u8>, bool) {
+    if chr.is_empty() {
+        let size = (header.chr_ram_size + header.chr_nvram_size).max(8 * 1024);
+        (vec![0; size], true)
+    } else {
+        (chr, false)
+    }
+}
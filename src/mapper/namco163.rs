@@ -0,0 +1,388 @@
+use super::{banked, chr_memory, load_memory, Mapper};
+use crate::cartridge::{Header, Mirroring};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// The sound half of the Namco 163: 128 bytes of internal RAM, reached through
+/// the address port at $F800 and the data port at $4800, that double as the
+/// registers and wavetables of up to eight channels.
+pub(super) struct N163Audio {
+    ram: [u8; 128],
+    address_port: u8,
+
+    cycle: u8,
+    channel: u8,
+    output: f32,
+}
+
+impl Default for N163Audio {
+    fn default() -> Self {
+        Self {
+            ram: [0; 128],
+            address_port: 0,
+
+            cycle: 0,
+            channel: 7,
+            output: 0.0,
+        }
+    }
+}
+
+impl N163Audio {
+    pub(super) fn read_data(&mut self) -> u8 {
+        let address = self.data_port_address();
+        self.ram[address]
+    }
+
+    pub(super) fn write_data(&mut self, data: u8) {
+        let address = self.data_port_address();
+        self.ram[address] = data;
+    }
+
+    pub(super) fn write_address(&mut self, data: u8) {
+        self.address_port = data;
+    }
+
+    /// Accesses through $4800 advance the address port when its top bit is set.
+    fn data_port_address(&mut self) -> usize {
+        let address = (self.address_port & 0x7F) as usize;
+        if self.address_port & 0x80 != 0 {
+            self.address_port = 0x80 | (self.address_port.wrapping_add(1) & 0x7F);
+        }
+
+        address
+    }
+
+    /// Only one channel is updated every 15 CPU cycles, cycling from channel 7
+    /// down through the enabled channels. The DAC outputs whichever channel was
+    /// updated last, so more active channels means a lower rate per channel.
+    pub(super) fn clock(&mut self) {
+        self.cycle += 1;
+        if self.cycle < 15 {
+            return;
+        }
+        self.cycle = 0;
+
+        let channel = self.channel as usize;
+        let base = 0x40 + channel * 8;
+        let ram = &mut self.ram;
+
+        let frequency =
+            ram[base] as u32 | (ram[base + 2] as u32) << 8 | ((ram[base + 4] & 0x03) as u32) << 16;
+        let phase =
+            ram[base + 1] as u32 | (ram[base + 3] as u32) << 8 | (ram[base + 5] as u32) << 16;
+        let length = 256 - (ram[base + 4] & 0xFC) as u32;
+
+        let phase = (phase + frequency) % (length << 16);
+        ram[base + 1] = phase as u8;
+        ram[base + 3] = (phase >> 8) as u8;
+        ram[base + 5] = (phase >> 16) as u8;
+
+        let index = ((phase >> 16) as usize + ram[base + 6] as usize) & 0xFF;
+        let sample = (ram[index >> 1] >> ((index & 0x01) * 4)) & 0x0F;
+        let volume = ram[base + 7] & 0x0F;
+
+        self.output = (sample as f32 - 8.0) * volume as f32 / 120.0;
+
+        let channels = ((ram[0x7F] >> 4) & 0x07) + 1;
+        self.channel = if self.channel <= 8 - channels {
+            7
+        } else {
+            self.channel - 1
+        };
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        self.output
+    }
+}
+
+impl Savestate for N163Audio {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        state.u8(self.address_port);
+
+        state.u8(self.cycle);
+        state.u8(self.channel);
+        state.u32(self.output.to_bits());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.ram)?;
+        self.address_port = state.u8()?;
+
+        self.cycle = state.u8()?;
+        self.channel = state.u8()?;
+        self.output = f32::from_bits(state.u32()?);
+
+        Ok(())
+    }
+}
+
+/// Mapper 19: the Namco 129/163.
+///
+/// Besides bank switching and the sound channels, the chip has a 15 bit IRQ
+/// counter that counts up every CPU cycle.
+pub struct Namco163 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+
+    chr_banks: [u8; 8],
+    nametable_banks: [u8; 4],
+    prg_banks: [u8; 3],
+
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: N163Audio,
+}
+
+impl Namco163 {
+    pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let (chr, chr_ram) = chr_memory(header, chr);
+
+        Self {
+            prg,
+            chr,
+            chr_ram,
+            prg_ram: vec![0; 8 * 1024],
+
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0, 0xE1, 0xE0, 0xE1],
+            prg_banks: [0; 3],
+
+            irq_counter: 0,
+            irq_pending: false,
+
+            audio: N163Audio::default(),
+        }
+    }
+
+    /// The bank register that `addr` is decoded through, for both pattern
+    /// tables and nametables.
+    fn ppu_bank(&self, addr: u16) -> u8 {
+        if addr < 0x2000 {
+            self.chr_banks[(addr as usize >> 10) & 0x07]
+        } else {
+            self.nametable_banks[(addr as usize >> 10) & 0x03]
+        }
+    }
+
+    /// The $E800 register can stop each half of the pattern tables from
+    /// mapping bank values of $E0 and up onto nametable RAM.
+    fn uses_ciram(&self, addr: u16) -> bool {
+        let disabled = match addr {
+            0x0000..=0x0FFF => self.prg_banks[1] & 0x40 != 0,
+            0x1000..=0x1FFF => self.prg_banks[1] & 0x80 != 0,
+            _ => false,
+        };
+
+        !disabled && self.ppu_bank(addr) >= 0xE0
+    }
+}
+
+impl Mapper for Namco163 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.audio.read_data()),
+            0x5000..=0x57FF => Some(self.irq_counter as u8),
+            0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8),
+            0x6000..=0x7FFF => Some(self.prg_ram[addr as usize & 0x1FFF]),
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[(addr as usize - 0x8000) / 0x2000] & 0x3F;
+                Some(self.prg[banked(&self.prg, bank as usize, 0x2000, addr)])
+            }
+            0xE000..=0xFFFF => {
+                let last = self.prg.len() / 0x2000 - 1;
+                Some(self.prg[banked(&self.prg, last, 0x2000, addr)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4800..=0x4FFF => self.audio.write_data(data),
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0xFF00) | data as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8;
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF => {
+                // Writes need $4x in $F800, with the low nibble protecting
+                // each 2 KiB window individually.
+                let window = (addr as usize - 0x6000) / 0x0800;
+                let protect = self.audio.address_port;
+                if protect & 0xF0 == 0x40 && protect & (1 << window) == 0 {
+                    self.prg_ram[addr as usize & 0x1FFF] = data;
+                }
+            }
+            0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) >> 11] = data,
+            0xC000..=0xDFFF => self.nametable_banks[(addr as usize - 0xC000) >> 11] = data,
+            0xE000..=0xF7FF => self.prg_banks[(addr as usize - 0xE000) >> 11] = data,
+            0xF800..=0xFFFF => self.audio.write_address(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.ppu_bank(addr);
+        self.chr[banked(&self.chr, bank as usize, 0x0400, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let bank = self.ppu_bank(addr);
+            let index = banked(&self.chr, bank as usize, 0x0400, addr);
+            self.chr[index] = data;
+        }
+    }
+
+    /// Nametables are freely assignable, so this only reports the closest
+    /// standard arrangement. Accesses go through [`Namco163::ciram_address`].
+    fn mirroring(&self) -> Mirroring {
+        match self.nametable_banks.map(|bank| bank & 0x01) {
+            [0, 0, 1, 1] => Mirroring::Horizontal,
+            [0, 0, 0, 0] => Mirroring::SingleScreenLower,
+            [1, 1, 1, 1] => Mirroring::SingleScreenUpper,
+            _ => Mirroring::Vertical,
+        }
+    }
+
+    fn ciram_address(&self, addr: u16) -> Option<u16> {
+        if self.uses_ciram(addr) {
+            let page = (self.ppu_bank(addr) & 0x01) as u16;
+            Some(page << 10 | (addr & 0x03FF))
+        } else {
+            None
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.irq_counter & 0x8000 != 0 && self.irq_counter & 0x7FFF != 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter & 0x7FFF == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        // Bit 6 of $E000 silences the sound channels.
+        if self.prg_banks[0] & 0x40 == 0 {
+            self.audio.clock();
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.prg_banks[0] & 0x40 == 0 {
+            self.audio.output()
+        } else {
+            0.0
+        }
+    }
+
+    /// The chip's sound RAM is kept too, as some games save to it.
+    fn save_ram(&self) -> Vec<u8> {
+        let mut ram = self.prg_ram.clone();
+        ram.extend_from_slice(&self.audio.ram);
+        ram
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        let (prg_ram, audio) = data.split_at(data.len().min(self.prg_ram.len()));
+        load_memory(&mut self.prg_ram, prg_ram);
+        load_memory(&mut self.audio.ram, audio);
+    }
+}
+
+impl Savestate for Namco163 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_ram {
+            state.bytes(&self.chr);
+        }
+
+        state.bytes(&self.chr_banks);
+        state.bytes(&self.nametable_banks);
+        state.bytes(&self.prg_banks);
+
+        state.u16(self.irq_counter);
+        state.bool(self.irq_pending);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.prg_ram)?;
+        if self.chr_ram {
+            state.bytes_into(&mut self.chr)?;
+        }
+
+        state.bytes_into(&mut self.chr_banks)?;
+        state.bytes_into(&mut self.nametable_banks)?;
+        state.bytes_into(&mut self.prg_banks)?;
+
+        self.irq_counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+
+        self.audio.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Header;
+
+    fn mapper() -> Namco163 {
+        let mut data = b"NES\x1A\x02\x01\x30\x10".to_vec();
+        data.resize(16, 0);
+        let header = Header::parse(&data).unwrap();
+
+        Namco163::new(&header, vec![0; 32 * 1024], vec![0; 8 * 1024])
+    }
+
+    #[test]
+    fn data_port_auto_increment() {
+        let mut mapper = mapper();
+
+        mapper.cpu_write(0xF800, 0xFE);
+        mapper.cpu_write(0x4800, 0x12);
+        mapper.cpu_write(0x4800, 0x34);
+        mapper.cpu_write(0x4800, 0x56);
+
+        assert_eq!(mapper.audio.ram[0x7E], 0x12);
+        assert_eq!(mapper.audio.ram[0x7F], 0x34);
+        assert_eq!(mapper.audio.ram[0x00], 0x56);
+
+        mapper.cpu_write(0xF800, 0x7F);
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x34));
+        assert_eq!(mapper.cpu_read(0x4800), Some(0x34));
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut mapper = mapper();
+
+        mapper.cpu_write(0x5000, 0xFD);
+        mapper.cpu_write(0x5800, 0xFF);
+
+        mapper.clock();
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+
+        mapper.clock();
+        assert_eq!(mapper.cpu_read(0x5000), Some(0xFF));
+
+        mapper.cpu_write(0x5800, 0x00);
+        assert!(!mapper.irq());
+    }
+}
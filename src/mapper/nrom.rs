@@ -0,0 +1,86 @@
+use super::{banked, chr_memory, load_memory, Mapper};
+use crate::cartridge::{Header, Mirroring};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// Mapper 0: boards without any bank switching. 16 KiB of PRG ROM is mirrored
+/// to fill $8000-$FFFF, and Family Basic carts add RAM at $6000.
+pub struct Nrom {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let (chr, chr_ram) = chr_memory(header, chr);
+
+        Self {
+            prg,
+            chr,
+            chr_ram,
+            prg_ram: vec![0; 8 * 1024],
+            mirroring: header.mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.prg_ram[addr as usize & 0x1FFF]),
+            0x8000..=0xFFFF if !self.prg.is_empty() => {
+                Some(self.prg[(addr as usize - 0x8000) % self.prg.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram[addr as usize & 0x1FFF] = data;
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.chr[banked(&self.chr, 0, 0x2000, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let index = banked(&self.chr, 0, 0x2000, addr);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        load_memory(&mut self.prg_ram, data);
+    }
+}
+
+impl Savestate for Nrom {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_ram {
+            state.bytes(&self.chr);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.prg_ram)?;
+        if self.chr_ram {
+            state.bytes_into(&mut self.chr)?;
+        }
+
+        Ok(())
+    }
+}
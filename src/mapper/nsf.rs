@@ -0,0 +1,217 @@
+use super::fds::FdsAudio;
+use super::namco163::N163Audio;
+use super::sunsoft_fme7::Sunsoft5b;
+use super::Mapper;
+use crate::cartridge::Mirroring;
+use crate::nsf::{self, Nsf};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// The hardware an NSF expects around the CPU: 4 KiB PRG banks switched
+/// through $5FF8-$5FFF, 8 KiB of RAM at $6000 and whichever expansion sound
+/// chips the file asks for.
+///
+/// Tunes using the FDS chip get RAM across all of $6000-$FFFF instead, and
+/// two more bank registers at $5FF6-$5FF7. Bank writes then copy the selected
+/// bank into that RAM.
+pub struct NsfMapper {
+    prg: Vec<u8>,
+    ram: Vec<u8>,
+    banks: [u8; 10],
+    fds_ram: bool,
+
+    sunsoft: Option<Sunsoft5b>,
+    namco: Option<N163Audio>,
+    fds: Option<FdsAudio>,
+}
+
+impl NsfMapper {
+    pub fn new(nsf: &Nsf) -> Self {
+        let fds_ram = nsf.chips & nsf::FDS != 0;
+        let base = if fds_ram { 0x6000 } else { 0x8000 };
+
+        // Banked tunes are aligned to 4 KiB, others are placed at their load
+        // address with banks counting up from the start of the window.
+        let padding = if nsf.bankswitched() {
+            nsf.load_address as usize & 0x0FFF
+        } else {
+            (nsf.load_address as usize).saturating_sub(base)
+        };
+
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(&nsf.data);
+
+        let mut mapper = Self {
+            prg,
+            ram: vec![0; if fds_ram { 40 * 1024 } else { 8 * 1024 }],
+            banks: [0; 10],
+            fds_ram,
+
+            sunsoft: (nsf.chips & nsf::SUNSOFT_5B != 0).then(Sunsoft5b::default),
+            namco: (nsf.chips & nsf::NAMCO_163 != 0).then(N163Audio::default),
+            fds: (nsf.chips & nsf::FDS != 0).then(FdsAudio::default),
+        };
+
+        mapper.reset(nsf);
+
+        mapper
+    }
+
+    /// Clears RAM and restores the initial banks, as is done before every
+    /// call to INIT.
+    fn reset(&mut self, nsf: &Nsf) {
+        self.ram.fill(0);
+
+        let banks = if nsf.bankswitched() {
+            nsf.banks
+        } else {
+            [0, 1, 2, 3, 4, 5, 6, 7]
+        };
+
+        if self.fds_ram {
+            let (low, high) = if nsf.bankswitched() {
+                (banks[6], banks[7])
+            } else {
+                (0, 1)
+            };
+
+            self.set_bank(0, low);
+            self.set_bank(1, high);
+            for (slot, bank) in banks.iter().enumerate() {
+                let bank = if nsf.bankswitched() { *bank } else { *bank + 2 };
+                self.set_bank(slot + 2, bank);
+            }
+        } else {
+            for (slot, bank) in banks.iter().enumerate() {
+                self.set_bank(slot + 2, *bank);
+            }
+        }
+    }
+
+    fn bank_data(&self, bank: u8) -> impl Iterator<Item = u8> + '_ {
+        let start = bank as usize * 0x1000;
+
+        (start..start + 0x1000).map(|index| self.prg.get(index).copied().unwrap_or(0))
+    }
+
+    /// Slot 0 and 1 are $6000 and $7000, and only exist with FDS RAM.
+    fn set_bank(&mut self, slot: usize, bank: u8) {
+        self.banks[slot] = bank;
+
+        if self.fds_ram {
+            let data: Vec<u8> = self.bank_data(bank).collect();
+            self.ram[slot * 0x1000..(slot + 1) * 0x1000].copy_from_slice(&data);
+        }
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x4097 if self.fds.is_some() => self.fds.as_ref()?.read(addr),
+            0x4800..=0x4FFF if self.namco.is_some() => Some(self.namco.as_mut()?.read_data()),
+            0x6000..=0xFFFF if self.fds_ram => Some(self.ram[addr as usize - 0x6000]),
+            0x6000..=0x7FFF => Some(self.ram[addr as usize & 0x1FFF]),
+            0x8000..=0xFFFF => {
+                let bank = self.banks[2 + (addr as usize - 0x8000) / 0x1000] as usize;
+                let index = bank * 0x1000 + (addr as usize & 0x0FFF);
+
+                Some(self.prg.get(index).copied().unwrap_or(0))
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let Some(fds) = &mut self.fds {
+            fds.write(addr, data);
+        }
+
+        if let Some(namco) = &mut self.namco {
+            match addr {
+                0x4800..=0x4FFF => namco.write_data(data),
+                0xF800..=0xFFFF => namco.write_address(data),
+                _ => {}
+            }
+        }
+
+        if let Some(sunsoft) = &mut self.sunsoft {
+            match addr {
+                0xC000..=0xDFFF => sunsoft.select(data),
+                0xE000..=0xFFFF => sunsoft.write(data),
+                _ => {}
+            }
+        }
+
+        match addr {
+            0x5FF6..=0x5FF7 if self.fds_ram => self.set_bank(addr as usize - 0x5FF6, data),
+            0x5FF8..=0x5FFF => self.set_bank(addr as usize - 0x5FF6, data),
+            0x6000..=0xFFFF if self.fds_ram => self.ram[addr as usize - 0x6000] = data,
+            0x6000..=0x7FFF => self.ram[addr as usize & 0x1FFF] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, _: u16) -> u8 {
+        0
+    }
+
+    fn ppu_write(&mut self, _: u16, _: u8) {}
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    fn clock(&mut self) {
+        if let Some(sunsoft) = &mut self.sunsoft {
+            sunsoft.clock();
+        }
+        if let Some(namco) = &mut self.namco {
+            namco.clock();
+        }
+        if let Some(fds) = &mut self.fds {
+            fds.clock();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let sunsoft = self.sunsoft.as_ref().map_or(0.0, |chip| chip.output());
+        let namco = self.namco.as_ref().map_or(0.0, |chip| chip.output());
+        let fds = self.fds.as_ref().map_or(0.0, |chip| chip.output());
+
+        (sunsoft + namco + fds).clamp(-1.0, 1.0)
+    }
+}
+
+impl Savestate for NsfMapper {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.ram);
+        state.bytes(&self.banks);
+
+        if let Some(sunsoft) = &self.sunsoft {
+            sunsoft.save_state(state);
+        }
+        if let Some(namco) = &self.namco {
+            namco.save_state(state);
+        }
+        if let Some(fds) = &self.fds {
+            fds.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.ram)?;
+        state.bytes_into(&mut self.banks)?;
+
+        if let Some(sunsoft) = &mut self.sunsoft {
+            sunsoft.load_state(state)?;
+        }
+        if let Some(namco) = &mut self.namco {
+            namco.load_state(state)?;
+        }
+        if let Some(fds) = &mut self.fds {
+            fds.load_state(state)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,515 @@
+use super::{banked, chr_memory, load_memory, Mapper};
+use crate::cartridge::{Header, Mirroring};
+use crate::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// Output levels of the 5B's volume DAC, in 3 dB steps from full scale.
+const VOLUME: [f32; 16] = [
+    0.0, 0.0079, 0.0112, 0.0158, 0.0224, 0.0316, 0.0447, 0.0631, 0.0891, 0.1259, 0.1778, 0.2512,
+    0.3548, 0.5012, 0.7079, 1.0,
+];
+
+/// The YM2149 derived sound chip of the Sunsoft 5B: three square wave
+/// channels sharing a noise generator and an envelope generator.
+pub(super) struct Sunsoft5b {
+    select: u8,
+    registers: [u8; 16],
+
+    prescaler: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    noise_lfsr: u32,
+    envelope_counter: u16,
+    envelope_level: u8,
+    envelope_rising: bool,
+    envelope_holding: bool,
+}
+
+impl Default for Sunsoft5b {
+    fn default() -> Self {
+        Self {
+            select: 0,
+            registers: [0; 16],
+
+            prescaler: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_lfsr: 1,
+            envelope_counter: 0,
+            envelope_level: 0,
+            envelope_rising: false,
+            envelope_holding: true,
+        }
+    }
+}
+
+impl Sunsoft5b {
+    pub(super) fn select(&mut self, data: u8) {
+        self.select = data;
+    }
+
+    pub(super) fn write(&mut self, data: u8) {
+        // Writes are ignored unless the upper nibble of the select latch is clear.
+        if self.select & 0xF0 != 0 {
+            return;
+        }
+
+        let register = self.select as usize;
+        self.registers[register] = data;
+
+        if register == 0x0D {
+            self.envelope_rising = data & 0x04 != 0;
+            self.envelope_level = if self.envelope_rising { 0 } else { 15 };
+            self.envelope_holding = false;
+            self.envelope_counter = 0;
+        }
+    }
+
+    /// The chip divides the CPU clock by 16 before driving its counters, so
+    /// a tone with period P toggles every 16 * P CPU cycles.
+    pub(super) fn clock(&mut self) {
+        self.prescaler += 1;
+        if self.prescaler < 16 {
+            return;
+        }
+        self.prescaler = 0;
+
+        for channel in 0..3 {
+            let period = (self.registers[channel * 2] as u16
+                | ((self.registers[channel * 2 + 1] & 0x0F) as u16) << 8)
+                .max(1);
+
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= period {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[0x06] & 0x1F).max(1) {
+            self.noise_counter = 0;
+
+            let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 0x01;
+            self.noise_lfsr = (self.noise_lfsr >> 1) | (feedback << 16);
+        }
+
+        let period = (self.registers[0x0B] as u16 | (self.registers[0x0C] as u16) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= period {
+            self.envelope_counter = 0;
+            self.step_envelope();
+        }
+    }
+
+    fn step_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+
+        if self.envelope_rising && self.envelope_level < 15 {
+            self.envelope_level += 1;
+            return;
+        }
+
+        if !self.envelope_rising && self.envelope_level > 0 {
+            self.envelope_level -= 1;
+            return;
+        }
+
+        let shape = self.registers[0x0D];
+        let (cont, alternate, hold) = (shape & 0x08 != 0, shape & 0x02 != 0, shape & 0x01 != 0);
+
+        if !cont {
+            self.envelope_level = 0;
+            self.envelope_holding = true;
+        } else if hold {
+            if alternate {
+                self.envelope_level = 15 - self.envelope_level;
+            }
+            self.envelope_holding = true;
+        } else if alternate {
+            self.envelope_rising = !self.envelope_rising;
+        } else {
+            self.envelope_level = if self.envelope_rising { 0 } else { 15 };
+        }
+    }
+
+    pub(super) fn output(&self) -> f32 {
+        let mixer = self.registers[0x07];
+        let mut sum = 0.0;
+
+        for channel in 0..3 {
+            let tone = mixer & (0x01 << channel) != 0 || self.tone_outputs[channel];
+            let noise = mixer & (0x08 << channel) != 0 || self.noise_lfsr & 0x01 != 0;
+
+            if tone && noise {
+                let volume = self.registers[0x08 + channel];
+                let level = if volume & 0x10 != 0 {
+                    self.envelope_level
+                } else {
+                    volume & 0x0F
+                };
+
+                sum += VOLUME[level as usize];
+            }
+        }
+
+        sum / 3.0
+    }
+}
+
+impl Savestate for Sunsoft5b {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.select);
+        state.bytes(&self.registers);
+        state.u8(self.prescaler);
+        for channel in 0..3 {
+            state.u16(self.tone_counters[channel]);
+            state.bool(self.tone_outputs[channel]);
+        }
+        state.u8(self.noise_counter);
+        state.u32(self.noise_lfsr);
+        state.u16(self.envelope_counter);
+        state.u8(self.envelope_level);
+        state.bool(self.envelope_rising);
+        state.bool(self.envelope_holding);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.select = state.u8()?;
+        state.bytes_into(&mut self.registers)?;
+        self.prescaler = state.u8()?;
+        for channel in 0..3 {
+            self.tone_counters[channel] = state.u16()?;
+            self.tone_outputs[channel] = state.bool()?;
+        }
+        self.noise_counter = state.u8()?;
+        self.noise_lfsr = state.u32()?;
+        self.envelope_counter = state.u16()?;
+        self.envelope_level = state.u8()?;
+        self.envelope_rising = state.bool()?;
+        self.envelope_holding = state.bool()?;
+
+        Ok(())
+    }
+}
+
+/// Mapper 69: the Sunsoft FME-7 and its 5B variant, which adds expansion audio.
+///
+/// Registers are accessed through a command port at $8000-$9FFF followed by a
+/// parameter write at $A000-$BFFF.
+pub struct SunsoftFme7 {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+
+    command: u8,
+    chr_banks: [u8; 8],
+    prg_banks: [u8; 4],
+    mirroring: Mirroring,
+
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    irq_pending: bool,
+
+    audio: Sunsoft5b,
+}
+
+impl SunsoftFme7 {
+    pub fn new(header: &Header, prg: Vec<u8>, chr: Vec<u8>) -> Self {
+        let (chr, chr_ram) = chr_memory(header, chr);
+        let prg_ram_size = (header.prg_ram_size + header.prg_nvram_size).max(8 * 1024);
+
+        Self {
+            prg,
+            chr,
+            chr_ram,
+            prg_ram: vec![0; prg_ram_size],
+
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring: Mirroring::Vertical,
+
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+
+            audio: Sunsoft5b::default(),
+        }
+    }
+
+    fn write_parameter(&mut self, data: u8) {
+        match self.command {
+            0x00..=0x07 => self.chr_banks[self.command as usize] = data,
+            0x08..=0x0B => self.prg_banks[self.command as usize - 0x08] = data,
+            0x0C => {
+                self.mirroring = match data & 0x03 {
+                    0 => Mirroring::Vertical,
+                    1 => Mirroring::Horizontal,
+                    2 => Mirroring::SingleScreenLower,
+                    _ => Mirroring::SingleScreenUpper,
+                }
+            }
+            0x0D => {
+                self.irq_enabled = data & 0x01 != 0;
+                self.irq_counter_enabled = data & 0x80 != 0;
+                self.irq_pending = false;
+            }
+            0x0E => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+        }
+    }
+}
+
+impl Mapper for SunsoftFme7 {
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => {
+                let bank = self.prg_banks[0];
+                match (bank & 0x40 != 0, bank & 0x80 != 0) {
+                    (false, _) => {
+                        Some(self.prg[banked(&self.prg, (bank & 0x3F) as usize, 0x2000, addr)])
+                    }
+                    (true, true) => Some(
+                        self.prg_ram[banked(&self.prg_ram, (bank & 0x3F) as usize, 0x2000, addr)],
+                    ),
+                    (true, false) => None,
+                }
+            }
+            0x8000..=0xDFFF => {
+                let bank = self.prg_banks[1 + (addr as usize - 0x8000) / 0x2000] & 0x3F;
+                Some(self.prg[banked(&self.prg, bank as usize, 0x2000, addr)])
+            }
+            0xE000..=0xFFFF => {
+                let last = self.prg.len() / 0x2000 - 1;
+                Some(self.prg[banked(&self.prg, last, 0x2000, addr)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {
+                let bank = self.prg_banks[0];
+                if bank & 0xC0 == 0xC0 {
+                    let index = banked(&self.prg_ram, (bank & 0x3F) as usize, 0x2000, addr);
+                    self.prg_ram[index] = data;
+                }
+            }
+            0x8000..=0x9FFF => self.command = data & 0x0F,
+            0xA000..=0xBFFF => self.write_parameter(data),
+            0xC000..=0xDFFF => self.audio.select(data),
+            0xE000..=0xFFFF => self.audio.write(data),
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr as usize >> 10) & 0x07];
+        self.chr[banked(&self.chr, bank as usize, 0x0400, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.chr_ram {
+            let bank = self.chr_banks[(addr as usize >> 10) & 0x07];
+            let index = banked(&self.chr, bank as usize, 0x0400, addr);
+            self.chr[index] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// The IRQ counter decrements every CPU cycle and fires when it wraps
+    /// from $0000 to $FFFF.
+    fn clock(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+
+        self.audio.clock();
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn save_ram(&self) -> Vec<u8> {
+        self.prg_ram.clone()
+    }
+
+    fn load_save_ram(&mut self, data: &[u8]) {
+        load_memory(&mut self.prg_ram, data);
+    }
+}
+
+impl Savestate for SunsoftFme7 {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.prg_ram);
+        if self.chr_ram {
+            state.bytes(&self.chr);
+        }
+
+        state.u8(self.command);
+        state.bytes(&self.chr_banks);
+        state.bytes(&self.prg_banks);
+        self.mirroring.save_state(state);
+
+        state.bool(self.irq_enabled);
+        state.bool(self.irq_counter_enabled);
+        state.u16(self.irq_counter);
+        state.bool(self.irq_pending);
+
+        self.audio.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        state.bytes_into(&mut self.prg_ram)?;
+        if self.chr_ram {
+            state.bytes_into(&mut self.chr)?;
+        }
+
+        self.command = state.u8()?;
+        state.bytes_into(&mut self.chr_banks)?;
+        state.bytes_into(&mut self.prg_banks)?;
+        self.mirroring.load_state(state)?;
+
+        self.irq_enabled = state.bool()?;
+        self.irq_counter_enabled = state.bool()?;
+        self.irq_counter = state.u16()?;
+        self.irq_pending = state.bool()?;
+
+        self.audio.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Header;
+
+    /// Each 8 KiB PRG bank is filled with its number.
+    fn mapper() -> SunsoftFme7 {
+        let mut data = b"NES\x1A\x02\x01\x50\x40".to_vec();
+        data.resize(16, 0);
+        let header = Header::parse(&data).unwrap();
+
+        let prg = (0..4).flat_map(|bank| [bank; 0x2000]).collect();
+        SunsoftFme7::new(&header, prg, vec![0; 8 * 1024])
+    }
+
+    fn command(mapper: &mut SunsoftFme7, command: u8, parameter: u8) {
+        mapper.cpu_write(0x8000, command);
+        mapper.cpu_write(0xA000, parameter);
+    }
+
+    #[test]
+    fn command_port() {
+        let mut mapper = mapper();
+
+        command(&mut mapper, 0x09, 0x02);
+        command(&mut mapper, 0x0B, 0x01);
+        assert_eq!(mapper.cpu_read(0x8000), Some(2));
+        assert_eq!(mapper.cpu_read(0xC000), Some(1));
+        assert_eq!(mapper.cpu_read(0xFFFF), Some(3));
+
+        // The parameter port keeps going to the last command.
+        mapper.cpu_write(0xBFFF, 0x03);
+        assert_eq!(mapper.cpu_read(0xDFFF), Some(3));
+
+        command(&mut mapper, 0x0C, 0x01);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        command(&mut mapper, 0x0C, 0x03);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn prg_ram() {
+        let mut mapper = mapper();
+
+        command(&mut mapper, 0x08, 0x01);
+        assert_eq!(mapper.cpu_read(0x6000), Some(1));
+        mapper.cpu_write(0x6000, 0x55);
+        assert_eq!(mapper.cpu_read(0x6000), Some(1));
+
+        // RAM selected, but not enabled.
+        command(&mut mapper, 0x08, 0x40);
+        assert_eq!(mapper.cpu_read(0x6000), None);
+        mapper.cpu_write(0x6000, 0x55);
+
+        command(&mut mapper, 0x08, 0xC0);
+        assert_eq!(mapper.cpu_read(0x6000), Some(0x00));
+        mapper.cpu_write(0x7FFF, 0xAA);
+        assert_eq!(mapper.cpu_read(0x7FFF), Some(0xAA));
+        assert_eq!(mapper.save_ram()[0x1FFF], 0xAA);
+    }
+
+    #[test]
+    fn irq_counter() {
+        let mut mapper = mapper();
+
+        command(&mut mapper, 0x0E, 0x02);
+        command(&mut mapper, 0x0F, 0x00);
+        command(&mut mapper, 0x0D, 0x81);
+
+        mapper.clock();
+        mapper.clock();
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert!(mapper.irq());
+
+        // Acknowledged by any write to the control register.
+        command(&mut mapper, 0x0D, 0x80);
+        assert!(!mapper.irq());
+        mapper.clock();
+        assert_eq!(mapper.irq_counter, 0xFFFE);
+
+        // Counting stops, but the IRQ can still be enabled.
+        command(&mut mapper, 0x0D, 0x01);
+        mapper.clock();
+        assert_eq!(mapper.irq_counter, 0xFFFE);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn audio_registers() {
+        let mut mapper = mapper();
+        let mut write = |register, data| {
+            mapper.cpu_write(0xC000, register);
+            mapper.cpu_write(0xE000, data);
+        };
+
+        // Channel A's tone on with a period of 1, noise off, full volume.
+        write(0x00, 0x01);
+        write(0x07, 0x3E);
+        write(0x08, 0x0F);
+        // Ignored, as the upper nibble of the select latch is set.
+        write(0x18, 0x00);
+        assert_eq!(mapper.audio.registers[0x08], 0x0F);
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        for _ in 0..16 {
+            mapper.clock();
+        }
+        assert_eq!(mapper.audio_output(), 1.0 / 3.0);
+        for _ in 0..16 {
+            mapper.clock();
+        }
+        assert_eq!(mapper.audio_output(), 0.0);
+    }
+}
@@ -0,0 +1,164 @@
+use super::controller;
+use super::movie::{self, Frame, Movie, MovieError, POWER_CYCLE, SOFT_RESET};
+use super::region::Region;
+use super::zip::Archive;
+
+/// Gamepad buttons in the order Mesen input logs list them.
+const BUTTONS: [u8; 8] = [
+    controller::UP,
+    controller::DOWN,
+    controller::LEFT,
+    controller::RIGHT,
+    controller::START,
+    controller::SELECT,
+    controller::B,
+    controller::A,
+];
+
+/// Reads a Mesen movie, a zip archive with the settings and input log as
+/// text files.
+///
+/// Movies that start from a savestate, or that need anything but gamepads,
+/// are refused.
+pub fn parse(data: &[u8]) -> Result<Movie, MovieError> {
+    let archive = Archive::new(data)?;
+    let mut movie = Movie::default();
+    let mut ports = [true, true];
+
+    if archive.names().any(|name| name == "SaveState.mst") {
+        return Err(MovieError::Unsupported("a savestate to start".to_string()));
+    }
+
+    let settings = archive.read("GameSettings.txt")?;
+    for (number, line) in String::from_utf8_lossy(&settings).lines().enumerate() {
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let value = value.trim();
+
+        match key {
+            "GameFile" => movie.rom_name = value.to_string(),
+            "SHA1" => {
+                movie.rom_sha1 =
+                    Some(movie::parse_digest(value).ok_or(MovieError::Syntax(number + 1))?);
+            }
+            "Region" => {
+                movie.region = match value {
+                    "PAL" => Region::Pal,
+                    "Dendy" => Region::Dendy,
+                    _ => Region::Ntsc,
+                }
+            }
+            "Controller1" | "Controller2" => {
+                let port = (key == "Controller2") as usize;
+                ports[port] = match value {
+                    "StandardController" => true,
+                    "None" => false,
+                    device => return Err(MovieError::Unsupported(format!("the {}", device))),
+                };
+            }
+            "ExpansionDevice" if value != "None" => {
+                return Err(MovieError::Unsupported(format!("the {}", value)));
+            }
+            _ => {}
+        }
+    }
+
+    let input = archive.read("Input.txt")?;
+    for (number, line) in String::from_utf8_lossy(&input).lines().enumerate() {
+        if line.starts_with('|') {
+            let frame = parse_frame(line, ports).ok_or(MovieError::Syntax(number + 1))?;
+            movie.frames.push(frame);
+        }
+    }
+
+    Ok(movie)
+}
+
+/// Reads an input line like `|..|U......A|........`: the reset and power
+/// buttons, then a field per connected controller.
+fn parse_frame(line: &str, ports: [bool; 2]) -> Option<Frame> {
+    let mut fields = line.strip_prefix('|')?.split('|');
+    let mut frame = Frame::default();
+
+    let held = |flag: u8| flag != b'.' && flag != b' ';
+
+    let system = fields.next()?.as_bytes();
+    if system.len() != 2 {
+        return None;
+    }
+    if held(system[0]) {
+        frame.commands |= SOFT_RESET;
+    }
+    if held(system[1]) {
+        frame.commands |= POWER_CYCLE;
+    }
+
+    for (buttons, _) in frame.buttons.iter_mut().zip(ports).filter(|(_, on)| *on) {
+        // Famicom controllers may carry a microphone bit after the buttons.
+        let field = fields.next()?.as_bytes();
+        if field.len() < BUTTONS.len() {
+            return None;
+        }
+
+        for (&flag, button) in field.iter().zip(BUTTONS) {
+            if held(flag) {
+                *buttons |= button;
+            }
+        }
+    }
+
+    Some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip;
+
+    const SETTINGS: &str = "MesenVersion 0.9.9
+MovieFormatVersion 1
+GameFile Test
+SHA1 da39a3ee5e6b4b0d3255bfef95601890afd80709
+Region Dendy
+ConsoleType Nes
+Controller1 StandardController
+Controller2 None
+ExpansionDevice None
+";
+
+    #[test]
+    fn parse_movie() {
+        let input = "|.P|........\n|..|....S...\n|..|U......A\n|R.|........\n";
+        let data = zip::store(&[
+            ("GameSettings.txt", SETTINGS.as_bytes()),
+            ("Input.txt", input.as_bytes()),
+        ]);
+        let movie = parse(&data).unwrap();
+
+        assert_eq!(movie.rom_name, "Test");
+        assert_eq!(movie.region, Region::Dendy);
+        assert_eq!(movie.rom_sha1.unwrap()[..2], [0xDA, 0x39]);
+
+        assert_eq!(movie.frames.len(), 4);
+        assert_eq!(movie.frames[0].commands, POWER_CYCLE);
+        assert_eq!(movie.frames[1].buttons, [controller::START, 0]);
+        assert_eq!(movie.frames[2].buttons, [controller::UP | controller::A, 0]);
+        assert_eq!(movie.frames[3].commands, SOFT_RESET);
+        assert_eq!(movie::import(&data).unwrap(), movie);
+    }
+
+    #[test]
+    fn refused() {
+        let settings = SETTINGS.replace("Controller2 None", "Controller2 Zapper");
+        let data = zip::store(&[
+            ("GameSettings.txt", settings.as_bytes()),
+            ("Input.txt", b""),
+        ]);
+        assert!(matches!(parse(&data), Err(MovieError::Unsupported(_))));
+
+        let data = zip::store(&[
+            ("GameSettings.txt", SETTINGS.as_bytes()),
+            ("Input.txt", b"|..|....\n"),
+        ]);
+        assert_eq!(parse(&data), Err(MovieError::Syntax(1)));
+    }
+}
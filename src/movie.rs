@@ -0,0 +1,369 @@
+use std::fmt;
+
+use super::checksum;
+use super::controller::{Adapter, Device};
+use super::nes::Nes;
+use super::region::Region;
+use super::zip::{Archive, ZipError};
+use super::{bk2, fm2, mmo};
+
+/// Commands a movie can give at the start of a frame, before its input.
+pub const SOFT_RESET: u8 = 0x01;
+pub const POWER_CYCLE: u8 = 0x02;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MovieError {
+    /// The line with this number, counting from 1, could not be read.
+    Syntax(usize),
+    /// The movie needs something that isn't emulated.
+    Unsupported(String),
+    /// The movie was made with a different ROM.
+    ChecksumMismatch,
+    /// The movie's zip container could not be read.
+    Archive(ZipError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Syntax(line) => write!(f, "movie line {} is malformed", line),
+            MovieError::Unsupported(feature) => write!(f, "movie needs {}", feature),
+            MovieError::ChecksumMismatch => write!(f, "movie was recorded with a different ROM"),
+            MovieError::Archive(error) => write!(f, "movie: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<ZipError> for MovieError {
+    fn from(error: ZipError) -> Self {
+        MovieError::Archive(error)
+    }
+}
+
+/// The input for one frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Frame {
+    /// Buttons held on each controller, as in [`Controller::buttons`].
+    ///
+    /// [`Controller::buttons`]: super::controller::Controller::buttons
+    pub buttons: [u8; 2],
+    /// [`SOFT_RESET`] and [`POWER_CYCLE`].
+    pub commands: u8,
+}
+
+/// A recording of the input given to a console, frame by frame, from power
+/// on. Replaying it on the same ROM reproduces the run exactly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    /// MD5 of the ROM's PRG and CHR data, when known. See [`rom_checksum`].
+    pub rom_checksum: Option<[u8; 16]>,
+    /// SHA-1 of the same data, which BizHawk and Mesen record instead.
+    pub rom_sha1: Option<[u8; 20]>,
+    pub rom_name: String,
+    pub region: Region,
+    pub rerecords: u32,
+    pub comments: Vec<String>,
+    pub frames: Vec<Frame>,
+}
+
+impl Movie {
+    /// Checks that the movie was recorded with `rom`, an iNES image. Movies
+    /// that don't record a checksum are accepted.
+    pub fn verify(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let md5_matches = self
+            .rom_checksum
+            .is_none_or(|checksum| checksum == rom_checksum(rom));
+        let sha1_matches = self
+            .rom_sha1
+            .is_none_or(|sha1| sha1 == checksum::sha1(rom_data(rom)));
+
+        if md5_matches && sha1_matches {
+            Ok(())
+        } else {
+            Err(MovieError::ChecksumMismatch)
+        }
+    }
+
+    /// Power cycles `nes` into the clean state the movie starts from, and
+    /// returns a player for the frames.
+    pub fn play(&self, nes: &mut Nes) -> MoviePlayer<'_> {
+        nes.set_region(self.region);
+        nes.power_cycle();
+
+        MoviePlayer {
+            movie: self,
+            frame: 0,
+        }
+    }
+}
+
+/// Reads a movie in any of the formats there is support for: FCEUX `.fm2`,
+/// BizHawk `.bk2` and Mesen `.mmo`, told apart by their contents.
+pub fn import(data: &[u8]) -> Result<Movie, MovieError> {
+    if !data.starts_with(b"PK\x03\x04") {
+        return fm2::parse(&String::from_utf8_lossy(data));
+    }
+
+    if Archive::new(data)?
+        .names()
+        .any(|name| name == bk2::INPUT_LOG)
+    {
+        bk2::parse(data)
+    } else {
+        mmo::parse(data)
+    }
+}
+
+/// The MD5 that movies identify ROMs by, taken over the PRG and CHR data of
+/// an iNES image but not its header or trainer, as FCEUX does.
+pub fn rom_checksum(rom: &[u8]) -> [u8; 16] {
+    checksum::md5(rom_data(rom))
+}
+
+fn rom_data(rom: &[u8]) -> &[u8] {
+    let start = match rom.get(6) {
+        Some(flags) if rom.starts_with(b"NES\x1A") && flags & 0x04 != 0 => 16 + 512,
+        Some(_) if rom.starts_with(b"NES\x1A") => 16,
+        _ => 0,
+    };
+
+    rom.get(start..).unwrap_or_default()
+}
+
+/// Reads a digest written out in hexadecimal.
+pub(crate) fn parse_digest<const N: usize>(text: &str) -> Option<[u8; N]> {
+    let text = text.trim();
+    if text.len() != N * 2 || !text.is_ascii() {
+        return None;
+    }
+
+    let mut digest = [0; N];
+    for (byte, pair) in digest.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+
+    Some(digest)
+}
+
+/// Feeds a movie's input to a console, one frame at a time.
+pub struct MoviePlayer<'a> {
+    movie: &'a Movie,
+    frame: usize,
+}
+
+impl MoviePlayer<'_> {
+    /// The number of frames played so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+
+    /// Carries out the commands and sets the controllers for the next frame.
+    /// Returns false, doing nothing, once the movie is over.
+    pub fn apply(&mut self, nes: &mut Nes) -> bool {
+        let Some(frame) = self.movie.frames.get(self.frame) else {
+            return false;
+        };
+
+        if frame.commands & POWER_CYCLE != 0 {
+            nes.power_cycle();
+        } else if frame.commands & SOFT_RESET != 0 {
+            nes.reset();
+        }
+
+        for (controller, buttons) in nes.bus.controllers.iter_mut().zip(frame.buttons) {
+            controller.buttons = buttons;
+        }
+
+        self.frame += 1;
+        true
+    }
+
+    /// Plays the next frame. Returns false once the movie is over.
+    pub fn run_frame(&mut self, nes: &mut Nes) -> bool {
+        let playing = self.apply(nes);
+        if playing {
+            nes.run_frame();
+        }
+
+        playing
+    }
+}
+
+/// Records the input given to a console into a [`Movie`].
+///
+/// Before each frame runs, call [`MovieRecorder::record`] to log the state of
+/// the controllers. Resets go through the recorder so they are logged too.
+pub struct MovieRecorder {
+    movie: Movie,
+    commands: u8,
+}
+
+impl MovieRecorder {
+    /// Starts a movie of `rom`, power cycling `nes` so that it starts from a
+    /// clean state.
+    pub fn new(nes: &mut Nes, rom: &[u8]) -> Self {
+        nes.power_cycle();
+
+        Self {
+            movie: Movie {
+                rom_checksum: Some(rom_checksum(rom)),
+                region: nes.region(),
+                ..Default::default()
+            },
+            commands: 0,
+        }
+    }
+
+    pub fn reset(&mut self, nes: &mut Nes) {
+        nes.reset();
+        self.commands |= SOFT_RESET;
+    }
+
+    pub fn power_cycle(&mut self, nes: &mut Nes) {
+        nes.power_cycle();
+        self.commands |= POWER_CYCLE;
+    }
+
+    /// Logs the controllers for the frame about to run. Movies only hold
+    /// joypads, so anything else plugged in is refused.
+    pub fn record(&mut self, nes: &Nes) -> Result<(), MovieError> {
+        let controllers = &nes.bus.controllers;
+        if nes.bus.multitap.adapter != Adapter::None {
+            return Err(MovieError::Unsupported("a four player adapter".to_string()));
+        }
+        for (port, controller) in controllers[..2].iter().enumerate() {
+            if !matches!(controller.device, Device::Joypad | Device::None) {
+                return Err(MovieError::Unsupported(format!(
+                    "device {:?} in port {}",
+                    controller.device, port
+                )));
+            }
+        }
+
+        self.movie.frames.push(Frame {
+            buttons: [controllers[0].buttons, controllers[1].buttons],
+            commands: std::mem::take(&mut self.commands),
+        });
+
+        Ok(())
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::fm2;
+    use crate::test_support;
+
+    /// An NROM image that keeps reading the first controller, summing what it
+    /// reads at $02.
+    fn rom() -> Vec<u8> {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x16, 0x40, // STA $4016
+            0xA2, 0x08,       // LDX #$08
+            0xAD, 0x16, 0x40, // LDA $4016
+            0x4A,             // LSR A
+            0x26, 0x01,       // ROL $01
+            0xCA,             // DEX
+            0xD0, 0xF7,       // BNE -9
+            0xA5, 0x01,       // LDA $01
+            0x18,             // CLC
+            0x65, 0x02,       // ADC $02
+            0x85, 0x02,       // STA $02
+            0xE6, 0x03,       // INC $03
+            0x4C, 0x00, 0x80, // JMP $8000
+        ];
+
+        test_support::nrom(&program, &[], &[])
+    }
+
+    fn ram(nes: &mut Nes) -> [u8; 3] {
+        [1, 2, 3].map(|addr| nes.bus.read(addr as u16))
+    }
+
+    #[test]
+    fn replays_in_sync() {
+        let rom = rom();
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+        // Whatever ran before recording is forgotten.
+        nes.bus.controllers[0].buttons = 0xFF;
+        nes.run_frame();
+
+        let mut recorder = MovieRecorder::new(&mut nes, &rom);
+        for frame in 0..40u8 {
+            if frame == 20 {
+                recorder.reset(&mut nes);
+            }
+            nes.bus.controllers[0].buttons = frame.wrapping_mul(37);
+            recorder.record(&nes).unwrap();
+            nes.run_frame();
+        }
+        let recorded = ram(&mut nes);
+
+        let movie = fm2::parse(&fm2::write(&recorder.finish())).unwrap();
+        assert_eq!(movie.frames[20].commands, SOFT_RESET);
+        assert_eq!(movie.verify(&rom), Ok(()));
+
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+        nes.run_frame();
+        let mut player = movie.play(&mut nes);
+        while player.run_frame(&mut nes) {}
+
+        assert_eq!(player.frame(), 40);
+        assert_eq!(ram(&mut nes), recorded);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut rom = rom();
+        let movie = Movie {
+            rom_checksum: Some(rom_checksum(&rom)),
+            ..Default::default()
+        };
+
+        // The header doesn't count.
+        rom[7] = 0x08;
+        assert_eq!(movie.verify(&rom), Ok(()));
+
+        rom[16] ^= 0xFF;
+        assert_eq!(movie.verify(&rom), Err(MovieError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn refuses_other_devices() {
+        let rom = rom();
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+        let mut recorder = MovieRecorder::new(&mut nes, &rom);
+
+        nes.bus.controllers[1].device = Device::Zapper;
+        assert_eq!(
+            recorder.record(&nes),
+            Err(MovieError::Unsupported(
+                "device Zapper in port 1".to_string()
+            ))
+        );
+        nes.bus.controllers[1].device = Device::None;
+        nes.bus.multitap.adapter = Adapter::FourScore;
+        assert!(recorder.record(&nes).is_err());
+        assert!(recorder.movie().frames.is_empty());
+    }
+}
@@ -0,0 +1,640 @@
+use super::bus::Bus;
+use super::cartridge::Cartridge;
+use super::cpu::Cpu;
+use super::png;
+use super::ppu::{HEIGHT, WIDTH};
+use super::region::Region;
+use super::resampler::Resampler;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// The console: a CPU and everything on its bus.
+///
+/// The CPU is the master clock. Every CPU cycle the bus advances the PPU by
+/// three dots (3.2 on PAL) and the APU and cartridge by one cycle, and
+/// interrupts are checked between instructions.
+pub struct Nes {
+    pub cpu: Cpu,
+    pub bus: Bus,
+
+    resampler: Resampler,
+    /// Audio produced since it was last taken, in the range -1.0..=1.0.
+    pub audio: Vec<f32>,
+
+    frame_callbacks: Vec<FrameCallback>,
+    audio_callbacks: Vec<AudioCallback>,
+    /// Audio for the callbacks, handed over once a frame.
+    callback_audio: Vec<f32>,
+    last_frame: u64,
+
+    /// The cartridge's board as it was at power on.
+    power_on_state: Vec<u8>,
+
+    run_ahead: usize,
+    /// The state run ahead from, kept to reuse its allocation.
+    run_ahead_state: StateWriter,
+    run_ahead_instance: Option<Box<Nes>>,
+    /// Whether audio is left unsampled, while running ahead.
+    muted: bool,
+}
+
+/// Called with the console each time the PPU finishes a frame.
+pub type FrameCallback = Box<dyn FnMut(&Nes)>;
+/// Called with the audio of each frame, after the frame callbacks.
+pub type AudioCallback = Box<dyn FnMut(&[f32])>;
+
+impl Nes {
+    /// Powers on a console with `cartridge` inserted, of the region the
+    /// cartridge's header asks for.
+    pub fn new(cartridge: Cartridge) -> Self {
+        let mut nes = Self {
+            cpu: Cpu::default(),
+            bus: Bus::default(),
+
+            resampler: Resampler::new(Region::Ntsc.cpu_clock(), 44100),
+            audio: Vec::new(),
+
+            frame_callbacks: Vec::new(),
+            audio_callbacks: Vec::new(),
+            callback_audio: Vec::new(),
+            last_frame: 0,
+
+            power_on_state: Vec::new(),
+
+            run_ahead: 0,
+            run_ahead_state: StateWriter::default(),
+            run_ahead_instance: None,
+            muted: false,
+        };
+
+        let mut state = StateWriter::default();
+        cartridge.mapper.save_state(&mut state);
+        nes.power_on_state = state.into_inner();
+
+        nes.set_region(cartridge.header.region);
+        nes.bus.insert(cartridge);
+        nes.cpu.pc = nes.reset_vector();
+
+        nes
+    }
+
+    fn reset_vector(&mut self) -> u16 {
+        let lo = self.bus.read(0xFFFCu16) as u16;
+        let hi = self.bus.read(0xFFFDu16) as u16;
+
+        (hi << 8) | lo
+    }
+
+    /// Presses the reset button.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.cpu.cycles = 0;
+        self.cpu.pc = self.reset_vector();
+        self.bus.ppu.reset();
+        self.bus.apu.reset();
+        self.bus.stall = 0;
+    }
+
+    /// Turns the console off and on again. Everything goes back to its
+    /// power on state, the cartridge's board and RAM included, which gives
+    /// movies and tests the same clean start every time.
+    pub fn power_cycle(&mut self) {
+        let region = self.region();
+        let cartridge = self.bus.cartridge.take();
+        // What is plugged in stays plugged in, and so do the cheats.
+        let adapter = self.bus.multitap.adapter;
+        let devices = self
+            .bus
+            .controllers
+            .each_ref()
+            .map(|controller| controller.device);
+        let palette = std::mem::take(&mut self.bus.palette);
+        let cheats = std::mem::take(&mut self.bus.cheats);
+
+        self.cpu = Cpu::default();
+        self.bus = Bus::default();
+        self.bus.set_region(region);
+        self.bus.multitap.adapter = adapter;
+        for (controller, device) in self.bus.controllers.iter_mut().zip(devices) {
+            controller.device = device;
+        }
+        self.bus.palette = palette;
+        self.bus.cheats = cheats;
+        self.last_frame = 0;
+
+        if let Some(mut cartridge) = cartridge {
+            cartridge
+                .mapper
+                .load_state(&mut StateReader::new(&self.power_on_state))
+                .expect("the board's power on state is its own");
+            self.bus.insert(cartridge);
+        }
+        self.cpu.pc = self.reset_vector();
+    }
+
+    pub fn region(&self) -> Region {
+        self.bus.region()
+    }
+
+    /// Switches the console to `region`, e.g. to run a game whose iNES
+    /// header doesn't say it is PAL. Best done before the first frame.
+    pub fn set_region(&mut self, region: Region) {
+        self.bus.set_region(region);
+        self.resampler.set_clock_rate(region.cpu_clock());
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    /// Sets the rate at which [`Nes::audio`] is filled.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_sample_rate(sample_rate);
+    }
+
+    /// Hook for dynamic rate control: produces `ratio` times as much audio,
+    /// to keep up with a host whose audio clock runs off the video clock.
+    /// See [`resampler::rate_adjustment`](super::resampler::rate_adjustment).
+    pub fn set_audio_rate_adjustment(&mut self, ratio: f64) {
+        self.resampler.set_rate_adjustment(ratio);
+    }
+
+    pub fn run_ahead(&self) -> usize {
+        self.run_ahead
+    }
+
+    /// Runs `frames` ahead of the console to cut input latency: after each
+    /// frame [`Nes::run_frame`] saves the state, runs that many more with the
+    /// same input, then loads the state back. The picture left in the PPU is
+    /// the one from ahead, while the audio and the frame callbacks follow the
+    /// console itself.
+    ///
+    /// Games that react to input a frame or more after reading it gain
+    /// nothing from going further ahead than that, and anything more makes
+    /// the picture skip.
+    pub fn set_run_ahead(&mut self, frames: usize) {
+        self.run_ahead = frames;
+    }
+
+    /// Runs ahead on `instance`, a second console with the same cartridge,
+    /// instead of on this one, which then never has a state loaded. Nothing
+    /// it produces is rewound, so its audio plays on without a seam.
+    pub fn set_run_ahead_instance(&mut self, instance: Option<Nes>) {
+        self.run_ahead_instance = instance.map(|mut instance| {
+            instance.clear_callbacks();
+            instance.muted = true;
+            Box::new(instance)
+        });
+    }
+
+    pub fn on_frame(&mut self, callback: impl FnMut(&Nes) + 'static) {
+        self.frame_callbacks.push(Box::new(callback));
+    }
+
+    pub fn on_audio(&mut self, callback: impl FnMut(&[f32]) + 'static) {
+        self.audio_callbacks.push(Box::new(callback));
+    }
+
+    pub fn clear_callbacks(&mut self) {
+        self.frame_callbacks.clear();
+        self.audio_callbacks.clear();
+        self.callback_audio.clear();
+    }
+
+    /// Advances the console by one CPU cycle.
+    pub fn clock(&mut self) {
+        if self.bus.stall > 0 {
+            self.bus.stall -= 1;
+        } else {
+            if self.cpu.complete() {
+                if self.bus.ppu.take_nmi() {
+                    self.cpu.nmi(&mut self.bus);
+                } else if self.bus.irq() {
+                    self.cpu.irq(&mut self.bus);
+                }
+            }
+
+            self.cpu.clock(&mut self.bus);
+        }
+
+        self.bus.clock();
+        self.sample();
+
+        if self.bus.ppu.frame_count != self.last_frame {
+            self.last_frame = self.bus.ppu.frame_count;
+            self.bus.freeze_cheats();
+            self.run_callbacks();
+        }
+    }
+
+    fn run_callbacks(&mut self) {
+        // The callbacks are moved out while they run, since they see the
+        // whole console.
+        let mut callbacks = std::mem::take(&mut self.frame_callbacks);
+        for callback in &mut callbacks {
+            callback(self);
+        }
+        callbacks.append(&mut self.frame_callbacks);
+        self.frame_callbacks = callbacks;
+
+        self.read_audio();
+        for callback in &mut self.audio_callbacks {
+            callback(&self.callback_audio);
+        }
+        self.callback_audio.clear();
+    }
+
+    fn sample(&mut self) {
+        if self.muted {
+            return;
+        }
+        if self.resampler.clock(self.bus.audio_output()) {
+            self.read_audio();
+        }
+    }
+
+    /// Moves the samples the resampler has finished into [`Nes::audio`].
+    fn read_audio(&mut self) {
+        let start = self.audio.len();
+        self.resampler.read(&mut self.audio);
+
+        if !self.audio_callbacks.is_empty() {
+            self.callback_audio.extend_from_slice(&self.audio[start..]);
+        }
+    }
+
+    /// Runs until the current instruction has completed.
+    pub fn step(&mut self) {
+        self.clock();
+        while !self.cpu.complete() || self.bus.stall > 0 {
+            self.clock();
+        }
+    }
+
+    /// Runs until the PPU enters vertical blank, then runs ahead if asked
+    /// to by [`Nes::set_run_ahead`].
+    pub fn run_frame(&mut self) {
+        self.emulate_frame();
+
+        if self.run_ahead > 0 {
+            self.run_ahead_frames();
+        }
+    }
+
+    fn emulate_frame(&mut self) {
+        self.bus.ppu.frame_complete = false;
+        while !self.bus.ppu.frame_complete {
+            self.clock();
+        }
+    }
+
+    fn run_ahead_frames(&mut self) {
+        // Taken out while the console saves into it. The buffer keeps its
+        // allocation from frame to frame, and loading borrows from it, so
+        // neither allocates once the first frame is done.
+        let mut state = std::mem::take(&mut self.run_ahead_state);
+        state.clear();
+        self.save_state(&mut state);
+        let mut reader = StateReader::new(state.data());
+
+        if let Some(instance) = &mut self.run_ahead_instance {
+            instance
+                .load_state(&mut reader)
+                .expect("the run-ahead instance has the same cartridge");
+            for _ in 0..self.run_ahead {
+                instance.emulate_frame();
+            }
+            self.bus.ppu.frame.copy_from_slice(&instance.bus.ppu.frame);
+        } else {
+            let frame_callbacks = std::mem::take(&mut self.frame_callbacks);
+            let audio_callbacks = std::mem::take(&mut self.audio_callbacks);
+            self.muted = true;
+
+            for _ in 0..self.run_ahead {
+                self.emulate_frame();
+            }
+
+            self.muted = false;
+            self.frame_callbacks = frame_callbacks;
+            self.audio_callbacks = audio_callbacks;
+            self.load_state(&mut reader)
+                .expect("the console's own state loads");
+        }
+
+        self.run_ahead_state = state;
+    }
+
+    /// The last frame, as pixels of [`Palette::rgb`] input.
+    ///
+    /// [`Palette::rgb`]: super::palette::Palette::rgb
+    pub fn frame(&self) -> &[u16] {
+        &self.bus.ppu.frame
+    }
+
+    /// The last frame converted to 8 bit RGB, row by row.
+    pub fn frame_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for &pixel in self.frame() {
+            rgb.extend(self.bus.palette.rgb(pixel));
+        }
+
+        rgb
+    }
+
+    /// The last frame converted to 8 bit RGBA, row by row.
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(WIDTH * HEIGHT * 4);
+        for &pixel in self.frame() {
+            rgba.extend(self.bus.palette.rgb(pixel));
+            rgba.push(0xFF);
+        }
+
+        rgba
+    }
+
+    /// The last frame as it left the PPU, two bytes per pixel: the color
+    /// index with the emphasis bits above it, little endian.
+    pub fn frame_indexed(&self) -> Vec<u8> {
+        self.frame()
+            .iter()
+            .flat_map(|pixel| pixel.to_le_bytes())
+            .collect()
+    }
+
+    /// The last frame as a PNG image. Identical frames give identical files.
+    pub fn screenshot(&self) -> Vec<u8> {
+        png::encode(WIDTH as u32, HEIGHT as u32, &self.frame_rgb())
+    }
+
+    /// Takes the audio produced so far.
+    pub fn take_audio(&mut self) -> Vec<f32> {
+        self.read_audio();
+        std::mem::take(&mut self.audio)
+    }
+}
+
+/// The whole console, for rewinding and the like. The palette, the audio
+/// settings and the callbacks are not part of the state.
+impl Savestate for Nes {
+    fn save_state(&self, state: &mut StateWriter) {
+        self.cpu.save_state(state);
+        self.bus.save_state(state);
+        state.u64(self.last_frame);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.cpu.load_state(state)?;
+        self.bus.load_state(state)?;
+        self.last_frame = state.u64()?;
+        self.resampler.set_clock_rate(self.region().cpu_clock());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cheats::Cheat;
+    use crate::controller;
+    use crate::test_support::nrom;
+
+    #[test]
+    fn vblank_nmi() {
+        // Enable NMIs and spin, counting NMIs at $0000.
+        let program = [0xA9, 0x80, 0x8D, 0x00, 0x20, 0x4C, 0x05, 0x80];
+        let nmi = [0xE6, 0x00, 0x40];
+        let mut nes = Nes::new(Cartridge::from_bytes(&nrom(&program, &nmi, &[])).unwrap());
+
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        for _ in 0..10 {
+            nes.step();
+        }
+
+        assert_eq!(nes.bus.ppu.frame_count, 3);
+        assert_eq!(nes.bus.read(0x0000u16), 3);
+    }
+
+    #[test]
+    fn audio_rate() {
+        let mut nes = Nes::new(Cartridge::from_bytes(&nrom(&[], &[], &[])).unwrap());
+        nes.set_sample_rate(48000);
+
+        for _ in 0..Region::Ntsc.cpu_clock().ceil() as usize {
+            nes.clock();
+        }
+
+        assert!(nes.take_audio().len().abs_diff(48000) <= 1);
+    }
+
+    /// CPU cycles taken by ten frames with rendering off, which skips no
+    /// dots.
+    fn frame_cycles(region: Region) -> u64 {
+        let mut nes = Nes::new(Cartridge::from_bytes(&nrom(&[], &[], &[])).unwrap());
+        nes.set_region(region);
+
+        nes.run_frame();
+        let start = nes.bus.cycles;
+        for _ in 0..10 {
+            nes.run_frame();
+        }
+
+        nes.bus.cycles - start
+    }
+
+    #[test]
+    fn region_timing() {
+        // 262 * 341 / 3, 312 * 341 / 3.2 and 312 * 341 / 3 cycles a frame,
+        // give or take the dots left over in the last cycle.
+        assert_eq!(frame_cycles(Region::Ntsc), 297806);
+        assert_eq!(frame_cycles(Region::Pal), 332475);
+        assert_eq!(frame_cycles(Region::Dendy), 354640);
+    }
+
+    /// A console that plays a tone and shows a backdrop color, both of
+    /// which change every frame.
+    fn changing_nes() -> Nes {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x01, 0x8D, 0x15, 0x40, // Enable the first pulse channel
+            0xA9, 0xBF, 0x8D, 0x00, 0x40,
+            0xA9, 0x02, 0x8D, 0x03, 0x40,
+            0xA9, 0x80, 0x8D, 0x00, 0x20, // Enable NMIs
+            0x4C, 0x14, 0x80,             // JMP $8014
+        ];
+        #[rustfmt::skip]
+        let nmi = [
+            0xE6, 0x00,                   // INC $00
+            0xA5, 0x00, 0x8D, 0x02, 0x40, // The counter sets the pitch
+            0xA9, 0x3F, 0x8D, 0x06, 0x20, // and the backdrop color
+            0xA9, 0x00, 0x8D, 0x06, 0x20,
+            0xA5, 0x00, 0x29, 0x3F, 0x8D, 0x07, 0x20,
+            0xA9, 0x00, 0x8D, 0x06, 0x20, 0x8D, 0x06, 0x20,
+            0x40,                         // RTI
+        ];
+
+        Nes::new(Cartridge::from_bytes(&nrom(&program, &nmi, &[])).unwrap())
+    }
+
+    fn state(nes: &Nes) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        nes.save_state(&mut state);
+        state.into_inner()
+    }
+
+    #[test]
+    fn cheats() {
+        let mut nes = changing_nes();
+        nes.bus.cheats.add(Cheat::parse("0010:42").unwrap());
+        nes.bus.cheats.add(Cheat::parse("8000?A9:EA").unwrap());
+
+        assert_eq!(nes.bus.read(0x10u16), 0x00);
+        nes.run_frame();
+        assert_eq!(nes.bus.read(0x10u16), 0x42);
+        assert_eq!(nes.bus.read(0x8000u16), 0xEA);
+
+        nes.bus.cheats.set_enabled(1, false);
+        assert_eq!(nes.bus.read(0x8000u16), 0xA9);
+    }
+
+    #[test]
+    fn freezes_leave_the_bus_alone() {
+        let mut nes = changing_nes();
+        nes.bus.cheats.add(Cheat::parse("0810:42").unwrap());
+        nes.bus.write(0x4000u16, 0xFA);
+
+        nes.bus.freeze_cheats();
+        assert_eq!(nes.bus.open_bus(), 0xFA);
+        assert_eq!(nes.bus.read(0x0010u16), 0x42);
+    }
+
+    #[test]
+    fn power_cycle_keeps_cheats_and_devices() {
+        let mut nes = changing_nes();
+        nes.bus.cheats.add(Cheat::parse("0010:42").unwrap());
+        let cheats = nes.bus.cheats.cheats().to_vec();
+        nes.bus.controllers[0].device = controller::Device::Vaus;
+        nes.bus.controllers[1].device = controller::Device::Zapper;
+
+        nes.power_cycle();
+        assert_eq!(nes.bus.cheats.cheats(), cheats);
+        assert_eq!(nes.bus.controllers[0].device, controller::Device::Vaus);
+        assert_eq!(nes.bus.controllers[1].device, controller::Device::Zapper);
+        nes.run_frame();
+        assert_eq!(nes.bus.read(0x10u16), 0x42);
+    }
+
+    #[test]
+    fn open_bus() {
+        let mut nes = changing_nes();
+        let bus = &mut nes.bus;
+
+        bus.write(0x4000u16, 0xFA);
+        assert_eq!(bus.read(0x4000u16), 0xFA);
+        assert_eq!(bus.read(0x5000u16), 0xFA);
+        // Only bit 0 carries a standard controller's buttons.
+        bus.controllers[0].buttons = controller::A;
+        bus.write(0x4016u16, 0x01);
+        bus.write(0x4016u16, 0x00);
+        bus.write(0x4000u16, 0xFA);
+        assert_eq!(bus.read(0x4016u16), 0xE1);
+        assert_eq!(bus.read(0x4017u16), 0xE0);
+        // Reading the APU status leaves the bus alone.
+        assert_eq!(bus.read(0x4015u16) & 0x20, 0x20);
+        assert_eq!(bus.read(0x5000u16), 0xE0);
+
+        bus.set_region(Region::Dendy);
+        bus.write(0x4016u16, 0xFE);
+        assert_eq!(bus.read(0x4016u16), 0xF8);
+    }
+
+    /// Starts a DMC sample, which fetches its first byte on the next cycle.
+    fn start_sample(bus: &mut Bus) {
+        bus.write(0x4012u16, 0x00);
+        bus.write(0x4013u16, 0x01);
+        bus.write(0x4015u16, 0x10);
+    }
+
+    #[test]
+    fn dmc_dma() {
+        for region in [Region::Ntsc, Region::Pal] {
+            let mut nes = changing_nes();
+            let bus = &mut nes.bus;
+            bus.set_region(region);
+            bus.controllers[0].buttons = controller::A | controller::SELECT;
+            bus.write(0x4016u16, 0x01);
+            bus.write(0x4016u16, 0x00);
+
+            start_sample(bus);
+            assert_eq!(bus.read(0x4016u16) & 0x01, 1);
+            bus.clock();
+            assert_eq!(bus.stall, 4);
+
+            // The NTSC CPU's repeated read skips B, reading Select next.
+            let select = bus.read(0x4016u16) & 0x01 == 1;
+            assert_eq!(select, region == Region::Ntsc);
+        }
+
+        // The DMA waits out a write.
+        let mut nes = changing_nes();
+        start_sample(&mut nes.bus);
+        nes.bus.clock();
+        assert_eq!(nes.bus.stall, 3);
+
+        // And takes two cycles during OAM DMA.
+        let mut nes = changing_nes();
+        nes.bus.write(0x4014u16, 0x02);
+        let oam_dma = nes.bus.stall;
+        start_sample(&mut nes.bus);
+        nes.bus.clock();
+        assert_eq!(nes.bus.stall, oam_dma + 2);
+    }
+
+    #[test]
+    fn run_ahead() {
+        let mut plain = changing_nes();
+        let mut frames = Vec::new();
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            plain.run_frame();
+            frames.push(plain.frame().to_vec());
+            states.push(state(&plain));
+        }
+        assert_ne!(frames[3], frames[4]);
+
+        let mut single = changing_nes();
+        single.set_run_ahead(2);
+        let mut second = changing_nes();
+        second.set_run_ahead(2);
+        second.set_run_ahead_instance(Some(changing_nes()));
+
+        let mut buffer = std::ptr::null();
+        for frame in 0..8 {
+            single.run_frame();
+            second.run_frame();
+
+            for nes in [&single, &second] {
+                assert!(nes.frame() == frames[frame + 2], "frame {}", frame);
+                assert!(state(nes) == states[frame], "frame {}", frame);
+            }
+
+            // The state is saved into the same buffer every frame.
+            if frame > 0 {
+                assert_eq!(single.run_ahead_state.data().as_ptr(), buffer);
+            }
+            buffer = single.run_ahead_state.data().as_ptr();
+        }
+
+        // Running ahead leaves no trace in the audio.
+        let audio = plain.take_audio();
+        let mut single_audio = single.take_audio();
+        let mut second_audio = second.take_audio();
+        assert!(audio.iter().any(|&sample| sample.abs() > 0.01));
+        single_audio.truncate(audio.len());
+        second_audio.truncate(audio.len());
+        assert!(single_audio[..] == audio[..single_audio.len()]);
+        assert!(second_audio[..] == audio[..second_audio.len()]);
+    }
+}
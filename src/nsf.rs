@@ -0,0 +1,478 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use super::bus::Bus;
+use super::cartridge::{Cartridge, Header};
+use super::cpu::Cpu;
+use super::mapper::NsfMapper;
+use super::region::Region;
+use super::resampler::Resampler;
+use super::wav;
+
+// Expansion sound chips, as flagged in the header.
+pub const VRC6: u8 = 1 << 0;
+pub const VRC7: u8 = 1 << 1;
+pub const FDS: u8 = 1 << 2;
+pub const MMC5: u8 = 1 << 3;
+pub const NAMCO_163: u8 = 1 << 4;
+pub const SUNSOFT_5B: u8 = 1 << 5;
+
+/// Expansion chips the player can produce sound for.
+pub const SUPPORTED_CHIPS: u8 = FDS | NAMCO_163 | SUNSOFT_5B;
+
+/// Where the driver returns to when INIT or PLAY finishes. Nothing is mapped
+/// there, and the CPU is never allowed to fetch from it.
+const RETURN_ADDRESS: u16 = 0x4100;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum NsfError {
+    /// The file starts with neither the NSF nor the NSFe magic.
+    InvalidHeader,
+    /// The file ends in the middle of the header or a chunk.
+    Truncated,
+    /// An NSFe chunk that must be understood to play the file is unknown.
+    UnsupportedChunk([u8; 4]),
+    /// A required NSFe chunk is absent.
+    MissingChunk(&'static str),
+}
+
+impl fmt::Display for NsfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NsfError::InvalidHeader => write!(f, "not an NSF or NSFe file"),
+            NsfError::Truncated => write!(f, "file is truncated"),
+            NsfError::UnsupportedChunk(id) => {
+                write!(f, "unsupported chunk {}", String::from_utf8_lossy(id))
+            }
+            NsfError::MissingChunk(id) => write!(f, "missing {} chunk", id),
+        }
+    }
+}
+
+impl std::error::Error for NsfError {}
+
+/// A tune in the NSF or NSFe format.
+#[derive(Debug, Clone, Default)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+
+    pub songs: u8,
+    /// Zero based.
+    pub starting_song: u8,
+
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+
+    /// Microseconds between calls to PLAY.
+    pub ntsc_speed: u16,
+    pub pal_speed: u16,
+    pub pal: bool,
+    pub dual_region: bool,
+
+    pub banks: [u8; 8],
+    pub chips: u8,
+    pub data: Vec<u8>,
+
+    /// Per track names and lengths in milliseconds, only present in NSFe.
+    pub track_labels: Vec<String>,
+    pub track_times: Vec<Option<u32>>,
+}
+
+impl Nsf {
+    pub fn parse(data: &[u8]) -> Result<Self, NsfError> {
+        if data.starts_with(b"NESM\x1A") {
+            Self::parse_nsf(data)
+        } else if data.starts_with(b"NSFE") {
+            Self::parse_nsfe(data)
+        } else {
+            Err(NsfError::InvalidHeader)
+        }
+    }
+
+    fn parse_nsf(data: &[u8]) -> Result<Self, NsfError> {
+        if data.len() < 0x80 {
+            return Err(NsfError::Truncated);
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+
+        // NSF2 may give the length of the program, with metadata following it.
+        let length = u32::from_le_bytes([data[0x7D], data[0x7E], data[0x7F], 0]) as usize;
+        let end = if data[0x05] >= 2 && length > 0 {
+            (0x80 + length).min(data.len())
+        } else {
+            data.len()
+        };
+
+        let mut banks = [0; 8];
+        banks.copy_from_slice(&data[0x70..0x78]);
+
+        Ok(Self {
+            title: string(&data[0x0E..0x2E]),
+            artist: string(&data[0x2E..0x4E]),
+            copyright: string(&data[0x4E..0x6E]),
+
+            songs: data[0x06],
+            starting_song: data[0x07].saturating_sub(1),
+
+            load_address: word(0x08),
+            init_address: word(0x0A),
+            play_address: word(0x0C),
+
+            ntsc_speed: word(0x6E),
+            pal_speed: word(0x78),
+            pal: data[0x7A] & 0x01 != 0,
+            dual_region: data[0x7A] & 0x02 != 0,
+
+            banks,
+            chips: data[0x7B],
+            data: data[0x80..end].to_vec(),
+
+            track_labels: Vec::new(),
+            track_times: Vec::new(),
+        })
+    }
+
+    /// NSFe files are a list of chunks, each a length, a four letter id and
+    /// the data. Chunks with an uppercase first letter are required to play
+    /// the file correctly and may not be skipped.
+    fn parse_nsfe(data: &[u8]) -> Result<Self, NsfError> {
+        let mut nsf = Self {
+            ntsc_speed: 16639,
+            pal_speed: 19997,
+            ..Self::default()
+        };
+        let mut info = false;
+        let mut position = 4;
+
+        loop {
+            let header = data
+                .get(position..position + 8)
+                .ok_or(NsfError::Truncated)?;
+            let length = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+            let id: [u8; 4] = header[4..8].try_into().unwrap();
+
+            position += 8;
+            let chunk = data
+                .get(position..position + length)
+                .ok_or(NsfError::Truncated)?;
+            position += length;
+
+            let byte = |offset: usize| chunk.get(offset).copied();
+            let word = |offset: usize| Some(u16::from_le_bytes([byte(offset)?, byte(offset + 1)?]));
+
+            match &id {
+                b"INFO" => {
+                    nsf.load_address = word(0).ok_or(NsfError::Truncated)?;
+                    nsf.init_address = word(2).ok_or(NsfError::Truncated)?;
+                    nsf.play_address = word(4).ok_or(NsfError::Truncated)?;
+
+                    let region = byte(6).unwrap_or(0);
+                    nsf.pal = region & 0x01 != 0;
+                    nsf.dual_region = region & 0x02 != 0;
+                    nsf.chips = byte(7).unwrap_or(0);
+                    nsf.songs = byte(8).unwrap_or(1);
+                    nsf.starting_song = byte(9).unwrap_or(0);
+
+                    info = true;
+                }
+                b"DATA" => nsf.data = chunk.to_vec(),
+                b"BANK" => {
+                    for (bank, value) in nsf.banks.iter_mut().zip(chunk) {
+                        *bank = *value;
+                    }
+                }
+                b"RATE" => {
+                    nsf.ntsc_speed = word(0).unwrap_or(nsf.ntsc_speed);
+                    nsf.pal_speed = word(2).unwrap_or(nsf.pal_speed);
+                }
+                b"auth" => {
+                    let mut fields = chunk.split(|&byte| byte == 0).map(string);
+                    nsf.title = fields.next().unwrap_or_default();
+                    nsf.artist = fields.next().unwrap_or_default();
+                    nsf.copyright = fields.next().unwrap_or_default();
+                }
+                b"tlbl" => {
+                    nsf.track_labels = chunk
+                        .split(|&byte| byte == 0)
+                        .map(string)
+                        .take(nsf.songs as usize)
+                        .collect();
+                }
+                b"time" => {
+                    nsf.track_times = chunk
+                        .chunks_exact(4)
+                        .map(|time| {
+                            u32::try_from(i32::from_le_bytes(time.try_into().unwrap())).ok()
+                        })
+                        .collect();
+                }
+                b"NEND" => break,
+                _ if id[0].is_ascii_uppercase() => return Err(NsfError::UnsupportedChunk(id)),
+                _ => {}
+            }
+        }
+
+        if !info {
+            return Err(NsfError::MissingChunk("INFO"));
+        }
+        if nsf.data.is_empty() {
+            return Err(NsfError::MissingChunk("DATA"));
+        }
+
+        Ok(nsf)
+    }
+
+    /// Tunes without any initial bank set are laid out linearly from their
+    /// load address.
+    pub fn bankswitched(&self) -> bool {
+        self.banks.iter().any(|&bank| bank != 0)
+    }
+}
+
+fn string(data: &[u8]) -> String {
+    let end = data
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(data.len());
+
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Plays an NSF without any video: a small driver calls INIT for the selected
+/// track and then PLAY at the rate the file asks for, while the APU and the
+/// expansion sound chips are sampled into PCM.
+pub struct NsfPlayer {
+    pub nsf: Nsf,
+    cpu: Cpu,
+    bus: Bus,
+    track: u8,
+
+    region: Region,
+    clock_rate: f64,
+    play_period: f64,
+    play_timer: f64,
+    resampler: Resampler,
+    /// Samples rendered past the end of the last `render` call.
+    pending: Vec<f32>,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf) -> Self {
+        let (region, speed) = if nsf.pal && !nsf.dual_region {
+            (Region::Pal, nsf.pal_speed)
+        } else {
+            (Region::Ntsc, nsf.ntsc_speed)
+        };
+        let clock_rate = region.cpu_clock();
+
+        let mut player = Self {
+            track: nsf.starting_song,
+            nsf,
+            cpu: Cpu::default(),
+            bus: Bus::default(),
+
+            region,
+            clock_rate,
+            play_period: clock_rate * speed.max(1) as f64 / 1_000_000.0,
+            play_timer: 0.0,
+            resampler: Resampler::new(clock_rate, 44100),
+            pending: Vec::new(),
+        };
+
+        player.select_track(player.track);
+
+        player
+    }
+
+    pub fn track(&self) -> u8 {
+        self.track
+    }
+
+    /// Powers the machine on with a clean state and runs INIT for `track`,
+    /// which is zero based.
+    pub fn select_track(&mut self, track: u8) {
+        self.track = track.min(self.nsf.songs.saturating_sub(1));
+
+        self.cpu = Cpu::default();
+        self.bus = Bus::default();
+        self.bus.set_region(self.region);
+        self.bus.insert(Cartridge {
+            header: Header::default(),
+            mapper: Box::new(NsfMapper::new(&self.nsf)),
+            corrected: Vec::new(),
+        });
+
+        for addr in 0x4000..=0x4013u16 {
+            self.bus.write(addr, 0x00);
+        }
+        self.bus.write(0x4015u16, 0x00);
+        self.bus.write(0x4015u16, 0x0F);
+        self.bus.write(0x4017u16, 0x40);
+
+        self.cpu.a = self.track;
+        self.cpu.x = (self.region == Region::Pal) as u8;
+        self.cpu.sp = 0xFF;
+        self.call(self.nsf.init_address);
+
+        // INIT is given up to a second to return before playback starts.
+        for _ in 0..self.clock_rate as usize {
+            if self.idle() {
+                break;
+            }
+            self.clock();
+        }
+
+        self.play_timer = 0.0;
+        self.resampler = Resampler::new(self.clock_rate, self.resampler.sample_rate());
+        self.pending.clear();
+    }
+
+    fn idle(&self) -> bool {
+        self.cpu.complete() && self.cpu.pc == RETURN_ADDRESS
+    }
+
+    /// Sets up the stack as if `addr` was called with JSR from the driver.
+    fn call(&mut self, addr: u16) {
+        let ret = RETURN_ADDRESS - 1;
+
+        self.bus
+            .write(0x0100 + self.cpu.sp as u16, (ret >> 8) as u8);
+        self.cpu.sp = self.cpu.sp.wrapping_sub(1);
+        self.bus.write(0x0100 + self.cpu.sp as u16, ret as u8);
+        self.cpu.sp = self.cpu.sp.wrapping_sub(1);
+
+        self.cpu.pc = addr;
+    }
+
+    fn clock(&mut self) {
+        if !self.idle() {
+            self.cpu.clock(&mut self.bus);
+        }
+
+        self.bus.clock();
+    }
+
+    /// Runs the machine for one CPU cycle, calling PLAY when it is due. A
+    /// PLAY routine that overruns its period skips the next call.
+    fn step(&mut self) {
+        self.play_timer -= 1.0;
+        if self.play_timer <= 0.0 {
+            self.play_timer += self.play_period;
+
+            if self.idle() {
+                self.call(self.nsf.play_address);
+            }
+        }
+
+        self.clock();
+    }
+
+    /// Renders `seconds` of mono audio at `sample_rate`.
+    pub fn render(&mut self, seconds: f64, sample_rate: u32) -> Vec<i16> {
+        let count = (seconds * sample_rate as f64) as usize;
+        if self.resampler.sample_rate() != sample_rate {
+            self.resampler.set_sample_rate(sample_rate);
+            self.pending.clear();
+        }
+
+        let mut levels = std::mem::take(&mut self.pending);
+        while levels.len() < count {
+            self.step();
+            if self.resampler.clock(self.bus.audio_output()) {
+                self.resampler.read(&mut levels);
+            }
+        }
+        self.pending = levels.split_off(count);
+
+        levels.into_iter().map(wav::pcm).collect()
+    }
+
+    /// Renders `seconds` of audio into a WAV file at `path`.
+    pub fn write_wav<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        seconds: f64,
+        sample_rate: u32,
+    ) -> io::Result<()> {
+        let samples = self.render(seconds, sample_rate);
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        wav::write(&mut writer, sample_rate, &samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nsf(program: &[u8]) -> Vec<u8> {
+        let mut data = b"NESM\x1A\x01\x03\x02".to_vec();
+        data.extend([0x00, 0x80, 0x00, 0x80, 0x04, 0x80]);
+        data.extend(b"Title");
+        data.resize(0x6E, 0);
+        data.extend(16639u16.to_le_bytes());
+        data.resize(0x80, 0);
+        data.extend(program);
+
+        data
+    }
+
+    #[test]
+    fn header() {
+        let nsf = Nsf::parse(&nsf(&[])).unwrap();
+
+        assert_eq!(nsf.title, "Title");
+        assert_eq!(nsf.songs, 3);
+        assert_eq!(nsf.starting_song, 1);
+        assert_eq!(nsf.init_address, 0x8000);
+        assert_eq!(nsf.play_address, 0x8004);
+        assert!(!nsf.bankswitched());
+    }
+
+    #[test]
+    fn nsfe() {
+        let mut data = b"NSFE".to_vec();
+        let mut chunk = |id: &[u8], body: &[u8]| {
+            data.extend((body.len() as u32).to_le_bytes());
+            data.extend(id);
+            data.extend(body);
+        };
+
+        chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x04, 0x80, 0x00, SUNSOFT_5B, 2, 0],
+        );
+        chunk(b"DATA", &[0x60]);
+        chunk(b"auth", b"Song\0Artist\0\0");
+        chunk(b"time", &[0xE8, 0x03, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]);
+        chunk(b"NEND", &[]);
+
+        let nsf = Nsf::parse(&data).unwrap();
+        assert_eq!(nsf.artist, "Artist");
+        assert_eq!(nsf.chips, SUNSOFT_5B);
+        assert_eq!(nsf.track_times, vec![Some(1000), None]);
+
+        data.truncate(4);
+        assert_eq!(Nsf::parse(&data).unwrap_err(), NsfError::Truncated);
+    }
+
+    #[test]
+    fn driver_calls_init_and_play() {
+        // INIT stores the track number, PLAY counts its calls.
+        let program = [0x8D, 0x00, 0x02, 0x60, 0xEE, 0x01, 0x02, 0x60];
+        let mut player = NsfPlayer::new(Nsf::parse(&nsf(&program)).unwrap());
+
+        player.select_track(2);
+        assert_eq!(player.bus.read(0x0200u16), 2);
+
+        // The first call is made right away, then one every 16639 us.
+        player.render(0.5, 44100);
+        assert_eq!(player.bus.read(0x0201u16), 31);
+    }
+}
@@ -0,0 +1,266 @@
+use std::f32::consts::PI;
+
+use super::ppu::{self, Ppu};
+
+/// Width of filtered frames. Every dot is decoded into two columns, which
+/// leaves room for the color fringes between them.
+pub const WIDTH: usize = ppu::WIDTH * 2;
+pub const HEIGHT: usize = ppu::HEIGHT;
+
+/// Composite samples per dot. The PPU generates its signal at twice the
+/// master clock, so a dot spans 8 of the 12 steps of the color subcarrier.
+const SAMPLES_PER_DOT: usize = 8;
+const SUBCARRIER_STEPS: i32 = 12;
+
+/// Dots repeated past either edge of a line so the filters don't run off it.
+const BORDER: usize = 3;
+const LINE_SAMPLES: usize = (ppu::WIDTH + BORDER * 2) * SAMPLES_PER_DOT;
+
+/// Signal levels for the four luminance rows of the palette, low and high,
+/// scaled so that black is 0.0 and white 1.0.
+const LOW_LEVELS: [f32; 4] = [-0.117, 0.000, 0.308, 0.715];
+const HIGH_LEVELS: [f32; 4] = [0.399, 0.684, 1.000, 1.000];
+
+/// Emphasized components attenuate the signal during their part of the
+/// subcarrier cycle.
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+/// Subcarrier steps at which red, green and blue emphasis start.
+const EMPHASIS_PHASES: [i32; 3] = [0, 4, 8];
+
+/// Rotates the decoded colors so they line up with the palette.
+const HUE: f32 = 106.0 * PI / 180.0;
+/// Scales the decoded chroma so it lines up with the palette.
+const SATURATION: f32 = 0.7;
+
+/// Recreates the composite video signal of the RP2C02 from a frame of
+/// palette indices, then decodes it back to RGB the way a television would.
+///
+/// Each dot becomes 8 samples of a square wave on the 12 phase color
+/// subcarrier, with the emphasis bits attenuating parts of the cycle.
+/// Scanlines start 8 or 4 steps further along the subcarrier than the one
+/// before, and frames wherever the PPU left off, so the dot crawl of the
+/// real console comes out on its own. Decoding separates luma and chroma
+/// with box filters, whose crosstalk brings the artifacts back.
+pub struct NtscFilter {
+    /// How narrow the chroma filter is, from 0.0 for colors bleeding over
+    /// three dots to 1.0 for a dot and a half.
+    pub sharpness: f32,
+    /// How much chroma leaks into luma, from 0.0 for a clean picture to 1.0
+    /// for the full checkerboard and dot crawl of a cheap television.
+    pub artifacts: f32,
+    /// Whether the subcarrier phase follows the PPU. When off, every frame
+    /// is decoded with the same phase so the picture stands still.
+    pub dot_crawl: bool,
+
+    // Running sums of the signal, and of it mixed with the subcarrier.
+    luma: Vec<f32>,
+    in_phase: Vec<f32>,
+    quadrature: Vec<f32>,
+    frame: Vec<u8>,
+}
+
+impl Default for NtscFilter {
+    fn default() -> Self {
+        Self {
+            sharpness: 0.5,
+            artifacts: 0.5,
+            dot_crawl: true,
+
+            luma: vec![0.0; LINE_SAMPLES + 1],
+            in_phase: vec![0.0; LINE_SAMPLES + 1],
+            quadrature: vec![0.0; LINE_SAMPLES + 1],
+            frame: vec![0; WIDTH * HEIGHT * 4],
+        }
+    }
+}
+
+impl NtscFilter {
+    /// Filters the last frame of `ppu` into [`WIDTH`] x [`HEIGHT`] RGBA
+    /// pixels, row by row.
+    pub fn apply(&mut self, ppu: &Ppu) -> &[u8] {
+        let frame_phase = if self.dot_crawl { ppu.frame_phase } else { 0 };
+
+        for y in 0..HEIGHT {
+            // 341 dots to a line, so each starts 2 dots later in the cycle.
+            let line_phase = (frame_phase as usize + y * 2) % 3;
+            let line = &ppu.frame[y * ppu::WIDTH..(y + 1) * ppu::WIDTH];
+
+            self.encode(line, line_phase);
+            self.decode(y);
+        }
+
+        &self.frame
+    }
+
+    fn encode(&mut self, line: &[u16], line_phase: usize) {
+        // Pixel x is output on dot x + 1.
+        let offset = (line_phase as i32 + 1 - BORDER as i32) * SAMPLES_PER_DOT as i32;
+
+        for index in 0..LINE_SAMPLES {
+            let x = (index / SAMPLES_PER_DOT).clamp(BORDER, BORDER + ppu::WIDTH - 1) - BORDER;
+            let phase = (index as i32 + offset).rem_euclid(SUBCARRIER_STEPS);
+            let sample = level(line[x], phase);
+
+            let angle = subcarrier_angle(phase);
+            self.luma[index + 1] = self.luma[index] + sample;
+            self.in_phase[index + 1] = self.in_phase[index] + sample * angle.cos();
+            self.quadrature[index + 1] = self.quadrature[index] + sample * angle.sin();
+        }
+    }
+
+    fn decode(&mut self, y: usize) {
+        let artifacts = self.artifacts.clamp(0.0, 1.0);
+        let sharpness = self.sharpness.clamp(0.0, 1.0);
+
+        // Windows of whole subcarrier cycles cancel out chroma and leave flat
+        // colors flat. Blending in a dot wide luma window lets chroma through.
+        let filter = |sums: &[f32], center, narrow, wide, amount: f32| {
+            let wide = average(sums, center, wide);
+            wide + (average(sums, center, narrow) - wide) * amount
+        };
+
+        for x in 0..WIDTH {
+            let center = BORDER * SAMPLES_PER_DOT + x * SAMPLES_PER_DOT / 2 + SAMPLES_PER_DOT / 4;
+
+            let luma = filter(&self.luma, center, 8, 12, artifacts);
+            let i = filter(&self.in_phase, center, 12, 24, sharpness) * 2.0 * SATURATION;
+            let q = filter(&self.quadrature, center, 12, 24, sharpness) * 2.0 * SATURATION;
+
+            let rgb = yiq_to_rgb([luma, i, q]);
+
+            let pixel = &mut self.frame[(y * WIDTH + x) * 4..][..4];
+            for (output, value) in pixel.iter_mut().zip(rgb) {
+                *output = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+            pixel[3] = 0xFF;
+        }
+    }
+}
+
+/// The YIQ color of `pixel` when it fills the screen.
+pub(crate) fn yiq(pixel: u16) -> [f32; 3] {
+    let mut yiq = [0.0; 3];
+
+    for phase in 0..SUBCARRIER_STEPS {
+        let sample = level(pixel, phase);
+        let angle = subcarrier_angle(phase);
+
+        yiq[0] += sample;
+        yiq[1] += sample * angle.cos();
+        yiq[2] += sample * angle.sin();
+    }
+
+    let [y, i, q] = yiq.map(|sum| sum / SUBCARRIER_STEPS as f32);
+    [y, i * 2.0 * SATURATION, q * 2.0 * SATURATION]
+}
+
+pub(crate) fn yiq_to_rgb([y, i, q]: [f32; 3]) -> [f32; 3] {
+    [
+        y + 0.946_882 * i + 0.623_557 * q,
+        y - 0.274_788 * i - 0.635_691 * q,
+        y - 1.108_545 * i + 1.709_007 * q,
+    ]
+}
+
+fn subcarrier_angle(phase: i32) -> f32 {
+    (phase as f32 + 0.5) * PI / 6.0 + HUE
+}
+
+/// The signal for `pixel` at a step of the subcarrier.
+fn level(pixel: u16, phase: i32) -> f32 {
+    let color = (pixel & 0x0F) as i32;
+    let row = if color > 0x0D {
+        1
+    } else {
+        (pixel >> 4) as usize & 0x03
+    };
+    let emphasis = pixel >> 6;
+
+    let in_color_phase = |hue: i32| (hue + phase) % SUBCARRIER_STEPS < 6;
+
+    let mut level = match color {
+        0x00 => HIGH_LEVELS[row],
+        0x0D.. => LOW_LEVELS[row],
+        _ if in_color_phase(color) => HIGH_LEVELS[row],
+        _ => LOW_LEVELS[row],
+    };
+
+    let emphasized = EMPHASIS_PHASES
+        .iter()
+        .enumerate()
+        .any(|(bit, &hue)| emphasis & (1 << bit) != 0 && in_color_phase(hue));
+    if emphasized {
+        level *= EMPHASIS_ATTENUATION;
+    }
+
+    level
+}
+
+/// The mean of the samples `width` wide around `center`, from running sums.
+fn average(sums: &[f32], center: usize, width: usize) -> f32 {
+    let start = center - width / 2;
+    let end = (start + width).min(sums.len() - 1);
+
+    (sums[end] - sums[start]) / (end - start) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(color: u16) -> Vec<u8> {
+        let mut ppu = Ppu::default();
+        ppu.frame.fill(color);
+
+        NtscFilter::default().apply(&ppu).to_vec()
+    }
+
+    #[test]
+    fn grays_are_flat() {
+        for color in [0x0F, 0x00, 0x10, 0x30] {
+            let frame = filter(color);
+            let first = &frame[..4];
+
+            for pixel in frame.chunks(4) {
+                assert_eq!(pixel, first, "{:02X}", color);
+            }
+            assert_eq!(first[0], first[1]);
+            assert_eq!(first[1], first[2]);
+        }
+
+        assert_eq!(filter(0x0F)[..4], [0, 0, 0, 0xFF]);
+        assert_eq!(filter(0x30)[..4], [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn hues() {
+        // Red, green and blue from the middle of a solid frame.
+        let middle = (HEIGHT / 2 * WIDTH + WIDTH / 2) * 4;
+        for (color, component) in [(0x16, 0), (0x1A, 1), (0x12, 2)] {
+            let frame = filter(color);
+            let pixel = &frame[middle..middle + 3];
+            let brightest = (0..3).max_by_key(|&index| pixel[index]).unwrap();
+
+            assert_eq!(brightest, component, "color {:02X}", color);
+        }
+    }
+
+    #[test]
+    fn dot_crawl() {
+        let mut ppu = Ppu::default();
+        ppu.frame.fill(0x16);
+        let mut filter = NtscFilter {
+            artifacts: 1.0,
+            ..Default::default()
+        };
+
+        let first = filter.apply(&ppu).to_vec();
+        ppu.frame_phase = 1;
+        assert_ne!(filter.apply(&ppu), first);
+
+        filter.dot_crawl = false;
+        let still = filter.apply(&ppu).to_vec();
+        ppu.frame_phase = 2;
+        assert_eq!(filter.apply(&ppu), still);
+    }
+}
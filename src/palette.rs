@@ -0,0 +1,348 @@
+use std::fmt;
+
+use super::ntsc;
+
+/// The colors of the RP2C02, indexed by the 6 bit values the PPU outputs.
+#[rustfmt::skip]
+pub const NTSC: [[u8; 3]; 64] = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136],
+    [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0],
+    [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228],
+    [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40],
+    [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236],
+    [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108],
+    [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236],
+    [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180],
+    [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+/// Converts a pixel from the PPU's frame, a color index with the emphasis
+/// bits above it, to RGB using the [`NTSC`] palette.
+pub fn rgb(pixel: u16) -> [u8; 3] {
+    emphasize(NTSC[pixel as usize & 0x3F], (pixel >> 6) & 0x07)
+}
+
+/// Each emphasized component dims the other two.
+fn emphasize(mut color: [u8; 3], emphasis: u16) -> [u8; 3] {
+    if emphasis != 0 {
+        for (component, value) in color.iter_mut().enumerate() {
+            if emphasis & (1 << component) == 0 {
+                *value = (*value as u32 * 816 / 1000) as u8;
+            }
+        }
+    }
+
+    color
+}
+
+/// The colors of the RGB PPUs used in arcade and licensed television
+/// hardware: the RP2C03 and the RC2C05 variants. Components are 3 bit.
+#[rustfmt::skip]
+const RGB_PPU: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+/// The color orders of the four RP2C04 variants, for
+/// [`Palette::reorder`]: color `n` of each is color `order[n]` of the
+/// [`Palette::rgb_ppu`].
+#[rustfmt::skip]
+pub const RP2C04_0001: [u8; 64] = [
+    0x35, 0x23, 0x16, 0x22, 0x1C, 0x09, 0x1D, 0x15,
+    0x20, 0x00, 0x27, 0x05, 0x04, 0x28, 0x08, 0x20,
+    0x21, 0x3E, 0x1F, 0x29, 0x3C, 0x32, 0x36, 0x12,
+    0x3F, 0x2B, 0x2E, 0x1E, 0x3D, 0x2D, 0x24, 0x01,
+    0x0E, 0x31, 0x33, 0x2A, 0x2C, 0x0C, 0x1B, 0x14,
+    0x2E, 0x07, 0x34, 0x06, 0x13, 0x02, 0x26, 0x2E,
+    0x2E, 0x19, 0x10, 0x0A, 0x39, 0x03, 0x37, 0x17,
+    0x0F, 0x11, 0x0B, 0x0D, 0x38, 0x25, 0x18, 0x3A,
+];
+
+#[rustfmt::skip]
+pub const RP2C04_0002: [u8; 64] = [
+    0x2E, 0x27, 0x18, 0x39, 0x3A, 0x25, 0x1C, 0x31,
+    0x16, 0x13, 0x38, 0x34, 0x20, 0x23, 0x3C, 0x0B,
+    0x0F, 0x21, 0x06, 0x3D, 0x1B, 0x29, 0x1E, 0x22,
+    0x1D, 0x24, 0x0E, 0x2B, 0x32, 0x08, 0x2E, 0x03,
+    0x04, 0x36, 0x26, 0x33, 0x11, 0x1F, 0x10, 0x02,
+    0x14, 0x3F, 0x00, 0x09, 0x12, 0x2E, 0x28, 0x20,
+    0x3E, 0x0D, 0x2A, 0x17, 0x0C, 0x01, 0x15, 0x19,
+    0x2E, 0x2C, 0x07, 0x37, 0x35, 0x05, 0x0A, 0x2D,
+];
+
+#[rustfmt::skip]
+pub const RP2C04_0003: [u8; 64] = [
+    0x14, 0x25, 0x3A, 0x10, 0x0B, 0x20, 0x31, 0x09,
+    0x01, 0x2E, 0x36, 0x08, 0x15, 0x3D, 0x3E, 0x3C,
+    0x22, 0x1C, 0x05, 0x12, 0x19, 0x18, 0x17, 0x1B,
+    0x00, 0x03, 0x2E, 0x02, 0x16, 0x06, 0x34, 0x35,
+    0x23, 0x0F, 0x0E, 0x37, 0x0D, 0x27, 0x26, 0x20,
+    0x29, 0x04, 0x21, 0x24, 0x11, 0x2D, 0x2E, 0x1F,
+    0x2C, 0x1E, 0x39, 0x33, 0x07, 0x2A, 0x28, 0x1D,
+    0x0A, 0x2E, 0x32, 0x38, 0x13, 0x2B, 0x3F, 0x0C,
+];
+
+#[rustfmt::skip]
+pub const RP2C04_0004: [u8; 64] = [
+    0x18, 0x03, 0x1C, 0x28, 0x2E, 0x35, 0x01, 0x17,
+    0x10, 0x1F, 0x2A, 0x0E, 0x36, 0x37, 0x0B, 0x39,
+    0x25, 0x1E, 0x12, 0x34, 0x2E, 0x1D, 0x06, 0x26,
+    0x3E, 0x1B, 0x22, 0x19, 0x04, 0x2E, 0x3A, 0x21,
+    0x05, 0x0A, 0x07, 0x02, 0x13, 0x14, 0x00, 0x15,
+    0x0C, 0x3D, 0x11, 0x0F, 0x0D, 0x38, 0x2D, 0x24,
+    0x33, 0x20, 0x08, 0x16, 0x3F, 0x2B, 0x20, 0x3C,
+    0x2E, 0x27, 0x23, 0x31, 0x29, 0x32, 0x2C, 0x09,
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaletteError {
+    /// `.pal` files hold either 64 or 512 colors of 3 bytes each.
+    InvalidSize(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::InvalidSize(size) => {
+                write!(f, "palette files are 192 or 1536 bytes, not {}", size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+/// Adjustments for [`Palette::generate`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Rotation of the hues in degrees.
+    pub hue: f32,
+    /// Chroma scale, 0.0 for grayscale.
+    pub saturation: f32,
+    /// Luma scale around mid gray.
+    pub contrast: f32,
+    /// Offset added to the luma.
+    pub brightness: f32,
+    /// The gamma of the display the palette is made for. The signal is
+    /// assumed to target 2.2.
+    pub gamma: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 0.0,
+            gamma: 2.2,
+        }
+    }
+}
+
+/// Colors for all 512 pixel values in [`Ppu::frame`]: the 64 palette
+/// indices under each of the 8 combinations of emphasis bits.
+///
+/// [`Ppu::frame`]: super::ppu::Ppu::frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 512],
+}
+
+impl Default for Palette {
+    /// The [`NTSC`] palette.
+    fn default() -> Self {
+        let mut colors = [[0; 3]; 512];
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            *color = rgb(pixel as u16);
+        }
+
+        Self { colors }
+    }
+}
+
+impl Palette {
+    /// Decodes the composite signal of the RP2C02 for every color, the same
+    /// way [`NtscFilter`](super::ntsc::NtscFilter) does, adjusted by
+    /// `settings`.
+    pub fn generate(settings: &Settings) -> Self {
+        let (sin, cos) = settings.hue.to_radians().sin_cos();
+        let mut colors = [[0; 3]; 512];
+
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            let [y, i, q] = ntsc::yiq(pixel as u16);
+
+            let y = (y - 0.5) * settings.contrast + 0.5 + settings.brightness;
+            let (i, q) = (
+                (i * cos - q * sin) * settings.saturation,
+                (i * sin + q * cos) * settings.saturation,
+            );
+
+            let rgb = ntsc::yiq_to_rgb([y, i, q]);
+            for (output, value) in color.iter_mut().zip(rgb) {
+                let value = value.clamp(0.0, 1.0).powf(2.2 / settings.gamma);
+                *output = (value * 255.0).round() as u8;
+            }
+        }
+
+        Self { colors }
+    }
+
+    /// The palette of the RGB PPUs: the RP2C03, the RP2C04 variants and the
+    /// RC2C05 variants. They set an emphasized component to full intensity
+    /// instead of dimming the others.
+    ///
+    /// Each RP2C04 outputs these colors in its own scrambled order, e.g.
+    /// [`RP2C04_0001`], which [`Palette::reorder`] applies.
+    pub fn rgb_ppu() -> Self {
+        let mut colors = [[0; 3]; 512];
+
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            let value = RGB_PPU[pixel & 0x3F];
+            let emphasis = pixel >> 6;
+
+            for (component, output) in color.iter_mut().enumerate() {
+                let level = if emphasis & (1 << component) != 0 {
+                    7
+                } else {
+                    (value >> (6 - component * 3)) & 0x07
+                };
+                *output = (level * 255 / 7) as u8;
+            }
+        }
+
+        Self { colors }
+    }
+
+    /// The palette of a PPU whose color `n` is color `order[n]` of this one.
+    pub fn reorder(&self, order: &[u8; 64]) -> Self {
+        let mut colors = [[0; 3]; 512];
+
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            let source = (pixel & !0x3F) | (order[pixel & 0x3F] as usize & 0x3F);
+            *color = self.colors[source];
+        }
+
+        Self { colors }
+    }
+
+    /// Loads a `.pal` file. Files with only 64 colors get the emphasized
+    /// colors by dimming the other components, as [`rgb`] does.
+    pub fn from_pal(data: &[u8]) -> Result<Self, PaletteError> {
+        let count = match data.len() {
+            192 => 64,
+            1536 => 512,
+            size => return Err(PaletteError::InvalidSize(size)),
+        };
+
+        let mut colors = [[0; 3]; 512];
+        for (pixel, color) in colors.iter_mut().enumerate() {
+            let entry = &data[(pixel % count) * 3..][..3];
+            *color = [entry[0], entry[1], entry[2]];
+
+            if count == 64 {
+                *color = emphasize(*color, pixel as u16 >> 6);
+            }
+        }
+
+        Ok(Self { colors })
+    }
+
+    /// The palette as a 1536 byte `.pal` file, or a 192 byte one without the
+    /// emphasized colors when `full` is false.
+    pub fn to_pal(&self, full: bool) -> Vec<u8> {
+        let count = if full { 512 } else { 64 };
+
+        self.colors[..count].as_flattened().to_vec()
+    }
+
+    /// Converts a pixel from the PPU's frame to RGB.
+    pub fn rgb(&self, pixel: u16) -> [u8; 3] {
+        self.colors[pixel as usize & 0x1FF]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pal_files() {
+        let palette = Palette::generate(&Settings::default());
+
+        let full = palette.to_pal(true);
+        assert_eq!(full.len(), 1536);
+        assert_eq!(Palette::from_pal(&full).unwrap(), palette);
+
+        let short = Palette::default().to_pal(false);
+        assert_eq!(short.len(), 192);
+        assert_eq!(Palette::from_pal(&short).unwrap(), Palette::default());
+
+        assert_eq!(
+            Palette::from_pal(&[0; 100]).unwrap_err(),
+            PaletteError::InvalidSize(100)
+        );
+    }
+
+    #[test]
+    fn generated() {
+        let palette = Palette::generate(&Settings::default());
+
+        assert_eq!(palette.rgb(0x0F), [0, 0, 0]);
+        assert_eq!(palette.rgb(0x30), [255, 255, 255]);
+
+        let gray = Palette::generate(&Settings {
+            saturation: 0.0,
+            ..Default::default()
+        });
+        for color in gray.colors {
+            assert_eq!(color, [color[0]; 3]);
+        }
+
+        // Emphasizing red makes color $26 redder by dimming green and blue.
+        let [r, g, b] = palette.rgb(0x26);
+        let [er, eg, eb] = palette.rgb(0x26 | 0x01 << 6);
+        assert!(eg < g && eb < b);
+        assert!(er - eg > r - g);
+    }
+
+    #[test]
+    fn rgb_ppu() {
+        let palette = Palette::rgb_ppu();
+
+        assert_eq!(palette.rgb(0x16), [255, 0, 0]);
+        assert_eq!(palette.rgb(0x0F | 0x04 << 6), [0, 0, 255]);
+
+        let mut order = [0; 64];
+        for (index, entry) in order.iter_mut().enumerate() {
+            *entry = 63 - index as u8;
+        }
+        assert_eq!(palette.reorder(&order).rgb(0x3F), palette.rgb(0x00));
+
+        let rp2c04 = palette.reorder(&RP2C04_0001);
+        assert_eq!(rp2c04.rgb(0x00), palette.rgb(0x35));
+        assert_eq!(rp2c04.rgb(0x3F | 0x01 << 6), palette.rgb(0x3A | 0x01 << 6));
+
+        // The orders differ, but each has the same colors.
+        let mut colors: Vec<_> = RP2C04_0001.iter().map(|&n| palette.rgb(n as u16)).collect();
+        colors.sort();
+        for order in [RP2C04_0002, RP2C04_0003, RP2C04_0004] {
+            let mut other: Vec<_> = order.iter().map(|&n| palette.rgb(n as u16)).collect();
+            other.sort();
+            assert_eq!(other, colors);
+        }
+    }
+}
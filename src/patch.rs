@@ -0,0 +1,428 @@
+use std::fmt;
+
+use super::checksum::crc32;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch is not in IPS, BPS or UPS format.
+    UnknownFormat,
+    /// The patch ends early, points outside the ROM or asks for a ROM larger
+    /// than any NES image.
+    Malformed,
+    /// The patch was made for a ROM with another CRC-32.
+    SourceMismatch { expected: u32, actual: u32 },
+    /// The patched ROM does not have the CRC-32 the patch promises.
+    TargetMismatch,
+    /// The patch is damaged, its own CRC-32 does not match.
+    ChecksumMismatch,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS, BPS or UPS patch"),
+            PatchError::Malformed => write!(f, "patch is malformed"),
+            PatchError::SourceMismatch { expected, actual } => write!(
+                f,
+                "patch is for a ROM with CRC-32 {:08X}, this one has {:08X}",
+                expected, actual
+            ),
+            PatchError::TargetMismatch => write!(f, "patched ROM has the wrong checksum"),
+            PatchError::ChecksumMismatch => write!(f, "patch is damaged, its checksum is wrong"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Applies an IPS, BPS or UPS patch to `rom`, telling the format by its
+/// magic.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if patch.starts_with(b"PATCH") {
+        ips(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        bps(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        ups(rom, patch)
+    } else {
+        Err(PatchError::UnknownFormat)
+    }
+}
+
+/// Reads patches, keeping track of where it is.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], position: usize) -> Self {
+        Self { data, position }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .ok_or(PatchError::Malformed)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn big_endian(&mut self, count: usize) -> Result<usize, PatchError> {
+        Ok(self
+            .bytes(count)?
+            .iter()
+            .fold(0, |number, &byte| number << 8 | byte as usize))
+    }
+
+    /// Reads a BPS and UPS number: seven bits a byte, least significant
+    /// first, the top bit marking the last byte. Every byte but the first
+    /// also adds one to what follows, so each number has only one encoding.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let mut number = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let byte = self.u8()?;
+            number = (byte as usize & 0x7F)
+                .checked_mul(shift)
+                .and_then(|bits| number.checked_add(bits))
+                .ok_or(PatchError::Malformed)?;
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+
+            shift = shift.checked_shl(7).ok_or(PatchError::Malformed)?;
+            number = number.checked_add(shift).ok_or(PatchError::Malformed)?;
+        }
+    }
+}
+
+// SECTION: IPS
+
+/// Applies an IPS patch: records of a 24-bit offset, a 16-bit length and as
+/// many bytes to write there, or with a length of zero, a 16-bit count and a
+/// byte to repeat. `EOF` ends the records and may be followed by the 24-bit
+/// size to truncate the ROM to.
+fn ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut target = rom.to_vec();
+    let mut reader = Reader::new(patch, 5);
+
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == 0x454F46 {
+            break;
+        }
+
+        let (length, fill) = match reader.big_endian(2)? {
+            0 => (reader.big_endian(2)?, Some(reader.u8()?)),
+            length => (length, None),
+        };
+
+        if target.len() < offset + length {
+            target.resize(offset + length, 0);
+        }
+        match fill {
+            Some(byte) => target[offset..offset + length].fill(byte),
+            None => target[offset..offset + length].copy_from_slice(reader.bytes(length)?),
+        }
+    }
+
+    if let Ok(size) = reader.big_endian(3) {
+        target.truncate(size);
+    }
+
+    Ok(target)
+}
+
+// SECTION: BPS and UPS
+
+/// The largest ROM a BPS or UPS patch may ask for, far beyond any NES image.
+/// The size is read from the patch before anything is checked, so a damaged
+/// one must not get to allocate whatever it says.
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+/// Splits off the three CRC-32s that end BPS and UPS patches, for the
+/// source, the target and the rest of the patch, checking the last and the
+/// first.
+fn footer(rom: &[u8], patch: &[u8]) -> Result<(usize, u32), PatchError> {
+    if patch.len() < 4 + 12 {
+        return Err(PatchError::Malformed);
+    }
+
+    let end = patch.len() - 12;
+    let crc = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+
+    if crc32(&patch[..end + 8]) != crc(end + 8) {
+        return Err(PatchError::ChecksumMismatch);
+    }
+    let expected = crc(end);
+    let actual = crc32(rom);
+    if expected != actual {
+        return Err(PatchError::SourceMismatch { expected, actual });
+    }
+
+    Ok((end, crc(end + 4)))
+}
+
+/// Applies a BPS patch, which builds the new ROM from commands that copy
+/// from the old ROM, from the patch or from what was built so far.
+fn bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (end, target_crc) = footer(rom, patch)?;
+    let mut reader = Reader::new(&patch[..end], 4);
+
+    if reader.number()? != rom.len() {
+        return Err(PatchError::Malformed);
+    }
+    let size = reader.number()?;
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::Malformed);
+    }
+    let metadata = reader.number()?;
+    reader.bytes(metadata)?;
+
+    let mut target = Vec::with_capacity(size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+
+    // Copies move their offsets by signed amounts, the sign in the low bit.
+    let seek = |offset: &mut usize, reader: &mut Reader| -> Result<(), PatchError> {
+        let number = reader.number()?;
+        *offset = if number & 1 != 0 {
+            offset.checked_sub(number >> 1)
+        } else {
+            offset.checked_add(number >> 1)
+        }
+        .ok_or(PatchError::Malformed)?;
+        Ok(())
+    };
+
+    while reader.position < end {
+        let command = reader.number()?;
+        let length = (command >> 2) + 1;
+        if length > size - target.len() {
+            return Err(PatchError::Malformed);
+        }
+
+        match command & 3 {
+            // Source read, from the same place in the old ROM.
+            0 => {
+                let at = target.len();
+                let bytes = rom.get(at..at + length).ok_or(PatchError::Malformed)?;
+                target.extend_from_slice(bytes);
+            }
+            // Target read, from the patch.
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // Source copy, from anywhere in the old ROM.
+            2 => {
+                seek(&mut source_offset, &mut reader)?;
+                let bytes = rom
+                    .get(source_offset..source_offset + length)
+                    .ok_or(PatchError::Malformed)?;
+                target.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // Target copy, a byte at a time as it may overlap what it makes.
+            _ => {
+                seek(&mut target_offset, &mut reader)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or(PatchError::Malformed)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != size || crc32(&target) != target_crc {
+        return Err(PatchError::TargetMismatch);
+    }
+
+    Ok(target)
+}
+
+/// Applies a UPS patch: runs of bytes to XOR into the ROM, each after a
+/// number of bytes to skip and ending with a zero.
+fn ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let (end, target_crc) = footer(rom, patch)?;
+    let mut reader = Reader::new(&patch[..end], 4);
+
+    if reader.number()? != rom.len() {
+        return Err(PatchError::Malformed);
+    }
+    let size = reader.number()?;
+    if size > MAX_TARGET_SIZE {
+        return Err(PatchError::Malformed);
+    }
+
+    let mut target = rom.to_vec();
+    target.resize(size, 0);
+
+    let mut offset = 0usize;
+    while reader.position < end {
+        offset = offset
+            .checked_add(reader.number()?)
+            .ok_or(PatchError::Malformed)?;
+
+        loop {
+            // Changes past the end of the ROM are left out when it shrinks.
+            // The zero that ends the run leaves its byte as it is.
+            let byte = reader.u8()?;
+            if let Some(target) = target.get_mut(offset) {
+                *target ^= byte;
+            }
+            offset = offset.checked_add(1).ok_or(PatchError::Malformed)?;
+
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    if crc32(&target) != target_crc {
+        return Err(PatchError::TargetMismatch);
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_number(data: &mut Vec<u8>, mut number: usize) {
+        loop {
+            let bits = (number & 0x7F) as u8;
+            number >>= 7;
+            if number == 0 {
+                data.push(bits | 0x80);
+                return;
+            }
+            data.push(bits);
+            number -= 1;
+        }
+    }
+
+    /// Ends a BPS or UPS patch with its checksums.
+    fn finish(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn numbers() {
+        for number in [0, 1, 127, 128, 129, 16511, 16512, 1 << 20] {
+            let mut data = Vec::new();
+            write_number(&mut data, number);
+            assert_eq!(Reader::new(&data, 0).number(), Ok(number));
+        }
+    }
+
+    #[test]
+    fn ips_patches() {
+        let rom = b"0123456789";
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0, 0, 2, 0, 2, b'a', b'b']);
+        // Runs of a byte, here past the end of the ROM.
+        patch.extend([0, 0, 8, 0, 0, 0, 4, b'z']);
+        patch.extend(b"EOF");
+        assert_eq!(apply(rom, &patch).unwrap(), b"01ab4567zzzz");
+
+        // Truncated to seven bytes.
+        patch.extend([0, 0, 7]);
+        assert_eq!(apply(rom, &patch).unwrap(), b"01ab456");
+
+        assert_eq!(apply(rom, b"PATCH\0\0"), Err(PatchError::Malformed));
+        assert_eq!(apply(rom, b"PATCHEO"), Err(PatchError::Malformed));
+        assert_eq!(apply(rom, b"NES\x1A"), Err(PatchError::UnknownFormat));
+    }
+
+    #[test]
+    fn bps_patches() {
+        let source = b"Hello, world!";
+        let target = b"Hello, hello, world!!!";
+
+        let mut patch = b"BPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, target.len());
+        write_number(&mut patch, 3);
+        patch.extend(b"xyz");
+        // "Hello, " from the source.
+        write_number(&mut patch, (7 - 1) << 2);
+        // "h" from the patch.
+        write_number(&mut patch, 1);
+        patch.push(b'h');
+        // "ello, world!" from the source, one byte in.
+        write_number(&mut patch, (12 - 1) << 2 | 2);
+        write_number(&mut patch, 1 << 1);
+        // "!!" from the last byte written, a byte at a time.
+        write_number(&mut patch, (2 - 1) << 2 | 3);
+        write_number(&mut patch, 19 << 1);
+        let patch = finish(patch, source, target);
+
+        assert_eq!(apply(source, &patch).unwrap(), target);
+        assert_eq!(
+            apply(b"Goodbye", &patch),
+            Err(PatchError::SourceMismatch {
+                expected: crc32(source),
+                actual: crc32(b"Goodbye"),
+            })
+        );
+
+        let mut damaged = patch.clone();
+        damaged[12] ^= 1;
+        assert_eq!(apply(source, &damaged), Err(PatchError::ChecksumMismatch));
+
+        let mut huge = b"BPS1".to_vec();
+        write_number(&mut huge, source.len());
+        write_number(&mut huge, usize::MAX >> 8);
+        write_number(&mut huge, 0);
+        let huge = finish(huge, source, target);
+        assert_eq!(apply(source, &huge), Err(PatchError::Malformed));
+    }
+
+    #[test]
+    fn ups_patches() {
+        let source = b"abcdefgh";
+        let target = b"abXdefghij";
+
+        let mut patch = b"UPS1".to_vec();
+        write_number(&mut patch, source.len());
+        write_number(&mut patch, target.len());
+        write_number(&mut patch, 2);
+        patch.extend([b'c' ^ b'X', 0]);
+        write_number(&mut patch, 4);
+        patch.extend([b'i', b'j', 0]);
+        let patch = finish(patch, source, target);
+
+        assert_eq!(apply(source, &patch).unwrap(), target);
+        assert!(matches!(
+            apply(target, &patch),
+            Err(PatchError::SourceMismatch { .. })
+        ));
+
+        let mut huge = b"UPS1".to_vec();
+        write_number(&mut huge, source.len());
+        write_number(&mut huge, MAX_TARGET_SIZE + 1);
+        let huge = finish(huge, source, target);
+        assert_eq!(apply(source, &huge), Err(PatchError::Malformed));
+
+        // Skips that run the offset past the largest address.
+        let mut far = b"UPS1".to_vec();
+        write_number(&mut far, source.len());
+        write_number(&mut far, target.len());
+        for skip in [usize::MAX, 1] {
+            write_number(&mut far, skip);
+            far.push(0);
+        }
+        let far = finish(far, source, target);
+        assert_eq!(apply(source, &far), Err(PatchError::Malformed));
+    }
+}
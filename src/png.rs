@@ -0,0 +1,244 @@
+use super::checksum::crc32_update;
+
+/// Encodes 8 bit RGB pixels as a PNG image.
+///
+/// The encoder is small and deterministic: the same pixels always give the
+/// same file, so screenshots can be compared byte for byte.
+pub fn encode(width: u32, height: u32, rgb: &[u8]) -> Vec<u8> {
+    encode_image(width, height, COLOR_RGB, rgb)
+}
+
+/// Encodes 8 bit RGBA pixels as a PNG image.
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    encode_image(width, height, COLOR_RGBA, rgba)
+}
+
+const COLOR_RGB: u8 = 2;
+const COLOR_RGBA: u8 = 6;
+
+fn encode_image(width: u32, height: u32, color_type: u8, pixels: &[u8]) -> Vec<u8> {
+    let channels = if color_type == COLOR_RGBA { 4 } else { 3 };
+    let stride = width as usize * channels;
+    assert_eq!(pixels.len(), stride * height as usize);
+
+    // Every row is preceded by its filter type, which is always none.
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(stride.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    header.extend([8, color_type, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1A\n".to_vec();
+    chunk(&mut png, b"IHDR", &header);
+    chunk(&mut png, b"IDAT", &zlib(&raw));
+    chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    png.extend(id);
+    png.extend(data);
+    png.extend((!crc32_update(crc32_update(!0, id), data)).to_be_bytes());
+}
+
+// SECTION: Deflate
+
+const WINDOW: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(crate) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Packs bits least significant first, as deflate wants them.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn bits(&mut self, value: u32, count: u32) {
+        self.buffer |= value << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are sent most significant bit first.
+    fn code(&mut self, code: u32, length: u32) {
+        self.bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// A literal byte or length symbol in the fixed Huffman code.
+    fn symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+
+        self.bytes
+    }
+}
+
+/// Wraps data in a zlib stream of a single fixed Huffman deflate block,
+/// found by a greedy search for earlier repeats.
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        bytes: vec![0x78, 0x01],
+        buffer: 0,
+        count: 0,
+    };
+    writer.bits(1, 1);
+    writer.bits(1, 2);
+
+    let hash = |at: usize| {
+        let key = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], 0]);
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+    let mut last_seen = vec![usize::MAX; 1 << HASH_BITS];
+
+    let mut at = 0;
+    while at < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+
+        if at + MIN_MATCH <= data.len() {
+            let key = hash(at);
+            let candidate = last_seen[key];
+            last_seen[key] = at;
+
+            if candidate != usize::MAX && at - candidate <= WINDOW {
+                let limit = MAX_MATCH.min(data.len() - at);
+                length = (0..limit)
+                    .take_while(|&offset| data[candidate + offset] == data[at + offset])
+                    .count();
+                distance = at - candidate;
+            }
+        }
+
+        if length < MIN_MATCH {
+            writer.symbol(data[at] as u16);
+            at += 1;
+            continue;
+        }
+
+        let code = LENGTH_BASE.partition_point(|&base| base as usize <= length) - 1;
+        writer.symbol(257 + code as u16);
+        writer.bits(
+            (length - LENGTH_BASE[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        );
+
+        let code = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        writer.code(code as u32, 5);
+        writer.bits(
+            (distance - DISTANCE_BASE[code] as usize) as u32,
+            DISTANCE_EXTRA[code] as u32,
+        );
+
+        // Index the repeated bytes too, so later runs can refer to them.
+        for skipped in at + 1..(at + length).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+            last_seen[hash(skipped)] = skipped;
+        }
+        at += length;
+    }
+
+    writer.symbol(256);
+
+    let mut stream = writer.finish();
+    stream.extend(adler32(data).to_be_bytes());
+
+    stream
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::inflate;
+
+    /// The zlib stream of the first IDAT chunk, inflated.
+    fn idat(png: &[u8]) -> Vec<u8> {
+        let length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+
+        let stream = &png[41..41 + length];
+        let data = inflate(&stream[2..length - 4]).unwrap();
+        assert_eq!(stream[length - 4..], adler32(&data).to_be_bytes());
+
+        data
+    }
+
+    #[test]
+    fn structure() {
+        let png = encode(2, 1, &[255, 0, 0, 0, 0, 255]);
+
+        assert!(png.starts_with(b"\x89PNG\r\n\x1A\n"));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert!(png.ends_with(&[b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+        assert_eq!(idat(&png), [0, 255, 0, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn compression() {
+        let mut rgba = Vec::new();
+        for index in 0..64 * 48 {
+            rgba.extend([(index % 7 * 30) as u8, (index / 64) as u8, 0x80, 0xFF]);
+        }
+
+        let png = encode_rgba(64, 48, &rgba);
+        assert!(png.len() < rgba.len() / 4);
+        assert_eq!(png, encode_rgba(64, 48, &rgba));
+
+        let raw: Vec<u8> = rgba
+            .chunks(64 * 4)
+            .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+            .collect();
+        assert_eq!(idat(&png), raw);
+    }
+}
@@ -0,0 +1,836 @@
+use super::cartridge::Cartridge;
+use super::region::Region;
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+// PPUCTRL
+const CTRL_INCREMENT: u8 = 1 << 2;
+const CTRL_SPRITE_TABLE: u8 = 1 << 3;
+const CTRL_BACKGROUND_TABLE: u8 = 1 << 4;
+const CTRL_SPRITE_SIZE: u8 = 1 << 5;
+const CTRL_NMI: u8 = 1 << 7;
+
+// PPUMASK
+const MASK_GRAYSCALE: u8 = 1 << 0;
+const MASK_BACKGROUND_LEFT: u8 = 1 << 1;
+const MASK_SPRITES_LEFT: u8 = 1 << 2;
+const MASK_BACKGROUND: u8 = 1 << 3;
+const MASK_SPRITES: u8 = 1 << 4;
+
+// PPUSTATUS
+const STATUS_OVERFLOW: u8 = 1 << 5;
+const STATUS_SPRITE_ZERO: u8 = 1 << 6;
+const STATUS_VBLANK: u8 = 1 << 7;
+
+/// The RP2C02 picture processing unit.
+///
+/// The PPU is stepped one dot at a time and performs its memory fetches on
+/// the dots the hardware does, so mappers that watch the PPU address bus see
+/// the same sequence of reads. Scrolling follows the internal `v`/`t`/`x`/`w`
+/// registers as described on the NESdev wiki.
+///
+/// Finished pixels land in [`Ppu::frame`] as 6 bit palette indices with the
+/// red, green and blue emphasis bits above them.
+pub struct Ppu {
+    region: Region,
+
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+    read_buffer: u8,
+    /// The value last driven onto the CPU data bus by a register access.
+    io_latch: u8,
+
+    /// Nametable RAM, with room for four screens on boards that need them.
+    ciram: [u8; 4096],
+    palette: [u8; 32],
+    pub oam: [u8; 256],
+
+    pub scanline: u16,
+    pub dot: u16,
+    odd_frame: bool,
+    /// Dots since power on modulo 3, as of dot 0 of the current scanline.
+    /// A dot lasts 8 of the 12 steps of the color subcarrier, so this fixes
+    /// the subcarrier phase the line starts with.
+    line_phase: u8,
+    /// [`Ppu::line_phase`] of the first line of the last frame.
+    pub frame_phase: u8,
+
+    // Background pipeline.
+    tile_id: u8,
+    tile_attribute: u8,
+    tile_lo: u8,
+    tile_hi: u8,
+    pattern_lo: u16,
+    pattern_hi: u16,
+    attribute_lo: u16,
+    attribute_hi: u16,
+
+    // Sprites for the current scanline.
+    sprite_count: usize,
+    sprite_patterns: [[u8; 2]; 8],
+    sprite_attributes: [u8; 8],
+    sprite_x: [u8; 8],
+    sprite_zero: bool,
+    /// The low pattern byte of the sprite being fetched, until its high byte
+    /// is.
+    sprite_lo: u8,
+
+    nmi_pending: bool,
+    pub frame_complete: bool,
+    pub frame_count: u64,
+    pub frame: Vec<u16>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self {
+            region: Region::Ntsc,
+
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            io_latch: 0,
+
+            ciram: [0; 4096],
+            palette: [0; 32],
+            oam: [0; 256],
+
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            line_phase: 0,
+            frame_phase: 0,
+
+            tile_id: 0,
+            tile_attribute: 0,
+            tile_lo: 0,
+            tile_hi: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+            attribute_lo: 0,
+            attribute_hi: 0,
+
+            sprite_count: 0,
+            sprite_patterns: [[0; 2]; 8],
+            sprite_attributes: [0; 8],
+            sprite_x: [0; 8],
+            sprite_zero: false,
+            sprite_lo: 0,
+
+            nmi_pending: false,
+            frame_complete: false,
+            frame_count: 0,
+            frame: vec![0; WIDTH * HEIGHT],
+        }
+    }
+}
+
+impl Ppu {
+    /// Resetting clears the control registers, but leaves memory and the
+    /// current position in the frame alone.
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.w = false;
+        self.read_buffer = 0;
+        self.t = 0;
+        self.x = 0;
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+        self.scanline = self.scanline.min(region.scanlines() - 1);
+    }
+
+    /// Returns whether the PPU has raised an NMI since the last call.
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    fn rendering(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    // SECTION: CPU facing registers
+
+    /// Reads one of the eight registers mirrored across $2000-$3FFF.
+    pub fn read_register(&mut self, cartridge: &mut Cartridge, addr: u16) -> u8 {
+        match addr & 0x07 {
+            0x02 => {
+                self.io_latch = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+            }
+            0x04 => self.io_latch = self.oam[self.oam_addr as usize],
+            0x07 => {
+                let addr = self.v & 0x3FFF;
+                let data = self.read(cartridge, addr);
+
+                // Palette reads are immediate, but still fill the buffer with
+                // the nametable byte underneath them.
+                self.io_latch = if addr >= 0x3F00 {
+                    self.read_buffer = self.read(cartridge, addr & 0x2FFF);
+                    (data & 0x3F) | (self.io_latch & 0xC0)
+                } else {
+                    std::mem::replace(&mut self.read_buffer, data)
+                };
+
+                self.increment_address();
+            }
+            _ => {}
+        }
+
+        self.io_latch
+    }
+
+    pub fn write_register(&mut self, cartridge: &mut Cartridge, addr: u16, data: u8) {
+        self.io_latch = data;
+
+        match addr & 0x07 {
+            0x00 => {
+                // Enabling NMIs during vertical blank raises one straight away.
+                if self.ctrl & CTRL_NMI == 0
+                    && data & CTRL_NMI != 0
+                    && self.status & STATUS_VBLANK != 0
+                {
+                    self.nmi_pending = true;
+                }
+
+                self.ctrl = data;
+                self.t = (self.t & !0x0C00) | ((data as u16 & 0x03) << 10);
+            }
+            0x01 => self.mask = data,
+            0x03 => self.oam_addr = data,
+            0x04 => self.write_oam(data),
+            0x05 => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (data as u16 >> 3);
+                    self.x = data & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0)
+                        | ((data as u16 & 0x07) << 12)
+                        | ((data as u16 & 0xF8) << 2);
+                }
+                self.w = !self.w;
+            }
+            0x06 => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((data as u16 & 0x3F) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | data as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            0x07 => {
+                self.write(cartridge, self.v & 0x3FFF, data);
+                self.increment_address();
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes to OAM through $2004, or by OAM DMA.
+    pub fn write_oam(&mut self, data: u8) {
+        self.oam[self.oam_addr as usize] = data;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn increment_address(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    // SECTION: PPU address space
+
+    fn read(&mut self, cartridge: &mut Cartridge, addr: u16) -> u8 {
+        if addr >= 0x3F00 {
+            return self.palette[palette_index(addr)];
+        }
+
+        match cartridge.mapper.ciram_address(addr) {
+            Some(offset) => self.ciram[offset as usize],
+            None => cartridge.mapper.ppu_read(addr),
+        }
+    }
+
+    fn write(&mut self, cartridge: &mut Cartridge, addr: u16, data: u8) {
+        if addr >= 0x3F00 {
+            self.palette[palette_index(addr)] = data & 0x3F;
+            return;
+        }
+
+        match cartridge.mapper.ciram_address(addr) {
+            Some(offset) => self.ciram[offset as usize] = data,
+            None => cartridge.mapper.ppu_write(addr, data),
+        }
+    }
+
+    // SECTION: Rendering
+
+    /// Advances the PPU by one dot.
+    pub fn clock(&mut self, cartridge: &mut Cartridge) {
+        let visible = self.scanline < HEIGHT as u16;
+        let pre_render = self.scanline == self.region.scanlines() - 1;
+
+        if self.rendering() && (visible || pre_render) {
+            self.render_dot(cartridge, visible, pre_render);
+        } else if visible && (1..=WIDTH as u16).contains(&self.dot) {
+            // With rendering off the backdrop color is shown, unless the
+            // address register points into the palette.
+            let color = if self.v & 0x3F00 == 0x3F00 {
+                self.palette[palette_index(self.v)]
+            } else {
+                self.palette[0]
+            };
+            self.put_pixel(color);
+        }
+
+        if self.scanline == self.region.vblank_line() && self.dot == 1 {
+            self.status |= STATUS_VBLANK;
+            if self.ctrl & CTRL_NMI != 0 {
+                self.nmi_pending = true;
+            }
+            self.frame_complete = true;
+            self.frame_count += 1;
+        }
+
+        if pre_render && self.dot == 1 {
+            self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO | STATUS_OVERFLOW);
+        }
+
+        self.dot += 1;
+
+        // Odd frames are one dot shorter while rendering, on NTSC only.
+        if pre_render
+            && self.dot == 340
+            && self.odd_frame
+            && self.rendering()
+            && self.region == Region::Ntsc
+        {
+            self.dot = 341;
+            self.line_phase = (self.line_phase + 2) % 3;
+        }
+
+        if self.dot > 340 {
+            self.dot = 0;
+            self.scanline += 1;
+            // 341 dots is 2 more than a multiple of 3.
+            self.line_phase = (self.line_phase + 2) % 3;
+
+            if self.scanline == self.region.scanlines() {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+                self.frame_phase = self.line_phase;
+            }
+        }
+    }
+
+    fn render_dot(&mut self, cartridge: &mut Cartridge, visible: bool, pre_render: bool) {
+        let dot = self.dot;
+
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+
+            match (dot - 1) % 8 {
+                // The fetch on dot 257 is the first of the sprites'.
+                0 if dot == 257 => self.load_background(),
+                0 => {
+                    self.load_background();
+                    let addr = 0x2000 | (self.v & 0x0FFF);
+                    self.tile_id = self.read(cartridge, addr);
+                }
+                2 => {
+                    let v = self.v;
+                    let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    let shift = ((v >> 4) & 0x04) | (v & 0x02);
+                    self.tile_attribute = (self.read(cartridge, addr) >> shift) & 0x03;
+                }
+                4 => {
+                    let addr = self.background_address();
+                    self.tile_lo = self.read(cartridge, addr);
+                }
+                6 => {
+                    let addr = self.background_address() + 8;
+                    self.tile_hi = self.read(cartridge, addr);
+                }
+                7 => self.increment_x(),
+                _ => {}
+            }
+        }
+
+        if visible && (1..=256).contains(&dot) {
+            self.render_pixel();
+        }
+
+        if dot == 257 {
+            self.v = (self.v & !0x041F) | (self.t & 0x041F);
+
+            if visible {
+                self.evaluate_sprites();
+            } else {
+                self.sprite_count = 0;
+            }
+        }
+        if (257..=320).contains(&dot) {
+            self.fetch_sprite(cartridge);
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            280..=304 if pre_render => {
+                self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+            }
+            // The second of the two unused nametable fetches ending the line.
+            339 => {
+                let addr = 0x2000 | (self.v & 0x0FFF);
+                self.read(cartridge, addr);
+            }
+            _ => {}
+        }
+    }
+
+    fn background_address(&self) -> u16 {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0x0000
+        };
+
+        table + self.tile_id as u16 * 16 + ((self.v >> 12) & 0x07)
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_lo <<= 1;
+        self.pattern_hi <<= 1;
+        self.attribute_lo <<= 1;
+        self.attribute_hi <<= 1;
+    }
+
+    fn load_background(&mut self) {
+        self.pattern_lo = (self.pattern_lo & 0xFF00) | self.tile_lo as u16;
+        self.pattern_hi = (self.pattern_hi & 0xFF00) | self.tile_hi as u16;
+
+        let lo = if self.tile_attribute & 0x01 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        let hi = if self.tile_attribute & 0x02 != 0 {
+            0xFF
+        } else {
+            0x00
+        };
+        self.attribute_lo = (self.attribute_lo & 0xFF00) | lo;
+        self.attribute_hi = (self.attribute_hi & 0xFF00) | hi;
+    }
+
+    fn increment_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let mut y = (self.v & 0x03E0) >> 5;
+        if y == 29 {
+            y = 0;
+            self.v ^= 0x0800;
+        } else if y == 31 {
+            y = 0;
+        } else {
+            y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (y << 5);
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Finds the first eight sprites on the next scanline.
+    fn evaluate_sprites(&mut self) {
+        let height = self.sprite_height();
+        self.sprite_count = 0;
+        self.sprite_zero = false;
+
+        for index in 0..64 {
+            let y = self.oam[index * 4] as u16;
+            if !(y..y + height).contains(&self.scanline) {
+                continue;
+            }
+
+            if self.sprite_count == 8 {
+                self.status |= STATUS_OVERFLOW;
+                break;
+            }
+
+            if index == 0 {
+                self.sprite_zero = true;
+            }
+
+            let slot = self.sprite_count;
+            self.sprite_attributes[slot] = self.oam[index * 4 + 2];
+            self.sprite_x[slot] = self.oam[index * 4 + 3];
+            // Reuse the pattern slot to remember the row until the fetch.
+            self.sprite_patterns[slot] = [self.oam[index * 4 + 1], (self.scanline - y) as u8];
+            self.sprite_count += 1;
+        }
+    }
+
+    /// Fetches the patterns of the sprites found by evaluation, a slot every
+    /// eight dots from dot 257: two unused nametable and attribute reads,
+    /// then the pattern's low and high bytes. All eight slots are fetched,
+    /// with unused ones reading tile $FF, as the hardware does.
+    fn fetch_sprite(&mut self, cartridge: &mut Cartridge) {
+        let slot = (self.dot - 257) as usize / 8;
+
+        match (self.dot - 257) % 8 {
+            0 => {
+                let addr = 0x2000 | (self.v & 0x0FFF);
+                self.read(cartridge, addr);
+            }
+            2 => {
+                let v = self.v;
+                let addr = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                self.read(cartridge, addr);
+            }
+            4 => {
+                let addr = self.sprite_address(slot);
+                self.sprite_lo = self.read(cartridge, addr);
+            }
+            6 => {
+                let addr = self.sprite_address(slot) + 8;
+                let mut hi = self.read(cartridge, addr);
+                let mut lo = self.sprite_lo;
+
+                if slot < self.sprite_count {
+                    if self.sprite_attributes[slot] & 0x40 != 0 {
+                        lo = lo.reverse_bits();
+                        hi = hi.reverse_bits();
+                    }
+                    self.sprite_patterns[slot] = [lo, hi];
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The address of the low pattern byte of the row of the sprite in
+    /// `slot` on the next line.
+    fn sprite_address(&self, slot: usize) -> u16 {
+        let height = self.sprite_height();
+        let (tile, mut row, attributes) = if slot < self.sprite_count {
+            let [tile, row] = self.sprite_patterns[slot];
+            (tile, row as u16, self.sprite_attributes[slot])
+        } else {
+            (0xFF, 0, 0)
+        };
+
+        if attributes & 0x80 != 0 {
+            row = height - 1 - row;
+        }
+
+        if height == 16 {
+            let table = (tile as u16 & 0x01) * 0x1000;
+            let tile = (tile & 0xFE) as u16 + (row >> 3);
+            table + tile * 16 + (row & 0x07)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0x0000
+            };
+            table + tile as u16 * 16 + row
+        }
+    }
+
+    fn render_pixel(&mut self) {
+        let x = self.dot as usize - 1;
+
+        let mut background = 0;
+        let mut background_palette = 0;
+        if self.mask & MASK_BACKGROUND != 0 && (x >= 8 || self.mask & MASK_BACKGROUND_LEFT != 0) {
+            let bit = 0x8000 >> self.x;
+            background =
+                ((self.pattern_hi & bit != 0) as u8) << 1 | (self.pattern_lo & bit != 0) as u8;
+            background_palette =
+                ((self.attribute_hi & bit != 0) as u8) << 1 | (self.attribute_lo & bit != 0) as u8;
+        }
+
+        let mut sprite = 0;
+        let mut sprite_palette = 0;
+        let mut sprite_behind = false;
+        if self.mask & MASK_SPRITES != 0 && (x >= 8 || self.mask & MASK_SPRITES_LEFT != 0) {
+            for slot in 0..self.sprite_count {
+                let offset = x.wrapping_sub(self.sprite_x[slot] as usize);
+                if offset >= 8 {
+                    continue;
+                }
+
+                let [lo, hi] = self.sprite_patterns[slot];
+                let pixel = ((hi >> (7 - offset)) & 0x01) << 1 | ((lo >> (7 - offset)) & 0x01);
+                if pixel == 0 {
+                    continue;
+                }
+
+                if slot == 0 && self.sprite_zero && background != 0 && x != 255 {
+                    self.status |= STATUS_SPRITE_ZERO;
+                }
+
+                sprite = pixel;
+                sprite_palette = (self.sprite_attributes[slot] & 0x03) + 4;
+                sprite_behind = self.sprite_attributes[slot] & 0x20 != 0;
+                break;
+            }
+        }
+
+        let index = match (background, sprite) {
+            (0, 0) => 0,
+            (0, _) => sprite_palette << 2 | sprite,
+            (_, 0) => background_palette << 2 | background,
+            _ if sprite_behind => background_palette << 2 | background,
+            _ => sprite_palette << 2 | sprite,
+        };
+
+        let color = self.palette[index as usize];
+        self.put_pixel(color);
+    }
+
+    fn put_pixel(&mut self, color: u8) {
+        let color = if self.mask & MASK_GRAYSCALE != 0 {
+            color & 0x30
+        } else {
+            color
+        };
+
+        // The PAL PPU swaps the red and green emphasis bits.
+        let mut emphasis = self.mask as u16 >> 5;
+        if self.region == Region::Pal {
+            emphasis = (emphasis & 0x04) | (emphasis & 0x01) << 1 | (emphasis & 0x02) >> 1;
+        }
+
+        let x = self.dot as usize - 1;
+        let y = self.scanline as usize;
+        self.frame[y * WIDTH + x] = color as u16 | emphasis << 6;
+    }
+}
+
+/// $3F10, $3F14, $3F18 and $3F1C mirror the backdrop entries below them.
+fn palette_index(addr: u16) -> usize {
+    let index = addr as usize & 0x1F;
+
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
+}
+
+impl Savestate for Ppu {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.ctrl);
+        state.u8(self.mask);
+        state.u8(self.status);
+        state.u8(self.oam_addr);
+
+        state.u16(self.v);
+        state.u16(self.t);
+        state.u8(self.x);
+        state.bool(self.w);
+        state.u8(self.read_buffer);
+        state.u8(self.io_latch);
+
+        state.bytes(&self.ciram);
+        state.bytes(&self.palette);
+        state.bytes(&self.oam);
+
+        state.u16(self.scanline);
+        state.u16(self.dot);
+        state.bool(self.odd_frame);
+        state.u8(self.line_phase);
+        state.u8(self.frame_phase);
+
+        state.u8(self.tile_id);
+        state.u8(self.tile_attribute);
+        state.u8(self.tile_lo);
+        state.u8(self.tile_hi);
+        state.u16(self.pattern_lo);
+        state.u16(self.pattern_hi);
+        state.u16(self.attribute_lo);
+        state.u16(self.attribute_hi);
+
+        state.u8(self.sprite_count as u8);
+        state.bytes(self.sprite_patterns.as_flattened());
+        state.bytes(&self.sprite_attributes);
+        state.bytes(&self.sprite_x);
+        state.bool(self.sprite_zero);
+        state.u8(self.sprite_lo);
+
+        state.bool(self.nmi_pending);
+        state.u64(self.frame_count);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        self.ctrl = state.u8()?;
+        self.mask = state.u8()?;
+        self.status = state.u8()?;
+        self.oam_addr = state.u8()?;
+
+        self.v = state.u16()?;
+        self.t = state.u16()?;
+        self.x = state.u8()?;
+        self.w = state.bool()?;
+        self.read_buffer = state.u8()?;
+        self.io_latch = state.u8()?;
+
+        state.bytes_into(&mut self.ciram)?;
+        state.bytes_into(&mut self.palette)?;
+        state.bytes_into(&mut self.oam)?;
+
+        self.scanline = state.u16()?;
+        self.dot = state.u16()?;
+        self.odd_frame = state.bool()?;
+        self.line_phase = state.u8()? % 3;
+        self.frame_phase = state.u8()? % 3;
+
+        self.tile_id = state.u8()?;
+        self.tile_attribute = state.u8()?;
+        self.tile_lo = state.u8()?;
+        self.tile_hi = state.u8()?;
+        self.pattern_lo = state.u16()?;
+        self.pattern_hi = state.u16()?;
+        self.attribute_lo = state.u16()?;
+        self.attribute_hi = state.u16()?;
+
+        self.sprite_count = (state.u8()? as usize).min(8);
+        state.bytes_into(self.sprite_patterns.as_flattened_mut())?;
+        state.bytes_into(&mut self.sprite_attributes)?;
+        state.bytes_into(&mut self.sprite_x)?;
+        self.sprite_zero = state.bool()?;
+        self.sprite_lo = state.u8()?;
+
+        self.nmi_pending = state.bool()?;
+        self.frame_count = state.u64()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{Header, Mirroring};
+    use crate::mapper::Mapper;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Logs every PPU read, nametables included.
+    struct Logger(Rc<RefCell<Vec<u16>>>);
+
+    impl Mapper for Logger {
+        fn cpu_read(&mut self, _addr: u16) -> Option<u8> {
+            None
+        }
+
+        fn cpu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn ppu_read(&mut self, addr: u16) -> u8 {
+            self.0.borrow_mut().push(addr);
+            0
+        }
+
+        fn ppu_write(&mut self, _addr: u16, _data: u8) {}
+
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Vertical
+        }
+
+        fn ciram_address(&self, _addr: u16) -> Option<u16> {
+            None
+        }
+    }
+
+    impl Savestate for Logger {
+        fn save_state(&self, _state: &mut StateWriter) {}
+
+        fn load_state(&mut self, _state: &mut StateReader) -> Result<(), StateError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sprite_fetches() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut cartridge = Cartridge {
+            header: Header::default(),
+            mapper: Box::new(Logger(log.clone())),
+            corrected: Vec::new(),
+        };
+        let mut ppu = Ppu::default();
+        ppu.write_register(&mut cartridge, 0x2000, CTRL_SPRITE_TABLE);
+        ppu.write_register(&mut cartridge, 0x2001, MASK_BACKGROUND | MASK_SPRITES);
+        // Tile $42, its row 3 fetched on scanline 4.
+        ppu.oam.fill(0xFF);
+        ppu.oam[..4].copy_from_slice(&[1, 0x42, 0x00, 0x10]);
+
+        while (ppu.scanline, ppu.dot) != (4, 257) {
+            ppu.clock(&mut cartridge);
+        }
+
+        let mut fetches = Vec::new();
+        for dot in 257..=320 {
+            log.borrow_mut().clear();
+            ppu.clock(&mut cartridge);
+            fetches.extend(log.borrow().iter().map(|&addr| (dot, addr)));
+        }
+
+        // Two unused nametable and attribute fetches, then the pattern, for
+        // each of the eight slots.
+        assert_eq!(fetches.len(), 32);
+        for (slot, fetch) in fetches.chunks(4).enumerate() {
+            let dot = 257 + slot as u16 * 8;
+            assert_eq!(fetch[0].0, dot);
+            assert_eq!(fetch[0].1 & 0xF000, 0x2000);
+            assert_eq!(fetch[1].0, dot + 2);
+            assert_eq!(fetch[1].1 & 0xFFC0, 0x23C0);
+
+            let pattern = if slot == 0 { 0x1423 } else { 0x1FF0 };
+            assert_eq!(fetch[2], (dot + 4, pattern));
+            assert_eq!(fetch[3], (dot + 6, pattern + 8));
+        }
+    }
+}
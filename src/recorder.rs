@@ -0,0 +1,261 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use super::nes::Nes;
+use super::ppu::{HEIGHT, WIDTH};
+use super::wav::{self, WavWriter};
+
+/// Writes 8 bit RGB frames as an uncompressed YUV4MPEG2 stream, which most
+/// video tools read directly. Frames are converted to full resolution 4:4:4
+/// BT.601 so no color detail is lost.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Starts a stream of `width` x `height` frames at `frame_rate`, with
+    /// pixels `pixel_aspect` wide, both given as fractions, e.g. those of
+    /// [`Region::frame_rate_fraction`] and [`Region::pixel_aspect`].
+    ///
+    /// [`Region::frame_rate_fraction`]: crate::region::Region::frame_rate_fraction
+    /// [`Region::pixel_aspect`]: crate::region::Region::pixel_aspect
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        frame_rate: (u64, u64),
+        pixel_aspect: (u64, u64),
+    ) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A{}:{} C444",
+            width, height, frame_rate.0, frame_rate.1, pixel_aspect.0, pixel_aspect.1
+        )?;
+
+        Ok(Self {
+            writer,
+            width,
+            height,
+            planes: vec![0; width * height * 3],
+        })
+    }
+
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let size = self.width * self.height;
+        assert_eq!(rgb.len(), size * 3);
+
+        for (index, pixel) in rgb.chunks(3).enumerate() {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|value| value as i32);
+
+            self.planes[index] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            self.planes[size + index] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            self.planes[size * 2 + index] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+struct Streams {
+    video: Option<Y4mWriter<Box<dyn Write>>>,
+    audio: Option<WavWriter<Box<dyn WriteSeek>>>,
+    /// The first error hit while recording, reported by `finish`.
+    error: Option<io::Error>,
+}
+
+/// Output the WAV header can be rewritten in once the length is known.
+pub trait WriteSeek: Write + Seek {}
+
+impl<T: Write + Seek> WriteSeek for T {}
+
+/// Records every frame a console produces as Y4M video, at the exact frame
+/// rate of its region, and its audio as WAV at the console's sample rate.
+///
+/// The recorder hooks into the frame and audio callbacks of the console,
+/// so it picks up output however the console is run. Since the callbacks
+/// can't return errors, the first one is kept and returned by
+/// [`Recorder::finish`].
+pub struct Recorder {
+    streams: Rc<RefCell<Streams>>,
+}
+
+impl Recorder {
+    /// Starts recording `nes` into the given streams, either of which may be
+    /// left out.
+    pub fn attach(
+        nes: &mut Nes,
+        video: Option<Box<dyn Write>>,
+        audio: Option<Box<dyn WriteSeek>>,
+    ) -> io::Result<Self> {
+        let video = match video {
+            Some(writer) => Some(Y4mWriter::new(
+                writer,
+                WIDTH,
+                HEIGHT,
+                nes.region().frame_rate_fraction(),
+                nes.region().pixel_aspect(),
+            )?),
+            None => None,
+        };
+        let audio = match audio {
+            Some(writer) => Some(WavWriter::new(writer, nes.sample_rate())?),
+            None => None,
+        };
+
+        let streams = Rc::new(RefCell::new(Streams {
+            video,
+            audio,
+            error: None,
+        }));
+
+        let frame_streams = Rc::clone(&streams);
+        nes.on_frame(move |nes| {
+            let streams = &mut *frame_streams.borrow_mut();
+            if let Some(video) = &mut streams.video {
+                if let Err(error) = video.write_frame(&nes.frame_rgb()) {
+                    streams.error.get_or_insert(error);
+                }
+            }
+        });
+
+        let audio_streams = Rc::clone(&streams);
+        nes.on_audio(move |samples| {
+            let streams = &mut *audio_streams.borrow_mut();
+            if let Some(audio) = &mut streams.audio {
+                let samples: Vec<i16> = samples.iter().map(|&level| wav::pcm(level)).collect();
+                if let Err(error) = audio.write_samples(&samples) {
+                    streams.error.get_or_insert(error);
+                }
+            }
+        });
+
+        Ok(Self { streams })
+    }
+
+    /// Starts recording `nes` into new files at the given paths.
+    pub fn create<P: AsRef<Path>>(
+        nes: &mut Nes,
+        video: Option<P>,
+        audio: Option<P>,
+    ) -> io::Result<Self> {
+        let video = match video {
+            Some(path) => Some(Box::new(BufWriter::new(File::create(path)?)) as Box<dyn Write>),
+            None => None,
+        };
+        let audio = match audio {
+            Some(path) => Some(Box::new(BufWriter::new(File::create(path)?)) as Box<dyn WriteSeek>),
+            None => None,
+        };
+
+        Self::attach(nes, video, audio)
+    }
+
+    /// Stops recording and completes the files. The console's callbacks stay
+    /// in place but do nothing from here on.
+    pub fn finish(self) -> io::Result<()> {
+        let mut streams = self.streams.borrow_mut();
+
+        if let Some(video) = streams.video.take() {
+            video.finish()?;
+        }
+        if let Some(audio) = streams.audio.take() {
+            audio.finish()?;
+        }
+
+        match streams.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::region::Region;
+    use crate::test_support;
+
+    /// Hands out what was written once the recorder is done with it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Cursor<Vec<u8>>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(data)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Shared {
+        fn seek(&mut self, position: io::SeekFrom) -> io::Result<u64> {
+            self.0.borrow_mut().seek(position)
+        }
+    }
+
+    #[test]
+    fn records_every_frame() {
+        let rom = test_support::nrom(&[], &[], &[]);
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+        nes.set_sample_rate(48000);
+
+        let video = Shared::default();
+        let audio = Shared::default();
+        let recorder = Recorder::attach(
+            &mut nes,
+            Some(Box::new(video.clone())),
+            Some(Box::new(audio.clone())),
+        )
+        .unwrap();
+
+        for _ in 0..60 {
+            nes.run_frame();
+        }
+        recorder.finish().unwrap();
+
+        let video = video.0.borrow().get_ref().clone();
+        let header = b"YUV4MPEG2 W256 H240 F39375000:655171 Ip A8:7 C444\n";
+        assert!(video.starts_with(header));
+        assert_eq!(video.len(), header.len() + 60 * (6 + 256 * 240 * 3));
+
+        // A second's worth of audio, give or take the first frame's start.
+        let audio = audio.0.borrow().get_ref().clone();
+        let data_size = u32::from_le_bytes(audio[40..44].try_into().unwrap()) as usize;
+        assert_eq!(audio.len(), 44 + data_size);
+        assert!((data_size / 2).abs_diff(48000) < 48000 / 60);
+    }
+
+    #[test]
+    fn pal_pixel_aspect() {
+        let region = Region::Pal;
+        let writer = Y4mWriter::new(
+            Vec::new(),
+            2,
+            1,
+            region.frame_rate_fraction(),
+            region.pixel_aspect(),
+        )
+        .unwrap();
+
+        let header = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert!(header.contains(" A2950000:2128137 "));
+    }
+}
@@ -0,0 +1,136 @@
+use super::savestate::{Savestate, StateError, StateReader, StateWriter};
+
+/// The console variants, which differ in clock rates and video timing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    /// The Dendy and other famiclones pair a PAL clock with NTSC-like
+    /// rendering, and delay vertical blank to fit the longer frame.
+    Dendy,
+}
+
+impl Region {
+    /// Reads the CPU/PPU timing field of an NES 2.0 header. Multi-region
+    /// games run as NTSC.
+    pub fn from_timing(timing: u8) -> Self {
+        match timing & 0x03 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+
+    /// The data lines the controller port at `$4016 + port` drives. The rest
+    /// keep what was last on the bus. The Dendy follows the Famicom, whose
+    /// first port only has the controller, the microphone and an expansion
+    /// line.
+    pub fn controller_lines(self, port: usize) -> u8 {
+        match (self, port) {
+            (Region::Dendy, 0) => 0x07,
+            _ => 0x1F,
+        }
+    }
+
+    pub fn master_clock(self) -> f64 {
+        match self {
+            Region::Ntsc => 236.25e6 / 11.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    /// Master clock cycles per CPU cycle.
+    pub fn cpu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Master clock cycles per PPU dot.
+    pub fn ppu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    pub fn cpu_clock(self) -> f64 {
+        self.master_clock() / self.cpu_divider() as f64
+    }
+
+    /// Scanlines per frame, including the pre-render line.
+    pub fn scanlines(self) -> u16 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    /// The scanline on which vertical blank starts.
+    pub fn vblank_line(self) -> u16 {
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    /// Frames per second. NTSC frames average half a dot less, as every
+    /// other frame skips one while rendering.
+    pub fn frame_rate(self) -> f64 {
+        let (numerator, denominator) = self.frame_rate_fraction();
+
+        numerator as f64 / denominator as f64
+    }
+
+    /// The width of a pixel relative to its height on a television, as a
+    /// fraction: 8:7 on NTSC, and wider on PAL, where the dot clock is
+    /// slower against the same picture width.
+    pub fn pixel_aspect(self) -> (u64, u64) {
+        match self {
+            Region::Ntsc => (8, 7),
+            Region::Pal | Region::Dendy => (2_950_000, 2_128_137),
+        }
+    }
+
+    /// [`Region::frame_rate`] as an exact fraction in lowest terms.
+    pub fn frame_rate_fraction(self) -> (u64, u64) {
+        let (clock, clock_divider) = match self {
+            Region::Ntsc => (236_250_000, 11),
+            Region::Pal | Region::Dendy => (26_601_712, 1),
+        };
+        let half_dots = 2 * 341 * self.scanlines() as u64 - (self == Region::Ntsc) as u64;
+
+        let numerator = clock * 2;
+        let denominator = clock_divider * self.ppu_divider() as u64 * half_dots;
+        let gcd = gcd(numerator, denominator);
+
+        (numerator / gcd, denominator / gcd)
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Savestate for Region {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError> {
+        *self = match state.u8()? {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            _ => Region::Dendy,
+        };
+
+        Ok(())
+    }
+}
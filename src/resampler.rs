@@ -0,0 +1,349 @@
+use std::f64::consts::PI;
+
+/// Taps of the band-limited step kernel, and the fractional sample positions
+/// it is tabulated for.
+const KERNEL_WIDTH: usize = 16;
+const PHASES: usize = 64;
+
+/// Cutoff of the kernel relative to the Nyquist frequency, leaving a little
+/// room for the transition band.
+const CUTOFF: f64 = 0.9;
+
+/// Input clocks between which completed samples are handed out.
+const FRAME_CLOCKS: u32 = 4096;
+
+/// Band-limited step synthesis, in the manner of Blargg's blip buffer.
+///
+/// The input is a waveform made of steps: it is described by the times,
+/// in input clocks, at which its level changes and by how much. Each step is
+/// drawn into the output as a band-limited step, which removes everything
+/// above the output's Nyquist frequency and so keeps the square waves of the
+/// APU from aliasing. Steps are added as impulses and summed up on output.
+pub struct BlipBuffer {
+    /// Output samples per input clock.
+    factor: f64,
+    /// Where clock 0 of the current frame lands in `buffer`, in samples.
+    offset: f64,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    buffer: Vec<f32>,
+    integrator: f32,
+}
+
+impl BlipBuffer {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        let mut kernel = vec![[0.0; KERNEL_WIDTH]; PHASES];
+
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let center = (KERNEL_WIDTH / 2 - 1) as f64 + phase as f64 / PHASES as f64;
+
+            for (tap, value) in taps.iter_mut().enumerate() {
+                let t = tap as f64 - center;
+                let sinc = if t == 0.0 {
+                    1.0
+                } else {
+                    (PI * CUTOFF * t).sin() / (PI * CUTOFF * t)
+                };
+                // Blackman window over the kernel.
+                let x = (t + KERNEL_WIDTH as f64 / 2.0) / KERNEL_WIDTH as f64;
+                let window = 0.42 - 0.5 * (2.0 * PI * x).cos() + 0.08 * (4.0 * PI * x).cos();
+
+                *value = (sinc * window.max(0.0)) as f32;
+            }
+
+            // Every step must add up to exactly its delta.
+            let sum: f32 = taps.iter().sum();
+            for value in taps.iter_mut() {
+                *value /= sum;
+            }
+        }
+
+        Self {
+            factor: sample_rate / clock_rate,
+            offset: 0.0,
+            kernel,
+            buffer: vec![0.0; KERNEL_WIDTH],
+            integrator: 0.0,
+        }
+    }
+
+    /// Changes the rates from the next step on.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: f64) {
+        self.factor = sample_rate / clock_rate;
+    }
+
+    /// Changes the level by `delta` at `time` clocks into the current frame.
+    pub fn add_delta(&mut self, time: u32, delta: f32) {
+        let position = self.offset + time as f64 * self.factor;
+        let index = position as usize;
+        let phase = ((position - index as f64) * PHASES as f64) as usize;
+
+        let end = index + KERNEL_WIDTH;
+        if self.buffer.len() < end {
+            self.buffer.resize(end, 0.0);
+        }
+
+        for (sample, tap) in self.buffer[index..end].iter_mut().zip(self.kernel[phase]) {
+            *sample += delta * tap;
+        }
+    }
+
+    /// Ends the current frame after `clocks` clocks. Samples before the end
+    /// of the frame can no longer change and become available.
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset += clocks as f64 * self.factor;
+
+        let needed = self.offset as usize + KERNEL_WIDTH;
+        if self.buffer.len() < needed {
+            self.buffer.resize(needed, 0.0);
+        }
+    }
+
+    pub fn samples_available(&self) -> usize {
+        self.offset as usize
+    }
+
+    /// Moves the available samples to the end of `output`.
+    pub fn read_samples(&mut self, output: &mut Vec<f32>) {
+        let count = self.samples_available();
+
+        for &delta in &self.buffer[..count] {
+            self.integrator += delta;
+            output.push(self.integrator);
+        }
+
+        self.buffer.drain(..count);
+        self.offset -= count as f64;
+    }
+}
+
+/// First-order high-pass filter.
+#[derive(Debug, Clone, Copy)]
+struct HighPass {
+    alpha: f32,
+    input: f32,
+    output: f32,
+}
+
+impl HighPass {
+    fn new(cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        Self {
+            alpha: (rc / (rc + dt)) as f32,
+            input: 0.0,
+            output: 0.0,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        self.output = self.alpha * (self.output + input - self.input);
+        self.input = input;
+        self.output
+    }
+}
+
+/// First-order low-pass filter.
+#[derive(Debug, Clone, Copy)]
+struct LowPass {
+    alpha: f32,
+    output: f32,
+}
+
+impl LowPass {
+    fn new(cutoff: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+
+        Self {
+            alpha: (dt / (rc + dt)) as f32,
+            output: 0.0,
+        }
+    }
+
+    fn filter(&mut self, input: f32) -> f32 {
+        self.output += self.alpha * (input - self.output);
+        self.output
+    }
+}
+
+/// Turns an output level sampled every CPU cycle into audio at the host's
+/// sample rate.
+///
+/// Level changes go through a [`BlipBuffer`], and the result through the
+/// filters of the console's audio output: high-pass filters at 90 Hz and
+/// 440 Hz, which remove the DC offset, and a low-pass filter at 14 kHz.
+///
+/// For playing along with a host whose audio clock drifts from the video
+/// clock, [`Resampler::set_rate_adjustment`] slightly speeds up or slows
+/// down the output, for example by the ratio [`rate_adjustment`] derives
+/// from how full the host's buffer is.
+pub struct Resampler {
+    blip: BlipBuffer,
+    high_pass: [HighPass; 2],
+    low_pass: LowPass,
+
+    clock_rate: f64,
+    sample_rate: f64,
+    adjustment: f64,
+
+    level: f32,
+    time: u32,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f64;
+
+        Self {
+            blip: BlipBuffer::new(clock_rate, sample_rate),
+            high_pass: [
+                HighPass::new(90.0, sample_rate),
+                HighPass::new(440.0, sample_rate),
+            ],
+            low_pass: LowPass::new(14_000.0, sample_rate),
+
+            clock_rate,
+            sample_rate,
+            adjustment: 1.0,
+
+            level: 0.0,
+            time: 0,
+        }
+    }
+
+    pub fn set_clock_rate(&mut self, clock_rate: f64) {
+        self.clock_rate = clock_rate;
+        self.update_rates();
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate as u32
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        *self = Self {
+            adjustment: self.adjustment,
+            ..Self::new(self.clock_rate, sample_rate)
+        };
+        self.update_rates();
+    }
+
+    /// Produces `ratio` times as many samples as the sample rate asks for.
+    /// Adjustments of a fraction of a percent go unheard.
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.adjustment = ratio;
+        self.update_rates();
+    }
+
+    fn update_rates(&mut self) {
+        self.blip
+            .set_rates(self.clock_rate, self.sample_rate * self.adjustment);
+    }
+
+    /// Advances by one input clock at which the output is at `level`.
+    /// Returns true when new samples are ready to [`Resampler::read`].
+    pub fn clock(&mut self, level: f32) -> bool {
+        if level != self.level {
+            self.blip.add_delta(self.time, level - self.level);
+            self.level = level;
+        }
+
+        self.time += 1;
+        if self.time == FRAME_CLOCKS {
+            self.blip.end_frame(self.time);
+            self.time = 0;
+            return true;
+        }
+
+        false
+    }
+
+    /// Moves the finished samples to the end of `output`.
+    pub fn read(&mut self, output: &mut Vec<f32>) {
+        if self.time > 0 {
+            self.blip.end_frame(self.time);
+            self.time = 0;
+        }
+
+        let start = output.len();
+        self.blip.read_samples(output);
+
+        for sample in &mut output[start..] {
+            let mut value = *sample;
+            for filter in &mut self.high_pass {
+                value = filter.filter(value);
+            }
+            *sample = self.low_pass.filter(value);
+        }
+    }
+}
+
+/// A rate adjustment for [`Resampler::set_rate_adjustment`] that keeps a
+/// host's audio buffer half full: the output speeds up by up to
+/// `max_deviation` as the buffer runs empty, and slows down as it fills.
+/// `fill` is how full the buffer is, from 0.0 to 1.0.
+pub fn rate_adjustment(fill: f64, max_deviation: f64) -> f64 {
+    1.0 + max_deviation * (1.0 - 2.0 * fill.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_settle_on_their_level() {
+        let mut blip = BlipBuffer::new(1_000_000.0, 44100.0);
+        blip.add_delta(100, 0.5);
+        blip.add_delta(3000, -0.25);
+        blip.end_frame(10_000);
+
+        let mut samples = Vec::new();
+        blip.read_samples(&mut samples);
+
+        assert_eq!(samples.len(), 441);
+        assert!((samples[100] - 0.5).abs() < 1e-4);
+        assert!((samples[440] - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn no_aliasing() {
+        // A square wave well above the output's Nyquist frequency averages
+        // out instead of folding down into audible tones.
+        let clock_rate = 1_789_773.0;
+        let mut resampler = Resampler::new(clock_rate, 44100);
+        let mut samples = Vec::new();
+
+        for clock in 0..clock_rate as u32 / 4 {
+            let level = if clock / 20 % 2 == 0 { 0.5 } else { 0.0 };
+            resampler.clock(level);
+        }
+        resampler.read(&mut samples);
+
+        let tail = &samples[samples.len() / 2..];
+        let peak = tail
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        assert!(peak < 0.01, "peak {}", peak);
+    }
+
+    #[test]
+    fn rates() {
+        let mut resampler = Resampler::new(1_789_773.0, 48000);
+        let mut samples = Vec::new();
+
+        for _ in 0..1_789_773 {
+            resampler.clock(0.0);
+        }
+        resampler.read(&mut samples);
+        assert!(samples.len().abs_diff(48000) <= 1);
+
+        samples.clear();
+        resampler.set_rate_adjustment(rate_adjustment(0.0, 0.005));
+        for _ in 0..1_789_773 {
+            resampler.clock(0.0);
+        }
+        resampler.read(&mut samples);
+        assert!(samples.len().abs_diff(48240) <= 1);
+    }
+}
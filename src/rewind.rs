@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+
+use super::controller::Input;
+use super::movie::{POWER_CYCLE, SOFT_RESET};
+use super::nes::Nes;
+use super::savestate::{Savestate, StateReader, StateWriter};
+
+/// A snapshot of the console and the input of the frames run from it.
+struct Snapshot {
+    /// Frames recorded before the snapshot was taken.
+    frame: u64,
+    /// The snapshot as a delta against the next newer one. Empty for the
+    /// newest, which is kept whole.
+    delta: Vec<u8>,
+    inputs: Vec<Frame>,
+}
+
+/// What was done to the console before a frame: [`SOFT_RESET`] and
+/// [`POWER_CYCLE`], then the input given to each controller.
+#[derive(Clone, Copy)]
+struct Frame {
+    commands: u8,
+    controllers: [Input; 4],
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        self.delta.len() + self.inputs.len() * size_of::<Frame>()
+    }
+}
+
+/// Steps the console back in time, one frame at a time.
+///
+/// Every `interval` frames a save state is taken. Only the newest is kept
+/// whole: each older one is stored as the XOR of it and the next newer one,
+/// with the runs of zeros that leaves where little changed squeezed out.
+/// Once the snapshots take more memory than allowed, the oldest go.
+///
+/// Going back restores the snapshot before the frame to return to and
+/// replays the input recorded since, the last frame included, so that the
+/// picture is drawn again. Resets go through [`Rewind::reset`] and
+/// [`Rewind::power_cycle`] so that they are replayed too. Frame callbacks
+/// see the replayed frames, while their audio is dropped from
+/// [`Nes::audio`].
+pub struct Rewind {
+    interval: usize,
+    capacity: usize,
+
+    snapshots: VecDeque<Snapshot>,
+    /// The newest snapshot, whole.
+    newest: Vec<u8>,
+    /// Memory taken by `newest` and the snapshots.
+    size: usize,
+    writer: StateWriter,
+    commands: u8,
+}
+
+impl Rewind {
+    /// Takes a snapshot every `interval` frames, keeping up to `megabytes`
+    /// of them.
+    pub fn new(interval: usize, megabytes: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity: megabytes * 1024 * 1024,
+
+            snapshots: VecDeque::new(),
+            newest: Vec::new(),
+            size: 0,
+            writer: StateWriter::default(),
+            commands: 0,
+        }
+    }
+
+    pub fn reset(&mut self, nes: &mut Nes) {
+        nes.reset();
+        self.commands |= SOFT_RESET;
+    }
+
+    pub fn power_cycle(&mut self, nes: &mut Nes) {
+        nes.power_cycle();
+        self.commands |= POWER_CYCLE;
+    }
+
+    /// Records the frame about to run. Call it before every frame, once the
+    /// controllers are set.
+    pub fn record(&mut self, nes: &Nes) {
+        let due = self
+            .snapshots
+            .back()
+            .is_none_or(|snapshot| snapshot.inputs.len() >= self.interval);
+        let mut commands = std::mem::take(&mut self.commands);
+        if due {
+            self.snapshot(nes);
+            // The snapshot is taken after the commands, so replaying from it
+            // must not carry them out again.
+            commands = 0;
+        }
+
+        let controllers = &nes.bus.controllers;
+        let newest = self.snapshots.back_mut().unwrap();
+        newest.inputs.push(Frame {
+            commands,
+            controllers: controllers.each_ref().map(|controller| controller.input()),
+        });
+        self.size += size_of::<Frame>();
+    }
+
+    fn snapshot(&mut self, nes: &Nes) {
+        self.writer.clear();
+        nes.save_state(&mut self.writer);
+        let state = self.writer.data();
+
+        let frame = match self.snapshots.back_mut() {
+            Some(previous) => {
+                previous.delta = encode_delta(&self.newest, state);
+                self.size += previous.delta.len();
+                previous.frame + previous.inputs.len() as u64
+            }
+            None => 0,
+        };
+
+        self.size = self.size - self.newest.len() + state.len();
+        self.newest.clear();
+        self.newest.extend_from_slice(state);
+        self.snapshots.push_back(Snapshot {
+            frame,
+            delta: Vec::new(),
+            inputs: Vec::new(),
+        });
+
+        while self.size > self.capacity && self.snapshots.len() > 1 {
+            let oldest = self.snapshots.pop_front().unwrap();
+            self.size -= oldest.size();
+        }
+    }
+
+    /// Frames recorded since the first snapshot still kept.
+    pub fn frames(&self) -> u64 {
+        match (self.snapshots.front(), self.snapshots.back()) {
+            (Some(oldest), Some(newest)) => {
+                newest.frame + newest.inputs.len() as u64 - oldest.frame
+            }
+            _ => 0,
+        }
+    }
+
+    /// The memory the snapshots take, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.newest.clear();
+        self.size = 0;
+        self.commands = 0;
+    }
+
+    /// Takes `nes` back to where it was one frame earlier. Returns false,
+    /// leaving it alone, when no snapshot goes back that far.
+    ///
+    /// `nes` must be the console the snapshots were taken of.
+    pub fn step_back(&mut self, nes: &mut Nes) -> bool {
+        let Some(newest) = self.snapshots.back() else {
+            return false;
+        };
+        let target = (newest.frame + newest.inputs.len() as u64).saturating_sub(1);
+
+        // The frame returned to is run again, from a snapshot before it.
+        if self.snapshots[0].frame >= target {
+            return false;
+        }
+        while self.snapshots.back().unwrap().frame >= target {
+            self.drop_newest();
+        }
+
+        let newest = self.snapshots.back_mut().unwrap();
+        let frames = (target - newest.frame) as usize;
+        self.size -= (newest.inputs.len() - frames) * size_of::<Frame>();
+        newest.inputs.truncate(frames);
+
+        nes.load_state(&mut StateReader::new(&self.newest))
+            .expect("snapshots are taken of this console");
+
+        let audio = nes.audio.len();
+        for input in &newest.inputs {
+            if input.commands & POWER_CYCLE != 0 {
+                nes.power_cycle();
+            } else if input.commands & SOFT_RESET != 0 {
+                nes.reset();
+            }
+            for (controller, &input) in nes.bus.controllers.iter_mut().zip(&input.controllers) {
+                controller.set_input(input);
+            }
+            nes.run_frame();
+        }
+        nes.audio.truncate(audio);
+
+        true
+    }
+
+    /// Drops the newest snapshot, restoring the one before it whole.
+    fn drop_newest(&mut self) {
+        let dropped = self.snapshots.pop_back().unwrap();
+        let previous = self.snapshots.back_mut().unwrap();
+
+        let state = decode_delta(&self.newest, &previous.delta);
+        self.size =
+            self.size - dropped.size() - self.newest.len() - previous.delta.len() + state.len();
+        self.newest = state;
+        previous.delta = Vec::new();
+    }
+}
+
+/// Encodes `older` as the XOR of it and `newer`: its length, then runs of
+/// zeros, each followed by bytes that differ.
+fn encode_delta(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let mut delta = Vec::new();
+    write_number(&mut delta, older.len());
+
+    let xor = |index: usize| older[index] ^ newer.get(index).copied().unwrap_or(0);
+
+    let mut index = 0;
+    while index < older.len() {
+        let zeros = (index..older.len())
+            .take_while(|&index| xor(index) == 0)
+            .count();
+        index += zeros;
+
+        // Differing bytes run until a few zeros in a row, which are cheaper
+        // as a run of their own.
+        let start = index;
+        while index < older.len() && (index..older.len().min(index + 4)).any(|i| xor(i) != 0) {
+            index += 1;
+        }
+
+        write_number(&mut delta, zeros);
+        write_number(&mut delta, index - start);
+        delta.extend((start..index).map(xor));
+    }
+
+    delta
+}
+
+fn decode_delta(newer: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_number(delta, &mut position);
+    let mut older = newer.to_vec();
+    older.resize(length, 0);
+
+    let mut index = 0;
+    while index < length {
+        index += read_number(delta, &mut position);
+        let count = read_number(delta, &mut position);
+
+        for (byte, xor) in older[index..index + count]
+            .iter_mut()
+            .zip(&delta[position..position + count])
+        {
+            *byte ^= xor;
+        }
+        index += count;
+        position += count;
+    }
+
+    older
+}
+
+/// Writes a number seven bits at a time, least significant first, with the
+/// top bit set on all bytes but the last.
+fn write_number(data: &mut Vec<u8>, mut number: usize) {
+    while number >= 0x80 {
+        data.push(number as u8 | 0x80);
+        number >>= 7;
+    }
+    data.push(number as u8);
+}
+
+fn read_number(data: &[u8], position: &mut usize) -> usize {
+    let mut number = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        number |= (byte as usize & 0x7F) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return number;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::controller::Device;
+    use crate::test_support;
+
+    /// An NROM image that keeps summing what it reads from the first
+    /// controller at $02.
+    fn rom() -> Vec<u8> {
+        #[rustfmt::skip]
+        let program = [
+            0xA9, 0x01, 0x8D, 0x16, 0x40, // Strobe the controllers
+            0xA9, 0x00, 0x8D, 0x16, 0x40,
+            0xAD, 0x16, 0x40,             // LDA $4016
+            0x65, 0x02,                   // ADC $02
+            0x85, 0x02,                   // STA $02
+            0x4C, 0x00, 0x80,             // JMP $8000
+        ];
+
+        test_support::nrom(&program, &[], &[])
+    }
+
+    fn state(nes: &Nes) -> Vec<u8> {
+        let mut state = StateWriter::default();
+        nes.save_state(&mut state);
+        state.into_inner()
+    }
+
+    #[test]
+    fn deltas() {
+        let newer = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let older = [1, 2, 9, 4, 5, 6, 7, 8, 9, 10, 12, 12, 0, 7];
+
+        let delta = encode_delta(&newer, &older);
+        assert_eq!(decode_delta(&newer, &delta), older);
+        assert_eq!(decode_delta(&older, &encode_delta(&older, &newer)), newer);
+
+        let state = [0x5A; 1000];
+        assert_eq!(encode_delta(&state, &state).len(), 5);
+    }
+
+    #[test]
+    fn steps_back_frame_by_frame() {
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom()).unwrap());
+        let mut rewind = Rewind::new(4, 1);
+        let mut states = Vec::new();
+
+        for frame in 0..30u8 {
+            nes.bus.controllers[0].buttons = frame.wrapping_mul(37);
+            rewind.record(&nes);
+            nes.run_frame();
+            states.push(state(&nes));
+        }
+        assert_eq!(rewind.frames(), 30);
+        assert!(rewind.size() < states[0].len() * 2);
+
+        for frame in (0..29).rev() {
+            assert!(rewind.step_back(&mut nes));
+            assert!(state(&nes) == states[frame], "frame {}", frame);
+        }
+        assert!(!rewind.step_back(&mut nes));
+
+        // Recording goes on from where the console was taken back to.
+        rewind.record(&nes);
+        nes.run_frame();
+        assert!(rewind.step_back(&mut nes));
+        assert!(state(&nes) == states[0]);
+    }
+
+    #[test]
+    fn size() {
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom()).unwrap());
+        let mut rewind = Rewind::new(4, 1);
+
+        for _ in 0..10 {
+            rewind.record(&nes);
+            nes.run_frame();
+        }
+        let expected = |rewind: &Rewind| {
+            rewind.newest.len() + rewind.snapshots.iter().map(Snapshot::size).sum::<usize>()
+        };
+        assert_eq!(rewind.size(), expected(&rewind));
+
+        // Going back drops the newest snapshot and the last frame's input.
+        for _ in 0..3 {
+            assert!(rewind.step_back(&mut nes));
+            assert_eq!(rewind.size(), expected(&rewind));
+        }
+
+        rewind.clear();
+        assert_eq!(rewind.size(), 0);
+    }
+
+    #[test]
+    fn memory_cap() {
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom()).unwrap());
+        let mut rewind = Rewind::new(4, 0);
+
+        for _ in 0..30 {
+            rewind.record(&nes);
+            nes.run_frame();
+        }
+
+        // Only the newest snapshot is left.
+        assert_eq!(rewind.frames(), 2);
+        assert!(rewind.step_back(&mut nes));
+        assert!(!rewind.step_back(&mut nes));
+    }
+
+    #[test]
+    fn replays_every_kind_of_input() {
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom()).unwrap());
+        nes.bus.controllers[0].device = Device::Vaus;
+        let mut rewind = Rewind::new(4, 1);
+        let mut states = Vec::new();
+
+        for frame in 0..10u8 {
+            let controller = &mut nes.bus.controllers[0];
+            controller.paddle = frame.wrapping_mul(29);
+            controller.trigger = frame % 3 == 0;
+            rewind.record(&nes);
+            nes.run_frame();
+            states.push(state(&nes));
+        }
+
+        for frame in (0..9).rev() {
+            assert!(rewind.step_back(&mut nes));
+            assert!(state(&nes) == states[frame], "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn replays_resets() {
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom()).unwrap());
+        let mut rewind = Rewind::new(4, 1);
+        let mut states = Vec::new();
+
+        for frame in 0..12u8 {
+            match frame {
+                2 | 4 => rewind.reset(&mut nes),
+                6 => rewind.power_cycle(&mut nes),
+                _ => {}
+            }
+            nes.bus.controllers[0].buttons = frame.wrapping_mul(37);
+            rewind.record(&nes);
+            nes.run_frame();
+            states.push(state(&nes));
+        }
+
+        for frame in (0..11).rev() {
+            assert!(rewind.step_back(&mut nes));
+            assert!(state(&nes) == states[frame], "frame {}", frame);
+        }
+    }
+}
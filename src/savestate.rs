@@ -0,0 +1,130 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The snapshot ended before all fields were read.
+    UnexpectedEnd,
+    /// A buffer in the snapshot differs in length from the one being restored.
+    SizeMismatch,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnexpectedEnd => write!(f, "save state ended unexpectedly"),
+            StateError::SizeMismatch => write!(f, "save state does not match this machine"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Components that can be captured into and restored from a save state.
+///
+/// Fields are written in a fixed order without tags, so `load_state` must read
+/// them back in exactly the order `save_state` wrote them.
+pub trait Savestate {
+    fn save_state(&self, state: &mut StateWriter);
+    fn load_state(&mut self, state: &mut StateReader) -> Result<(), StateError>;
+}
+
+/// Appends little-endian fields to a byte buffer.
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    /// Empties the writer while keeping its allocation around for reuse.
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+}
+
+/// Reads fields back in the order a [`StateWriter`] produced them.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < n {
+            return Err(StateError::UnexpectedEnd);
+        }
+
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// Restores a buffer written with [`StateWriter::bytes`] into `target`,
+    /// which must have the same length.
+    pub fn bytes_into(&mut self, target: &mut [u8]) -> Result<(), StateError> {
+        let len = self.u32()? as usize;
+        if len != target.len() {
+            return Err(StateError::SizeMismatch);
+        }
+
+        target.copy_from_slice(self.take(len)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use super::nes::Nes;
+
+/// Frames to wait before pressing reset when a test asks for it, which the
+/// protocol requires to be at least 100 ms.
+const RESET_DELAY: u64 = 8;
+
+/// The outcome of a test ROM that reports through the status protocol used
+/// by blargg's tests: $6001-$6003 hold $DE $B0 $61 once the protocol is in
+/// use, $6000 holds the status and $6004 a zero terminated message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestResult {
+    Passed,
+    /// The result code the test failed with.
+    Failed(u8),
+    /// The test did not finish in time.
+    Timeout,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub result: TestResult,
+    pub frames: u64,
+    pub text: String,
+}
+
+enum Status {
+    Running,
+    NeedsReset,
+    Done(u8),
+}
+
+fn status(nes: &mut Nes) -> Option<Status> {
+    let signature = [0x6001u16, 0x6002, 0x6003].map(|addr| nes.bus.read(addr));
+    if signature != [0xDE, 0xB0, 0x61] {
+        return None;
+    }
+
+    Some(match nes.bus.read(0x6000u16) {
+        0x80 => Status::Running,
+        0x81 => Status::NeedsReset,
+        code => Status::Done(code),
+    })
+}
+
+/// The message the test has written so far.
+pub fn text(nes: &mut Nes) -> String {
+    let mut text = Vec::new();
+
+    for addr in 0x6004..0x8000u16 {
+        match nes.bus.read(addr) {
+            0 => break,
+            byte => text.push(byte),
+        }
+    }
+
+    String::from_utf8_lossy(&text).into_owned()
+}
+
+/// Watches a running test ROM frame by frame, pressing reset whenever the
+/// test asks for it.
+#[derive(Default)]
+pub struct Monitor {
+    reset_at: Option<u64>,
+}
+
+impl Monitor {
+    /// Checks the status after `frame` has been run, returning the result
+    /// once the test has finished.
+    pub fn poll(&mut self, nes: &mut Nes, frame: u64) -> Option<TestResult> {
+        match status(nes)? {
+            Status::Done(0) => Some(TestResult::Passed),
+            Status::Done(code) => Some(TestResult::Failed(code)),
+            Status::NeedsReset => {
+                let at = *self.reset_at.get_or_insert(frame + RESET_DELAY);
+                if frame >= at {
+                    nes.reset();
+                    self.reset_at = None;
+                }
+                None
+            }
+            Status::Running => None,
+        }
+    }
+}
+
+/// Runs a test ROM for up to `max_frames` frames.
+pub fn run(nes: &mut Nes, max_frames: u64) -> Report {
+    let mut monitor = Monitor::default();
+
+    for frame in 1..=max_frames {
+        nes.run_frame();
+        // Sound isn't needed, so don't let it pile up.
+        nes.audio.clear();
+
+        if let Some(result) = monitor.poll(nes, frame) {
+            return Report {
+                result,
+                frames: frame,
+                text: text(nes),
+            };
+        }
+    }
+
+    Report {
+        result: TestResult::Timeout,
+        frames: max_frames,
+        text: text(nes),
+    }
+}
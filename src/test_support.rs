@@ -0,0 +1,24 @@
+//! Helpers shared by the unit tests and, through a `#[path]` module, the
+//! integration tests.
+
+/// An NROM image with 32 KiB of PRG ROM filled with NOPs, holding `program`
+/// at $8000 and `data` at $8100, and with `chr` at the start of its 8 KiB of
+/// CHR ROM. The reset and IRQ vectors point at $8000 and the NMI vector at
+/// $8100, so `data` can be an NMI handler.
+pub fn nrom(program: &[u8], data: &[u8], chr: &[u8]) -> Vec<u8> {
+    let mut rom = b"NES\x1A\x02\x01\x00\x00".to_vec();
+    rom.resize(16, 0);
+
+    let mut prg = vec![0xEA; 32 * 1024];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x100..0x100 + data.len()].copy_from_slice(data);
+    prg[0x7FFA..].copy_from_slice(&[0x00, 0x81, 0x00, 0x80, 0x00, 0x80]);
+
+    let mut chr_rom = vec![0; 8 * 1024];
+    chr_rom[..chr.len()].copy_from_slice(chr);
+
+    rom.extend(prg);
+    rom.extend(chr_rom);
+
+    rom
+}
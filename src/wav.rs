@@ -0,0 +1,99 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Converts an audio level in -1.0..=1.0 to a 16 bit sample.
+pub fn pcm(level: f32) -> i16 {
+    (level.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// Writes 16 bit mono PCM samples as a RIFF WAVE file.
+pub fn write<W: Write>(writer: &mut W, sample_rate: u32, samples: &[i16]) -> io::Result<()> {
+    write_header(writer, sample_rate, samples.len() as u32 * 2)?;
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn write_header<W: Write>(writer: &mut W, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // channels
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // bytes per frame
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())
+}
+
+/// Streams 16 bit mono PCM samples into a RIFF WAVE file whose length isn't
+/// known up front. The sizes in the header are filled in by
+/// [`WavWriter::finish`].
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        write_header(&mut writer, sample_rate, 0)?;
+
+        Ok(Self {
+            writer,
+            sample_rate,
+            data_size: 0,
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        self.writer.write_all(&bytes)?;
+        self.data_size += bytes.len() as u32;
+
+        Ok(())
+    }
+
+    /// Completes the header and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.writer, self.sample_rate, self.data_size)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn streamed() {
+        let samples = [0, 1, -1, i16::MAX, i16::MIN];
+
+        let mut whole = Vec::new();
+        write(&mut whole, 22050, &samples).unwrap();
+
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 22050).unwrap();
+        writer.write_samples(&samples[..2]).unwrap();
+        writer.write_samples(&samples[2..]).unwrap();
+
+        assert_eq!(writer.finish().unwrap().into_inner(), whole);
+        assert_eq!(pcm(2.0), i16::MAX);
+        assert_eq!(pcm(0.0), 0);
+    }
+}
@@ -0,0 +1,439 @@
+use std::fmt;
+
+use super::checksum::crc32;
+use super::png::{DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ZipError {
+    /// The archive or a deflate stream in it is cut short or corrupt.
+    Malformed,
+    /// A file is compressed with a method other than store or deflate.
+    UnsupportedMethod(u16),
+    /// A file doesn't match the CRC-32 the archive gives for it.
+    ChecksumMismatch,
+    /// The archive holds no file by this name.
+    NotFound(String),
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZipError::Malformed => write!(f, "zip archive is malformed"),
+            ZipError::UnsupportedMethod(method) => {
+                write!(f, "zip compression method {} is not supported", method)
+            }
+            ZipError::ChecksumMismatch => write!(f, "zip archive is corrupt"),
+            ZipError::NotFound(name) => write!(f, "zip archive has no {}", name),
+        }
+    }
+}
+
+impl std::error::Error for ZipError {}
+
+/// A file listed in the central directory.
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed_size: usize,
+    size: usize,
+    offset: usize,
+}
+
+/// Reads files out of a zip archive held in memory.
+///
+/// Only what movie containers need is supported: stored and deflated files,
+/// without encryption or the 64 bit extensions.
+pub struct Archive<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, ZipError> {
+        // The end of central directory record is followed by a comment of up
+        // to 64 KiB, so it is searched for from the end.
+        let end = (0..=data.len().saturating_sub(22))
+            .rev()
+            .take(0x10000 + 1)
+            .find(|&at| data[at..].starts_with(b"PK\x05\x06"))
+            .ok_or(ZipError::Malformed)?;
+
+        let count = u16_at(data, end + 10)? as usize;
+        let mut at = u32_at(data, end + 16)? as usize;
+        let mut entries = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            if !data[at.min(data.len())..].starts_with(b"PK\x01\x02") {
+                return Err(ZipError::Malformed);
+            }
+
+            let name_length = u16_at(data, at + 28)? as usize;
+            let extra_length = u16_at(data, at + 30)? as usize;
+            let comment_length = u16_at(data, at + 32)? as usize;
+            let name = data
+                .get(at + 46..at + 46 + name_length)
+                .ok_or(ZipError::Malformed)?;
+
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: u16_at(data, at + 10)?,
+                crc: u32_at(data, at + 16)?,
+                compressed_size: u32_at(data, at + 20)? as usize,
+                size: u32_at(data, at + 24)? as usize,
+                offset: u32_at(data, at + 42)? as usize,
+            });
+
+            at += 46 + name_length + extra_length + comment_length;
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    /// The names of the files in the archive, directories included.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.name.as_str())
+    }
+
+    /// Extracts the file called `name`.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, ZipError> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| entry.name == name)
+            .ok_or_else(|| ZipError::NotFound(name.to_string()))?;
+
+        let at = entry.offset;
+        if !self.data[at.min(self.data.len())..].starts_with(b"PK\x03\x04") {
+            return Err(ZipError::Malformed);
+        }
+        let start =
+            at + 30 + u16_at(self.data, at + 26)? as usize + u16_at(self.data, at + 28)? as usize;
+        let compressed = self
+            .data
+            .get(start..start + entry.compressed_size)
+            .ok_or(ZipError::Malformed)?;
+
+        let contents = match entry.method {
+            0 => compressed.to_vec(),
+            8 => inflate(compressed)?,
+            method => return Err(ZipError::UnsupportedMethod(method)),
+        };
+
+        if contents.len() != entry.size || crc32(&contents) != entry.crc {
+            return Err(ZipError::ChecksumMismatch);
+        }
+
+        Ok(contents)
+    }
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16, ZipError> {
+    data.get(at..at + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(ZipError::Malformed)
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, ZipError> {
+    data.get(at..at + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or(ZipError::Malformed)
+}
+
+// SECTION: Inflate
+
+/// The order in which a dynamic block lists the code lengths of its code
+/// length code.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reads bits least significant first, as deflate packs them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, ZipError> {
+        let mut value = 0;
+
+        for bit in 0..count {
+            let byte = *self
+                .data
+                .get(self.position / 8)
+                .ok_or(ZipError::Malformed)?;
+            value |= ((byte >> (self.position % 8)) as u32 & 1) << bit;
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.position = self.position.next_multiple_of(8);
+    }
+}
+
+/// A canonical Huffman code, stored as the number of codes of each length
+/// and the symbols in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[symbol as usize] != 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[symbol as usize]);
+
+        Self { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, ZipError> {
+        // Codes of each length follow on from the last code of the length
+        // before, shifted left by one.
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as usize;
+            let count = count as usize;
+            if code - first < count {
+                return Ok(self.symbols[index + code - first]);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(ZipError::Malformed)
+    }
+}
+
+/// Decompresses a raw deflate stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, ZipError> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let start = reader.position / 8;
+                let header = data.get(start..start + 4).ok_or(ZipError::Malformed)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(ZipError::Malformed);
+                }
+
+                let stored = data
+                    .get(start + 4..start + 4 + length as usize)
+                    .ok_or(ZipError::Malformed)?;
+                output.extend_from_slice(stored);
+                reader.position += (4 + length as usize) * 8;
+            }
+            1 => {
+                let mut lengths = [0; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &literals, &distances, &mut output)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut output)?;
+            }
+            _ => return Err(ZipError::Malformed),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// Reads the literal and distance codes at the start of a dynamic block.
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), ZipError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let length_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..length_count] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (
+                *lengths.last().ok_or(ZipError::Malformed)?,
+                3 + reader.bits(2)?,
+            ),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(ZipError::Malformed);
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    output: &mut Vec<u8>,
+) -> Result<(), ZipError> {
+    loop {
+        match literals.decode(reader)? as usize {
+            symbol @ 0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            symbol => {
+                let code = symbol - 257;
+                let base = *LENGTH_BASE.get(code).ok_or(ZipError::Malformed)?;
+                let length = base as usize + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+
+                let code = distances.decode(reader)? as usize;
+                let base = *DISTANCE_BASE.get(code).ok_or(ZipError::Malformed)?;
+                let distance = base as usize + reader.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(ZipError::Malformed);
+                }
+
+                // The copy may overlap what it writes, repeating a short run.
+                for _ in 0..length {
+                    output.push(output[output.len() - distance]);
+                }
+            }
+        }
+    }
+}
+
+/// Stores `files` uncompressed in a zip archive, for building test data.
+#[cfg(test)]
+pub(crate) fn store(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+
+    for (name, contents) in files {
+        let mut header = Vec::new();
+        header.extend(20u16.to_le_bytes());
+        header.extend([0; 8]);
+        header.extend(crc32(contents).to_le_bytes());
+        header.extend((contents.len() as u32).to_le_bytes());
+        header.extend((contents.len() as u32).to_le_bytes());
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend([0; 2]);
+
+        directory.extend(b"PK\x01\x02");
+        directory.extend(20u16.to_le_bytes());
+        directory.extend(&header);
+        directory.extend([0; 10]);
+        directory.extend((archive.len() as u32).to_le_bytes());
+        directory.extend(name.as_bytes());
+
+        archive.extend(b"PK\x03\x04");
+        archive.extend(&header);
+        archive.extend(name.as_bytes());
+        archive.extend(*contents);
+    }
+
+    let offset = archive.len() as u32;
+    archive.extend(&directory);
+    archive.extend(b"PK\x05\x06");
+    archive.extend([0; 4]);
+    archive.extend((files.len() as u16).to_le_bytes());
+    archive.extend((files.len() as u16).to_le_bytes());
+    archive.extend((directory.len() as u32).to_le_bytes());
+    archive.extend(offset.to_le_bytes());
+    archive.extend([0; 2]);
+
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn squares() -> Vec<u8> {
+        (0..10u32)
+            .map(|n| format!("{} squared is {}\n", n, n * n))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn dynamic_block() {
+        // Written by zlib at level 9, which picks a dynamic Huffman code.
+        #[rustfmt::skip]
+        let stream = [
+            0x55, 0xCC, 0xB1, 0x0D, 0xC0, 0x20, 0x0C, 0x44, 0xD1, 0xFE, 0xA6, 0xF0, 0x08, 0x18, 0x8C, 0xC1,
+            0xE3, 0x20, 0x41, 0x41, 0x99, 0xA0, 0xEC, 0x9F, 0xF6, 0x28, 0x9F, 0xF4, 0xF5, 0x93, 0x9C, 0xE7,
+            0x1B, 0xEF, 0x9A, 0xB2, 0x8F, 0x24, 0x28, 0x53, 0x91, 0x99, 0x86, 0xC2, 0x0C, 0xD8, 0x15, 0x3B,
+            0x2A, 0x3B, 0x57, 0x38, 0xBB, 0x38, 0xDA, 0x75, 0x0B, 0x74, 0xB6, 0x1B, 0x82, 0xDD, 0x15, 0x3F,
+        ];
+
+        assert_eq!(inflate(&stream).unwrap(), squares());
+        assert_eq!(inflate(&stream[..40]), Err(ZipError::Malformed));
+    }
+
+    #[test]
+    fn stored_and_fixed_blocks() {
+        // A stored block of "ab" followed by the PNG encoder's fixed block.
+        let mut stream = vec![0x00, 0x02, 0x00, 0xFD, 0xFF, b'a', b'b'];
+        let png = crate::png::encode(1, 1, &[1, 2, 3]);
+        let length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        stream.extend(&png[41 + 2..41 + length - 4]);
+
+        assert_eq!(inflate(&stream).unwrap(), [b'a', b'b', 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn archive() {
+        let squares = squares();
+        let data = store(&[("a.txt", b"first"), ("dir/b.txt", &squares)]);
+        let archive = Archive::new(&data).unwrap();
+
+        assert_eq!(archive.names().collect::<Vec<_>>(), ["a.txt", "dir/b.txt"]);
+        assert_eq!(archive.read("a.txt").unwrap(), b"first");
+        assert_eq!(archive.read("dir/b.txt").unwrap(), squares);
+        assert_eq!(
+            archive.read("c.txt"),
+            Err(ZipError::NotFound("c.txt".to_string()))
+        );
+
+        let mut corrupt = data.clone();
+        corrupt[30 + 5] = b'F';
+        assert_eq!(
+            Archive::new(&corrupt).unwrap().read("a.txt"),
+            Err(ZipError::ChecksumMismatch)
+        );
+        assert_eq!(Archive::new(&data[..20]).err(), Some(ZipError::Malformed));
+    }
+}
@@ -0,0 +1,176 @@
+//! Runs blargg's accuracy test ROMs, which report their result through the
+//! status protocol at $6000.
+//!
+//! The ROMs are not part of the repository. Invoke `tests/fetch_roms.sh` from
+//! the `tests` directory to download them, or point `NES_TEST_ROMS` at a copy.
+//! Missing ROMs are skipped.
+
+mod common;
+
+use std::fs;
+
+use nes::cartridge::{Cartridge, CartridgeError};
+use nes::nes::Nes;
+use nes::test_rom::{self, TestResult};
+
+/// Frames a single ROM may take before it counts as hung. The slowest tests
+/// finish in well under half a minute of emulated time.
+const TIMEOUT: u64 = 30 * 60;
+
+/// Runs every ROM of a suite that is present and fails when any of them did
+/// not pass, after printing a line per ROM.
+fn run_suite(suite: &str, roms: &[&str]) {
+    let directory = common::rom_directory().join(suite);
+    let mut failures = Vec::new();
+
+    for rom in roms {
+        let path = directory.join(rom);
+        let name = format!("{}/{}", suite, rom);
+
+        let Ok(data) = fs::read(&path) else {
+            println!("SKIP    {} (not found)", name);
+            continue;
+        };
+
+        let cartridge = match Cartridge::from_bytes(&data) {
+            Ok(cartridge) => cartridge,
+            Err(CartridgeError::UnsupportedMapper(mapper)) => {
+                println!("SKIP    {} (mapper {} is not supported)", name, mapper);
+                continue;
+            }
+            Err(error) => panic!("{}: {}", name, error),
+        };
+
+        let mut nes = Nes::new(cartridge);
+        let report = test_rom::run(&mut nes, TIMEOUT);
+        let text = report.text.trim_end().replace('\n', "\n        ");
+
+        match report.result {
+            TestResult::Passed => println!("PASS    {} ({} frames)", name, report.frames),
+            TestResult::Failed(code) => {
+                println!("FAIL    {} (code {})\n        {}", name, code, text);
+                failures.push(name);
+            }
+            TestResult::Timeout => {
+                println!(
+                    "TIMEOUT {} after {} frames\n        {}",
+                    name, report.frames, text
+                );
+                failures.push(name);
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "failed: {}", failures.join(", "));
+}
+
+#[test]
+fn instr_test() {
+    run_suite(
+        "instr_test-v5/rom_singles",
+        &[
+            "01-basics.nes",
+            "02-implied.nes",
+            "03-immediate.nes",
+            "04-zero_page.nes",
+            "05-zp_xy.nes",
+            "06-absolute.nes",
+            "07-abs_xy.nes",
+            "08-ind_x.nes",
+            "09-ind_y.nes",
+            "10-branches.nes",
+            "11-stack.nes",
+            "12-jmp_jsr.nes",
+            "13-rts.nes",
+            "14-rti.nes",
+            "15-brk.nes",
+            "16-special.nes",
+        ],
+    );
+}
+
+#[test]
+fn instr_misc() {
+    run_suite(
+        "instr_misc/rom_singles",
+        &[
+            "01-abs_x_wrap.nes",
+            "02-branch_wrap.nes",
+            "03-dummy_reads.nes",
+            "04-dummy_reads_apu.nes",
+        ],
+    );
+}
+
+#[test]
+fn cpu_timing() {
+    run_suite(
+        "instr_timing/rom_singles",
+        &["1-instr_timing.nes", "2-branch_timing.nes"],
+    );
+}
+
+#[test]
+fn cpu_interrupts() {
+    run_suite(
+        "cpu_interrupts_v2/rom_singles",
+        &[
+            "1-cli_latency.nes",
+            "2-nmi_and_brk.nes",
+            "3-nmi_and_irq.nes",
+            "4-irq_and_dma.nes",
+            "5-branch_delays_irq.nes",
+        ],
+    );
+}
+
+#[test]
+fn ppu_vbl_nmi() {
+    run_suite(
+        "ppu_vbl_nmi/rom_singles",
+        &[
+            "01-vbl_basics.nes",
+            "02-vbl_set_time.nes",
+            "03-vbl_clear_time.nes",
+            "04-nmi_control.nes",
+            "05-nmi_timing.nes",
+            "06-suppression.nes",
+            "07-nmi_on_timing.nes",
+            "08-nmi_off_timing.nes",
+            "09-even_odd_frames.nes",
+            "10-even_odd_timing.nes",
+        ],
+    );
+}
+
+#[test]
+fn apu_test() {
+    run_suite(
+        "apu_test/rom_singles",
+        &[
+            "1-len_ctr.nes",
+            "2-len_table.nes",
+            "3-irq_flag.nes",
+            "4-jitter.nes",
+            "5-len_timing.nes",
+            "6-irq_flag_timing.nes",
+            "7-dmc_basics.nes",
+            "8-dmc_rates.nes",
+        ],
+    );
+}
+
+#[test]
+fn mmc3_test() {
+    run_suite(
+        "mmc3_test_2/rom_singles",
+        &[
+            "1-clocking.nes",
+            "2-details.nes",
+            "3-A12_clocking.nes",
+            "4-scanline_timing.nes",
+            "5-MMC3.nes",
+            "6-MMC3_alt.nes",
+        ],
+    );
+}
@@ -0,0 +1,40 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[allow(dead_code)]
+#[path = "../../src/test_support.rs"]
+pub mod test_support;
+
+/// Where the test ROMs fetched by `tests/fetch_roms.sh` live, unless
+/// `NES_TEST_ROMS` points somewhere else.
+#[allow(dead_code)]
+pub fn rom_directory() -> PathBuf {
+    env::var_os("NES_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"))
+}
+
+/// Compares `png` with the golden image at `path`, or writes it there when
+/// run with `UPDATE_GOLDEN=1`. On a mismatch the screenshot is written next
+/// to the golden image as `.actual.png`.
+#[allow(dead_code)]
+pub fn assert_golden(path: &Path, png: &[u8]) {
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, png).unwrap();
+        return;
+    }
+
+    let golden = fs::read(path)
+        .unwrap_or_else(|error| panic!("{}: {}, run with UPDATE_GOLDEN=1", path.display(), error));
+    if golden != png {
+        let actual = path.with_extension("actual.png");
+        fs::write(&actual, png).unwrap();
+        panic!(
+            "{} differs from the golden image, see {}",
+            path.display(),
+            actual.display()
+        );
+    }
+}
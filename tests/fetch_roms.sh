@@ -0,0 +1,11 @@
+git clone -n --depth=1 --filter=tree:0 \
+  https://github.com/christopherpow/nes-test-roms/
+cd nes-test-roms
+git sparse-checkout set --no-cone instr_test-v5 instr_timing instr_misc \
+  cpu_interrupts_v2 ppu_vbl_nmi apu_test mmc3_test_2 \
+  other/nestest.nes other/nestest.log
+git checkout
+cd ..
+mkdir -p roms
+mv nes-test-roms/* roms/.
+rm -rf nes-test-roms
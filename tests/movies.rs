@@ -0,0 +1,56 @@
+//! Replays recorded movies as regression tests.
+//!
+//! Movies live in the `movies` directory next to the test ROMs, see
+//! `tests/common`. Each `NAME.fm2`, `NAME.bk2` or `NAME.mmo` is played on
+//! `NAME.nes` from the same directory, and the last frame must match
+//! `NAME.png` there. Run with `UPDATE_GOLDEN=1` to write the PNGs. Movies
+//! without a ROM are skipped.
+
+mod common;
+
+use std::fs;
+
+use nes::cartridge::Cartridge;
+use nes::movie;
+use nes::nes::Nes;
+
+#[test]
+fn movies() {
+    let directory = common::rom_directory().join("movies");
+    let Ok(entries) = fs::read_dir(&directory) else {
+        println!("SKIP    {} (not found)", directory.display());
+        return;
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| {
+                ["fm2", "bk2", "mmo"].contains(&&*extension.to_string_lossy())
+            })
+        })
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let Ok(rom) = fs::read(path.with_extension("nes")) else {
+            println!("SKIP    {} (no ROM)", name);
+            continue;
+        };
+
+        let movie = movie::import(&fs::read(&path).unwrap())
+            .unwrap_or_else(|error| panic!("{}: {}", name, error));
+        movie
+            .verify(&rom)
+            .unwrap_or_else(|error| panic!("{}: {}", name, error));
+
+        let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+        let mut player = movie.play(&mut nes);
+        while player.run_frame(&mut nes) {}
+        let screenshot = nes.screenshot();
+
+        common::assert_golden(&path.with_extension("png"), &screenshot);
+        println!("PASS    {} ({} frames)", name, player.frame());
+    }
+}
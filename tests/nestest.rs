@@ -0,0 +1,83 @@
+//! Runs nestest in automation mode and compares every instruction against
+//! the reference trace in nestest.log. Both files are fetched into
+//! `tests/roms/other` by `tests/fetch_roms.sh`; the test is skipped without
+//! them.
+
+mod common;
+
+use std::fs;
+
+use nes::cartridge::Cartridge;
+use nes::nes::Nes;
+
+/// Lines of the log shown before the first divergence.
+const CONTEXT: usize = 8;
+
+/// Reduces a log line to the program counter, registers and cycle count,
+/// dropping the disassembly and the PPU position.
+fn normalize(line: &str) -> Option<String> {
+    let pc = line.get(0..4)?;
+    let registers = &line[line.find("A:")?..line.find(" PPU:")?];
+    let cycles = &line[line.find("CYC:")?..];
+
+    Some(format!("{} {} {}", pc, registers, cycles.trim_end()))
+}
+
+fn trace(nes: &Nes) -> String {
+    let cpu = &nes.cpu;
+
+    format!(
+        "{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        cpu.pc,
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.status,
+        cpu.sp,
+        // The reset sequence before the first instruction takes 7 cycles.
+        nes.bus.cycles + 7
+    )
+}
+
+#[test]
+fn nestest() {
+    let directory = common::rom_directory().join("other");
+    let (Ok(rom), Ok(log)) = (
+        fs::read(directory.join("nestest.nes")),
+        fs::read_to_string(directory.join("nestest.log")),
+    ) else {
+        println!("SKIP    nestest (not found)");
+        return;
+    };
+
+    let mut nes = Nes::new(Cartridge::from_bytes(&rom).unwrap());
+
+    // Automation mode starts at $C000 instead of the reset vector.
+    nes.cpu.pc = 0xC000;
+    nes.cpu.status = 0x24;
+
+    let lines: Vec<&str> = log.lines().filter(|line| !line.is_empty()).collect();
+
+    for (index, line) in lines.iter().enumerate() {
+        let expected = normalize(line).expect("malformed nestest.log");
+        let actual = trace(&nes);
+
+        if actual != expected {
+            let context = lines[index.saturating_sub(CONTEXT)..index].join("\n  ");
+            panic!(
+                "nestest diverged at line {}:\n  {}\n> {}\nexpected: {}\n  actual: {}",
+                index + 1,
+                context,
+                line,
+                expected,
+                actual
+            );
+        }
+
+        nes.step();
+    }
+
+    // Official and unofficial opcodes report their results in $02 and $03.
+    assert_eq!(nes.bus.read(0x0002u16), 0x00, "official opcode failure");
+    assert_eq!(nes.bus.read(0x0003u16), 0x00, "unofficial opcode failure");
+}
@@ -0,0 +1,79 @@
+//! Compares screenshots against the golden images in `tests/golden`.
+//!
+//! Run with `UPDATE_GOLDEN=1` to write the current screenshots as the new
+//! golden images after an intended change in output.
+
+mod common;
+
+use std::path::PathBuf;
+
+use nes::cartridge::Cartridge;
+use nes::nes::Nes;
+
+/// An NROM image that fills the first nametable with columns of four tiles:
+/// blank, solid, a checkerboard and a diagonal line.
+fn pattern_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let program = [
+        0x78,             // SEI
+        0xD8,             // CLD
+        0xA2, 0xFF,       // LDX #$FF
+        0x9A,             // TXS
+        0x2C, 0x02, 0x20, // BIT $2002
+        0x2C, 0x02, 0x20, // BIT $2002     ; wait for two vertical blanks
+        0x10, 0xFB,       // BPL -5
+        0x2C, 0x02, 0x20, // BIT $2002
+        0x10, 0xFB,       // BPL -5
+        0xA9, 0x3F,       // LDA #$3F      ; background palette
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA2, 0x00,       // LDX #$00
+        0xBD, 0x00, 0x81, // LDA $8100,X
+        0x8D, 0x07, 0x20, // STA $2007
+        0xE8,             // INX
+        0xE0, 0x04,       // CPX #$04
+        0xD0, 0xF5,       // BNE -11
+        0xA9, 0x20,       // LDA #$20      ; nametable and attributes
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA0, 0x00,       // LDY #$00
+        0xA2, 0x04,       // LDX #$04
+        0x98,             // TYA
+        0x29, 0x03,       // AND #$03
+        0x8D, 0x07, 0x20, // STA $2007
+        0xC8,             // INY
+        0xD0, 0xF7,       // BNE -9
+        0xCA,             // DEX
+        0xD0, 0xF4,       // BNE -12
+        0xA9, 0x00,       // LDA #$00
+        0x8D, 0x05, 0x20, // STA $2005
+        0x8D, 0x05, 0x20, // STA $2005
+        0x8D, 0x00, 0x20, // STA $2000
+        0xA9, 0x0A,       // LDA #$0A      ; show the background
+        0x8D, 0x01, 0x20, // STA $2001
+        0x4C, 0x53, 0x80, // JMP $8053
+    ];
+
+    let mut chr = [0; 0x40];
+    chr[0x10..0x18].fill(0xFF);
+    for row in 0..8 {
+        chr[0x28 + row] = if row % 2 == 0 { 0xAA } else { 0x55 };
+        chr[0x30 + row] = 0x80 >> row;
+        chr[0x38 + row] = 0x80 >> row;
+    }
+
+    common::test_support::nrom(&program, &[0x0F, 0x16, 0x2A, 0x12], &chr)
+}
+
+#[test]
+fn pattern() {
+    let mut nes = Nes::new(Cartridge::from_bytes(&pattern_rom()).unwrap());
+    for _ in 0..5 {
+        nes.run_frame();
+    }
+
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden/pattern.png");
+    common::assert_golden(&golden, &nes.screenshot());
+}